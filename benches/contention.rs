@@ -0,0 +1,74 @@
+//! Throughput of recording from many threads at once into one subscriber:
+//! counters sent from per-thread senders and, for comparison, from the one
+//! shared sender behind a lock, and spans entered and closed, which also
+//! go through the span registry.
+//!
+//! Run with `cargo bench --bench contention`. Events are written to
+//! `io::sink()`, so this measures the recording threads and the channel
+//! rather than serialization.
+
+use std::io;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tokio_trace_chrome_trace::{ChromeTraceBuilder, JsonSink};
+use tracing::Dispatch;
+
+const EVENTS_PER_THREAD: usize = 200_000;
+
+#[derive(Clone, Copy)]
+enum Work {
+    Counters,
+    Spans,
+}
+
+fn run(threads: usize, shared: bool, work: Work) -> Duration {
+    let subscriber = ChromeTraceBuilder::new()
+        .sink(JsonSink::from_writer(io::sink()))
+        .shared_sender(shared)
+        .build();
+    let subscriber = Arc::new(subscriber);
+    let barrier = Arc::new(Barrier::new(threads + 1));
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let subscriber = subscriber.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                let dispatch = Dispatch::new(subscriber.clone());
+                barrier.wait();
+                match work {
+                    Work::Counters => {
+                        for i in 0..EVENTS_PER_THREAD {
+                            subscriber.record_counter("bench", i as f64);
+                        }
+                    }
+                    Work::Spans => tracing::dispatcher::with_default(&dispatch, || {
+                        for i in 0..EVENTS_PER_THREAD {
+                            tracing::info_span!("bench", i).in_scope(|| ());
+                        }
+                    }),
+                }
+            })
+        })
+        .collect();
+    barrier.wait();
+    let start = Instant::now();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let elapsed = start.elapsed();
+    subscriber.finish();
+    elapsed
+}
+
+fn main() {
+    println!("threads   per-thread (events/s)   shared lock (events/s)   spans (spans/s)");
+    for &threads in &[1, 2, 4, 8, 16] {
+        let events = (threads * EVENTS_PER_THREAD) as f64;
+        let local = events / run(threads, false, Work::Counters).as_secs_f64();
+        let shared = events / run(threads, true, Work::Counters).as_secs_f64();
+        let spans = events / run(threads, false, Work::Spans).as_secs_f64();
+        println!("{:>7}   {:>21.0}   {:>22.0}   {:>15.0}", threads, local, shared, spans);
+    }
+}
@@ -0,0 +1,93 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::json::Value;
+use crate::TraceEvent;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static FREED: AtomicUsize = AtomicUsize::new(0);
+
+/// A global allocator that counts allocations and bytes before handing them
+/// to `A`, for `ChromeTraceBuilder::allocation_counters`:
+///
+/// ```ignore
+/// #[global_allocator]
+/// static ALLOC: CountingAlloc = CountingAlloc::new(System);
+/// ```
+pub struct CountingAlloc<A = System> {
+    inner: A,
+}
+
+impl<A> CountingAlloc<A> {
+    pub const fn new(inner: A) -> Self {
+        CountingAlloc { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAlloc<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        FREED.fetch_add(layout.size(), Ordering::Relaxed);
+        self.inner.dealloc(ptr, layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = self.inner.realloc(ptr, layout, new_size);
+        if !new.is_null() {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+            FREED.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        new
+    }
+}
+
+/// Turns the allocator's running totals into per-interval counters.
+#[derive(Default)]
+pub(crate) struct AllocSampler {
+    allocations: usize,
+    allocated: usize,
+}
+
+impl AllocSampler {
+    pub fn sample(&mut self, ts: u64) -> Vec<TraceEvent> {
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+        let allocated = ALLOCATED.load(Ordering::Relaxed);
+        let freed = FREED.load(Ordering::Relaxed);
+        let mut event = TraceEvent::new("C", "allocations".to_owned(), String::new(), ts);
+        event.args.insert(
+            "allocs".to_owned(),
+            Value::from(allocations.wrapping_sub(self.allocations)),
+        );
+        event.args.insert(
+            "bytes".to_owned(),
+            Value::from(allocated.wrapping_sub(self.allocated)),
+        );
+        let mut live = TraceEvent::new("C", "live heap".to_owned(), String::new(), ts);
+        live.args.insert(
+            "bytes".to_owned(),
+            Value::from(allocated.saturating_sub(freed)),
+        );
+        self.allocations = allocations;
+        self.allocated = allocated;
+        vec![event, live]
+    }
+}
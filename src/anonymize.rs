@@ -0,0 +1,81 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use crate::json::Value;
+use crate::{ClosedSpan, TraceEvent};
+
+/// Rewrites events so a trace can be shared outside the organization: pids
+/// and tids are renumbered in order of appearance, and the values of
+/// selected args (by default `host` and `hostname`) are replaced by a salted
+/// hash, so equal values still look equal. Names, timings and nesting are
+/// left alone.
+pub struct Anonymizer {
+    salt: String,
+    args: Vec<String>,
+    pids: HashMap<u32, u32>,
+    tids: HashMap<(u32, u64), u64>,
+}
+
+impl Default for Anonymizer {
+    fn default() -> Self {
+        Anonymizer {
+            salt: String::new(),
+            args: vec!["host".to_owned(), "hostname".to_owned()],
+            pids: HashMap::new(),
+            tids: HashMap::new(),
+        }
+    }
+}
+
+impl Anonymizer {
+    pub fn new() -> Self {
+        Anonymizer::default()
+    }
+
+    /// Also hash the values of args (and span fields) named `name`.
+    pub fn hash_arg(mut self, name: &str) -> Self {
+        self.args.push(name.to_owned());
+        self
+    }
+
+    /// Mixed into every hash, so that hashed values can't be recovered by
+    /// hashing guesses without knowing it.
+    pub fn salt(mut self, salt: &str) -> Self {
+        self.salt = salt.to_owned();
+        self
+    }
+
+    pub(crate) fn event(&mut self, mut event: TraceEvent) -> TraceEvent {
+        let next = self.pids.len() as u32 + 1;
+        let pid = *self.pids.entry(event.pid).or_insert(next);
+        let next = self.tids.len() as u64 + 1;
+        event.tid = *self.tids.entry((event.pid, event.tid)).or_insert(next);
+        event.pid = pid;
+        for (key, value) in event.args.iter_mut() {
+            if self.args.contains(key) {
+                *value = self.hash(value);
+            }
+        }
+        event
+    }
+
+    pub(crate) fn span(&self, mut span: ClosedSpan) -> ClosedSpan {
+        for (key, value) in span.fields.iter_mut() {
+            if self.args.contains(key) {
+                *value = self.hash(value);
+            }
+        }
+        span
+    }
+
+    fn hash(&self, value: &Value) -> Value {
+        let mut hasher = DefaultHasher::new();
+        self.salt.hash(&mut hasher);
+        match *value {
+            Value::String(ref s) => s.hash(&mut hasher),
+            ref other => other.to_string().hash(&mut hasher),
+        }
+        Value::from(format!("{:016x}", hasher.finish()))
+    }
+}
@@ -0,0 +1,190 @@
+use crate::json::{Map, Value};
+
+/// How event args are shaped, see `ChromeTraceBuilder::arg_layout`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ArgLayout {
+    /// As recorded: fields keep their names, dots and all.
+    #[default]
+    AsRecorded,
+    /// Nested objects become dot-separated keys, e.g. `{"http.status": 200}`.
+    Flatten,
+    /// Dot-separated field names become nested objects, e.g.
+    /// `{"http": {"status": 200}}`.
+    Nest,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Shape {
+    pub layout: ArgLayout,
+    /// Objects nested deeper than this are written as JSON strings.
+    pub max_depth: Option<usize>,
+    /// Strings longer than this many characters are cut short.
+    pub max_string: Option<usize>,
+    /// See `ChromeTraceBuilder::normalize_units`.
+    pub units: bool,
+}
+
+impl Shape {
+    pub fn is_noop(&self) -> bool {
+        self.layout == ArgLayout::AsRecorded
+            && self.max_depth.is_none()
+            && self.max_string.is_none()
+            && !self.units
+    }
+
+    pub fn apply(&self, args: Map<String, Value>) -> Map<String, Value> {
+        let args = if self.units {
+            args.into_iter().map(|(key, value)| normalize(key, value)).collect()
+        } else {
+            args
+        };
+        let args = match self.layout {
+            ArgLayout::AsRecorded => args,
+            ArgLayout::Flatten => {
+                let mut flat = Map::new();
+                flatten("", args, &mut flat);
+                flat
+            }
+            ArgLayout::Nest => nest(args),
+        };
+        let args = match self.max_depth {
+            Some(depth) => args
+                .into_iter()
+                .map(|(key, value)| (key, limit(value, depth)))
+                .collect(),
+            None => args,
+        };
+        match self.max_string {
+            Some(max) => args
+                .into_iter()
+                .map(|(key, value)| (key, shorten(value, max)))
+                .collect(),
+            None => args,
+        }
+    }
+}
+
+/// What to multiply and divide a time field by to get microseconds.
+type ToMicros = Option<(u64, u64)>;
+
+/// Time fields are converted to microseconds, the trace's own unit, and
+/// renamed to match; the rest keep their values. By suffix: the stem, the
+/// unit the field is in, and the conversion, if it's a time.
+const UNITS: &[(&str, &str, ToMicros)] = &[
+    ("_ns", "us", Some((1, 1000))),
+    ("_us", "us", Some((1, 1))),
+    ("_ms", "us", Some((1000, 1))),
+    ("bytes", "bytes", None),
+    ("count", "count", None),
+];
+
+/// The unit a field named `key` is in once normalized, and the name and
+/// conversion to normalize it with.
+fn unit_of(key: &str) -> Option<(&'static str, String, ToMicros)> {
+    UNITS.iter().find_map(|&(suffix, unit, factor)| {
+        let stem = key.strip_suffix(suffix)?;
+        let key = match factor {
+            Some(_) if !stem.is_empty() => format!("{}_us", stem),
+            Some(_) => return None,
+            None => key.to_owned(),
+        };
+        Some((unit, key, factor))
+    })
+}
+
+/// Renames a numeric time field to `*_us` and converts its value.
+fn normalize(key: String, value: Value) -> (String, Value) {
+    let (key, factor) = match (unit_of(&key), value.as_f64()) {
+        (Some((_, renamed, Some(factor))), Some(_)) => (renamed, factor),
+        _ => return (key, value),
+    };
+    let (mul, div) = factor;
+    let value = match value.as_u64() {
+        Some(n) if n % div == 0 => Value::from(n.saturating_mul(mul) / div),
+        _ => value.as_f64().map_or(value, |n| Value::from(n * mul as f64 / div as f64)),
+    };
+    (key, value)
+}
+
+/// Puts the unit every series of counter `name` shares, if they do and
+/// it's known, in the track name, e.g. `queue (bytes)`.
+pub(crate) fn label_counter(name: &mut String, args: &Map<String, Value>) {
+    let mut units = args.keys().map(|key| unit_of(key).map(|(unit, _, _)| unit));
+    let unit = match units.next() {
+        Some(Some(unit)) => unit,
+        _ => return,
+    };
+    if units.all(|other| other == Some(unit)) {
+        name.push_str(&format!(" ({})", unit));
+    }
+}
+
+/// Cuts every string in `value` down to `max` characters, marking the cut
+/// with `...`.
+fn shorten(value: Value, max: usize) -> Value {
+    match value {
+        Value::String(s) => match s.char_indices().nth(max) {
+            Some((end, _)) => Value::String(format!("{}...", &s[..end])),
+            None => Value::String(s),
+        },
+        Value::Array(values) => Value::Array(values.into_iter().map(|value| shorten(value, max)).collect()),
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| (key, shorten(value, max)))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+fn flatten(prefix: &str, args: Map<String, Value>, flat: &mut Map<String, Value>) {
+    for (key, value) in args {
+        let key = if prefix.is_empty() { key } else { format!("{}.{}", prefix, key) };
+        match value {
+            Value::Object(inner) if !inner.is_empty() => flatten(&key, inner, flat),
+            value => {
+                flat.insert(key, value);
+            }
+        }
+    }
+}
+
+fn nest(args: Map<String, Value>) -> Map<String, Value> {
+    let mut nested = Map::new();
+    for (key, value) in args {
+        let mut parts = key.split('.').filter(|part| !part.is_empty()).peekable();
+        let mut obj = &mut nested;
+        while let Some(part) = parts.next() {
+            if parts.peek().is_none() {
+                obj.insert(part.to_owned(), value);
+                break;
+            }
+            let entry = obj.entry(part.to_owned()).or_insert_with(|| Value::Object(Map::new()));
+            if !entry.is_object() {
+                // `a` and `a.b` were both recorded; keep `a`'s value under
+                // an empty key.
+                let leaf = entry.take();
+                let mut inner = Map::new();
+                inner.insert(String::new(), leaf);
+                *entry = Value::Object(inner);
+            }
+            obj = entry.as_object_mut().unwrap();
+        }
+    }
+    nested
+}
+
+/// Replaces anything nested more than `depth` levels below `value` with its
+/// JSON text.
+fn limit(value: Value, depth: usize) -> Value {
+    match value {
+        Value::Object(_) | Value::Array(_) if depth == 0 => Value::from(value.to_string()),
+        Value::Object(obj) => Value::Object(
+            obj.into_iter()
+                .map(|(key, value)| (key, limit(value, depth - 1)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(|item| limit(item, depth - 1)).collect()),
+        value => value,
+    }
+}
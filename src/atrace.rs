@@ -0,0 +1,48 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::process;
+
+const MARKERS: &[&str] = &[
+    "/sys/kernel/tracing/trace_marker",
+    "/sys/kernel/debug/tracing/trace_marker",
+];
+
+/// The kernel's ftrace marker file, which is where Android's ATrace begin
+/// and end sections are written. Sections are attributed to the writing
+/// thread, so this is written from `enter`/`exit` directly rather than from
+/// the writer thread.
+pub(crate) struct Marker {
+    file: File,
+    pid: u32,
+}
+
+impl Marker {
+    pub fn open() -> io::Result<Self> {
+        let mut last = io::Error::new(io::ErrorKind::NotFound, "no trace_marker");
+        for path in MARKERS {
+            match OpenOptions::new().write(true).open(path) {
+                Ok(file) => {
+                    return Ok(Marker {
+                        file,
+                        pid: process::id(),
+                    })
+                }
+                Err(e) => last = e,
+            }
+        }
+        Err(last)
+    }
+
+    pub fn begin(&self, name: &str) {
+        self.write(format!("B|{}|{}", self.pid, name));
+    }
+
+    pub fn end(&self) {
+        self.write(format!("E|{}", self.pid));
+    }
+
+    fn write(&self, marker: String) {
+        // Each write is one marker, so it has to go out in a single call.
+        drop((&self.file).write(marker.as_bytes()));
+    }
+}
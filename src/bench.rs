@@ -0,0 +1,107 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing_core::span::{Attributes, Id, Record};
+use tracing_core::{dispatcher, Dispatch, Event, Interest, Metadata};
+
+use crate::{ChromeTraceSubscriber, Error};
+
+/// Traces one iteration of each benchmark to its own file, so there's a
+/// timeline of exactly the work being measured. Wrap the routine passed to
+/// criterion's `Bencher::iter`:
+///
+/// ```ignore
+/// let mut trace = BenchTrace::new("target/chrome-trace");
+/// c.bench_function("parse", |b| b.iter(|| trace.iteration("parse", || parse(INPUT))));
+/// ```
+///
+/// The first call for each `id` runs under its own subscriber, writing
+/// `<dir>/<id>.json`, between global `iteration start` and `iteration end`
+/// marks. Later calls run the routine untraced, so the measurement isn't
+/// skewed by more than the one traced iteration.
+pub struct BenchTrace {
+    dir: PathBuf,
+    traced: HashSet<String>,
+}
+
+impl BenchTrace {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        BenchTrace {
+            dir: dir.into(),
+            traced: HashSet::new(),
+        }
+    }
+
+    /// Runs `f`, tracing it if this is the first iteration of `id`. If the
+    /// trace file can't be created the iteration runs untraced.
+    pub fn iteration<F: FnOnce() -> R, R>(&mut self, id: &str, f: F) -> R {
+        if !self.traced.insert(id.to_owned()) {
+            return f();
+        }
+        let file: String = id
+            .chars()
+            .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        let subscriber = match std::fs::create_dir_all(&self.dir)
+            .map_err(Error::from)
+            .and_then(|()| ChromeTraceSubscriber::from_path(self.dir.join(file + ".json")))
+        {
+            Ok(subscriber) => Arc::new(subscriber),
+            Err(_) => return f(),
+        };
+        let dispatch = Dispatch::new(Shared(subscriber.clone()));
+        let result = dispatcher::with_default(&dispatch, || {
+            subscriber.annotate("iteration start");
+            let result = f();
+            subscriber.annotate("iteration end");
+            result
+        });
+        subscriber.finish();
+        result
+    }
+}
+
+/// Lets `BenchTrace` keep using the subscriber it installed.
+struct Shared(Arc<ChromeTraceSubscriber>);
+
+impl tracing_core::Subscriber for Shared {
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.0.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn new_span(&self, attrs: &Attributes) -> Id {
+        self.0.new_span(attrs)
+    }
+
+    fn record(&self, span: &Id, values: &Record) {
+        self.0.record(span, values)
+    }
+
+    fn record_follows_from(&self, span: &Id, follows: &Id) {
+        self.0.record_follows_from(span, follows)
+    }
+
+    fn event(&self, event: &Event) {
+        self.0.event(event)
+    }
+
+    fn enter(&self, span: &Id) {
+        self.0.enter(span)
+    }
+
+    fn exit(&self, span: &Id) {
+        self.0.exit(span)
+    }
+
+    fn clone_span(&self, id: &Id) -> Id {
+        self.0.clone_span(id)
+    }
+
+    fn try_close(&self, id: Id) -> bool {
+        self.0.try_close(id)
+    }
+}
@@ -0,0 +1,27 @@
+//! Expands a trace written with `JsonSink::compact` into one viewers can
+//! load.
+//!
+//! Usage: chrome-trace-decode <compact trace> <output file>
+
+use std::env;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::process;
+
+use tokio_trace_chrome_trace::decode_compact;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: {} <compact trace> <output file>", args[0]);
+        process::exit(2);
+    }
+    let result = File::open(&args[1]).and_then(|input| {
+        let output = BufWriter::new(File::create(&args[2])?);
+        decode_compact(BufReader::new(input), output)
+    });
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,68 @@
+//! Compares the slice durations of two traces and reports the slices whose
+//! mean duration grew by more than a threshold, exiting with status 1 if
+//! any did, for use in a local regression check. Errors, like a trace that
+//! can't be read, exit with status 2.
+//!
+//! Usage: chrome-trace-diff <base trace> <new trace> [threshold percent]
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::process;
+
+use tokio_trace_chrome_trace::{compare_slices, summarize_slices, SliceStats};
+
+/// Growth in mean duration, in percent, reported as a regression unless
+/// another threshold is given.
+const DEFAULT_THRESHOLD: f64 = 10.0;
+
+fn load(path: &str) -> io::Result<BTreeMap<String, SliceStats>> {
+    summarize_slices(BufReader::new(File::open(path)?))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 && args.len() != 4 {
+        eprintln!("usage: {} <base trace> <new trace> [threshold percent]", args[0]);
+        process::exit(2);
+    }
+    let threshold = match args.get(3).map(|arg| arg.parse::<f64>()) {
+        Some(Ok(threshold)) => threshold,
+        Some(Err(_)) => {
+            eprintln!("error: threshold must be a number");
+            process::exit(2);
+        }
+        None => DEFAULT_THRESHOLD,
+    };
+    let (base, new) = match (load(&args[1]), load(&args[2])) {
+        (Ok(base), Ok(new)) => (base, new),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("error: {}", e);
+            process::exit(2);
+        }
+    };
+    let mut regressed = false;
+    println!("{:>9} {:>12} {:>12} {:>8}  name", "change", "base mean", "new mean", "count");
+    for change in compare_slices(&base, &new) {
+        let percent = (change.ratio() - 1.0) * 100.0;
+        let flag = if percent > threshold {
+            regressed = true;
+            "!"
+        } else {
+            " "
+        };
+        println!(
+            "{}{:>+7.1}% {:>10.0}us {:>10.0}us {:>8}  {}",
+            flag,
+            percent,
+            change.base.mean_us(),
+            change.new.mean_us(),
+            change.new.count,
+            change.name
+        );
+    }
+    if regressed {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,34 @@
+//! Listens for one `StreamSink` connection and writes the trace it sends to
+//! a file.
+//!
+//! Usage: chrome-trace-receive <listen address> <output file>
+
+use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+use std::net::TcpListener;
+use std::process;
+
+use tokio_trace_chrome_trace::reassemble_stream;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: {} <listen address> <output file>", args[0]);
+        process::exit(2);
+    }
+    let result = TcpListener::bind(&args[1])
+        .and_then(|listener| listener.accept())
+        .and_then(|(stream, peer)| {
+            eprintln!("receiving trace from {}", peer);
+            let output = BufWriter::new(File::create(&args[2])?);
+            reassemble_stream(stream, output)
+        });
+    match result {
+        Ok(events) => eprintln!("wrote {} events to {}", events, args[2]),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    }
+}
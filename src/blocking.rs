@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use crate::channel::Tx;
+use crate::clock::Instant;
+use crate::stats::Stats;
+use crate::writer::Route;
+use crate::{in_micros, Message, TraceEvent};
+
+struct Poll {
+    span: &'static str,
+    since: Instant,
+    reported: bool,
+}
+
+/// The outermost span each thread is currently inside, i.e. the poll it's
+/// running, keyed by thread id.
+pub(crate) struct Polls {
+    threshold: Duration,
+    polls: Mutex<HashMap<u64, Poll>>,
+}
+
+impl Polls {
+    pub fn new(threshold: Duration) -> Self {
+        Polls {
+            threshold,
+            polls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn enter(&self, tid: u64, span: &'static str) {
+        let poll = Poll {
+            span,
+            since: Instant::now(),
+            reported: false,
+        };
+        self.polls.lock().unwrap().insert(tid, poll);
+    }
+
+    /// Ends the thread's poll, returning the warning to emit if it ran too
+    /// long and the monitor hadn't caught it yet.
+    pub fn exit(&self, tid: u64, start: Instant) -> Option<TraceEvent> {
+        let poll = self.polls.lock().unwrap().remove(&tid)?;
+        let elapsed = poll.since.elapsed();
+        if poll.reported || elapsed < self.threshold {
+            return None;
+        }
+        Some(warning(tid, &poll, elapsed, start))
+    }
+
+    fn overdue(&self, start: Instant) -> Vec<TraceEvent> {
+        let mut polls = self.polls.lock().unwrap();
+        let mut warnings = Vec::new();
+        for (&tid, poll) in polls.iter_mut() {
+            let elapsed = poll.since.elapsed();
+            if !poll.reported && elapsed >= self.threshold {
+                poll.reported = true;
+                warnings.push(warning(tid, poll, elapsed, start));
+            }
+        }
+        warnings
+    }
+}
+
+fn warning(tid: u64, poll: &Poll, elapsed: Duration, start: Instant) -> TraceEvent {
+    let mut event = TraceEvent::new(
+        "I",
+        format!("blocking in {}", poll.span),
+        "blocking".to_owned(),
+        in_micros(start.elapsed()),
+    );
+    event.tid = tid;
+    event.scope = Some("g");
+    event.cname = Some("terrible".to_owned());
+    event.args.insert("span".to_owned(), poll.span.into());
+    event.args.insert("elapsed_us".to_owned(), in_micros(elapsed).into());
+    event
+}
+
+/// Checks for overdue polls until the subscriber owning `polls` goes away.
+pub(crate) fn spawn_monitor(
+    polls: Weak<Polls>,
+    tx: Tx,
+    stats: Arc<Stats>,
+    start: Instant,
+) {
+    thread::spawn(move || loop {
+        let interval = match polls.upgrade() {
+            Some(polls) => {
+                for warning in polls.overdue(start) {
+                    if !stats.send(&tx, Message::Event(warning, Route::Default)) {
+                        return;
+                    }
+                }
+                polls.threshold / 4
+            }
+            None => return,
+        };
+        thread::sleep(interval.max(Duration::from_millis(1)));
+    });
+}
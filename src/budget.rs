@@ -0,0 +1,79 @@
+use std::mem;
+
+use crate::span::SpanState;
+use crate::TraceEvent;
+
+/// What to give up as the subscriber nears its memory limit, see
+/// `ChromeTraceBuilder::memory_limit`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShedPolicy {
+    /// Drop new events outright.
+    DropEvents,
+    /// Strip the args from new events first, keeping the timeline, and only
+    /// drop them once the limit itself is reached.
+    DropArgs,
+}
+
+/// What to do with an event under a `Budget`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Shed {
+    Nothing,
+    Args,
+    Event,
+}
+
+/// Stripping args starts at this fraction of the limit.
+const SOFT_LIMIT: f64 = 0.9;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Budget {
+    pub limit: usize,
+    pub policy: ShedPolicy,
+}
+
+impl Budget {
+    pub fn shed(&self, used: usize) -> Shed {
+        if used >= self.limit {
+            Shed::Event
+        } else if used as f64 >= self.limit as f64 * SOFT_LIMIT {
+            match self.policy {
+                ShedPolicy::DropEvents => Shed::Event,
+                ShedPolicy::DropArgs => Shed::Args,
+            }
+        } else {
+            Shed::Nothing
+        }
+    }
+}
+
+/// A rough count of the heap and inline bytes an event takes up while it's
+/// queued.
+pub(crate) fn event_size(event: &TraceEvent) -> usize {
+    mem::size_of::<TraceEvent>()
+        + event.name.len()
+        + event.cat.len()
+        + event.target.len()
+        + event
+            .args
+            .iter()
+            .map(|(key, value)| key.len() + value_size(value))
+            .sum::<usize>()
+}
+
+/// A rough count of the bytes each open span takes up in the registry,
+/// leaving out its fields.
+pub(crate) fn span_size() -> usize {
+    // The state itself, plus the map entry and a short target.
+    mem::size_of::<SpanState>() + 64
+}
+
+fn value_size(value: &crate::json::Value) -> usize {
+    use crate::json::Value;
+    mem::size_of::<Value>()
+        + match *value {
+            Value::String(ref s) => s.len(),
+            Value::Array(ref items) => items.iter().map(value_size).sum(),
+            Value::Object(ref obj) => obj.iter().map(|(key, value)| key.len() + value_size(value)).sum(),
+            _ => 0,
+        }
+}
@@ -0,0 +1,116 @@
+use std::cell::RefCell;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
+#[cfg(target_arch = "wasm32")]
+use std::sync::Arc;
+use std::sync::Mutex;
+
+#[cfg(target_arch = "wasm32")]
+use crate::writer::Inline;
+use crate::Message;
+
+/// What happens when an event is recorded while the channel to the writer
+/// thread is full, see `ChromeTraceBuilder::channel_capacity`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChannelPolicy {
+    /// The recording thread waits for the writer thread to catch up.
+    #[default]
+    Block,
+    /// The new event is dropped and counted.
+    DropNewest,
+}
+
+thread_local! {
+    // This thread's own clone of the last subscriber's sender it used, and
+    // that subscriber's instance number.
+    static LOCAL: RefCell<Option<(usize, Tx)>> = const { RefCell::new(None) };
+}
+
+pub(crate) enum SendError {
+    /// The event was dropped under `ChannelPolicy::DropNewest`.
+    Full,
+    /// The writer thread has exited.
+    Closed,
+}
+
+/// The sending half of the channel to the writer thread, which is bounded
+/// if `ChromeTraceBuilder::channel_capacity` was set.
+#[derive(Clone)]
+pub(crate) enum Tx {
+    Unbounded(Sender<Message>),
+    Bounded(SyncSender<Message>, ChannelPolicy),
+    /// Straight to the writer, where there are no threads.
+    #[cfg(target_arch = "wasm32")]
+    Inline(Arc<Inline>),
+}
+
+impl Tx {
+    pub fn send(&self, msg: Message) -> Result<(), SendError> {
+        match *self {
+            Tx::Unbounded(ref tx) => tx.send(msg).map_err(|_| SendError::Closed),
+            Tx::Bounded(ref tx, ChannelPolicy::DropNewest) if is_event(&msg) => {
+                tx.try_send(msg).map_err(|e| match e {
+                    TrySendError::Full(_) => SendError::Full,
+                    TrySendError::Disconnected(_) => SendError::Closed,
+                })
+            }
+            // Anything else, like `Done`, has to get through.
+            Tx::Bounded(ref tx, _) => tx.send(msg).map_err(|_| SendError::Closed),
+            #[cfg(target_arch = "wasm32")]
+            Tx::Inline(ref writer) => writer.send(msg),
+        }
+    }
+}
+
+/// Calls `f` with a sender for subscriber `instance` that only this thread
+/// uses, so recording threads don't all queue up on `shared`'s lock. It's
+/// cloned from `shared` the first time, or when the thread switches
+/// subscribers.
+pub(crate) fn with_local<F, R>(instance: usize, shared: &Mutex<Tx>, f: F) -> R
+where
+    F: FnOnce(&Tx) -> R,
+{
+    let mut f = Some(f);
+    let local = LOCAL.try_with(|local| {
+        // Busy if `f` ends up back here, e.g. from the internal logger.
+        let mut local = local.try_borrow_mut().ok()?;
+        match *local {
+            Some((cached, _)) if cached == instance => {}
+            _ => *local = Some((instance, shared.lock().unwrap().clone())),
+        }
+        let f = f.take()?;
+        local.as_ref().map(|(_, tx)| f(tx))
+    });
+    match local {
+        Ok(Some(result)) => result,
+        // The thread is exiting, or already inside `f`.
+        _ => {
+            let f = f.take().expect("sender already used");
+            f(&shared.lock().unwrap())
+        }
+    }
+}
+
+fn is_event(msg: &Message) -> bool {
+    matches!(*msg, Message::Event(..) | Message::Captured(..))
+}
+
+pub(crate) fn channel(capacity: Option<usize>, policy: ChannelPolicy) -> (Tx, Receiver<Message>) {
+    match capacity {
+        Some(capacity) => {
+            let (tx, rx) = mpsc::sync_channel(capacity);
+            (Tx::Bounded(tx, policy), rx)
+        }
+        None => {
+            let (tx, rx) = mpsc::channel();
+            (Tx::Unbounded(tx), rx)
+        }
+    }
+}
+
+/// A sender that writes each message out before returning, for targets
+/// without threads, and the writer to start once the subscriber does.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn inline() -> (Tx, Arc<Inline>) {
+    let writer = Arc::new(Inline::default());
+    (Tx::Inline(writer.clone()), writer)
+}
@@ -0,0 +1,191 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::channel::Tx;
+use crate::guard::Shutdown;
+use crate::json::Value;
+use crate::stats::Stats;
+use crate::stream::{read_frame, StreamSink};
+use crate::writer::Route;
+use crate::{Message, TraceEvent, TraceSink, WALL_CLOCK_EVENT};
+
+/// The environment variable `ParentCollector::spawn` passes the pipe's file
+/// descriptor to the child in.
+pub const CHILD_TRACE_FD_VAR: &str = "CHROME_TRACE_FD";
+
+/// The child's end of the pipe set up by `ParentCollector::spawn`: a sink
+/// sending everything the child records to its parent's trace.
+///
+/// ```ignore
+/// let subscriber = match ChildTraceWriter::from_env() {
+///     Some(writer) => ChromeTraceSubscriber::builder().sink(writer).build(),
+///     None => ChromeTraceSubscriber::from_path("child.json")?,
+/// };
+/// ```
+pub struct ChildTraceWriter {
+    stream: StreamSink<BufWriter<File>>,
+}
+
+impl ChildTraceWriter {
+    /// Writes to the pipe named by `CHROME_TRACE_FD`, if the parent set it.
+    /// The variable is removed, so the descriptor is only taken once.
+    pub fn from_env() -> Option<Self> {
+        let fd = env::var(CHILD_TRACE_FD_VAR).ok()?.parse().ok()?;
+        env::remove_var(CHILD_TRACE_FD_VAR);
+        Some(unsafe { ChildTraceWriter::from_fd(fd) })
+    }
+
+    /// Writes to the open pipe `fd`, taking ownership of it. It isn't
+    /// passed on to the child's own children.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be open and owned by nothing else, as for
+    /// `FromRawFd::from_raw_fd`.
+    pub unsafe fn from_fd(fd: RawFd) -> Self {
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        ChildTraceWriter {
+            stream: StreamSink::new(BufWriter::new(File::from_raw_fd(fd))),
+        }
+    }
+}
+
+impl TraceSink for ChildTraceWriter {
+    fn write_event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        self.stream.write_event(event)
+    }
+
+    fn system_trace(&mut self, text: &str) -> io::Result<()> {
+        self.stream.system_trace(text)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.stream.finish()
+    }
+}
+
+/// Launches child processes whose traces are merged into this one, each in
+/// its own process track. Made by `ChromeTraceSubscriber::collector`;
+/// cheap to clone.
+///
+/// Children set themselves up with `ChildTraceWriter::from_env`. Their
+/// timestamps are moved onto this trace's timeline using the `wall_clock`
+/// record each subscriber writes at startup.
+#[derive(Clone)]
+pub struct ParentCollector {
+    pub(crate) tx: Arc<Mutex<Tx>>,
+    pub(crate) stats: Arc<Stats>,
+    pub(crate) shutdown: Arc<Shutdown>,
+    /// When this trace's timestamps start, in microseconds since the Unix
+    /// epoch.
+    pub(crate) epoch: u64,
+}
+
+impl ParentCollector {
+    /// Spawns `command` with a pipe for its trace, and a thread reading the
+    /// events it sends until it finishes or exits.
+    ///
+    /// Outside Linux and Android the pipe can't be created close-on-exec in
+    /// one step, so a process another thread spawns at the same moment may
+    /// inherit the write end, and the reading thread then waits until that
+    /// process exits too. Spawn from one thread there if that matters.
+    pub fn spawn(&self, command: &mut Command) -> io::Result<Child> {
+        let (read, write) = pipe()?;
+        let reader = unsafe { File::from_raw_fd(read) };
+        let writer = unsafe { File::from_raw_fd(write) };
+        // The write end is only made inheritable in this child, once forked.
+        unsafe {
+            command.pre_exec(move || {
+                if libc::fcntl(write, libc::F_SETFD, 0) == 0 {
+                    Ok(())
+                } else {
+                    Err(io::Error::last_os_error())
+                }
+            });
+        }
+        let child = command.env(CHILD_TRACE_FD_VAR, write.to_string()).spawn()?;
+        // Only the child writes, so the reader sees the end of the stream
+        // when it exits.
+        drop(writer);
+        let collector = self.clone();
+        thread::spawn(move || collector.collect(BufReader::new(reader)));
+        Ok(child)
+    }
+
+    fn collect(&self, mut input: BufReader<File>) {
+        // How far the child's timestamps are from ours, once its
+        // `wall_clock` record says.
+        let mut shift = 0;
+        let mut payload = Vec::new();
+        while let Ok(true) = read_frame(&mut input, &mut payload) {
+            let frame: Value = match serde_json::from_slice(&payload) {
+                Ok(frame) => frame,
+                Err(_) => continue,
+            };
+            let msg = if let Some(text) = frame.get("systemTraceEvents").and_then(Value::as_str) {
+                Message::SystemTrace(text.to_owned())
+            } else {
+                let mut event = match TraceEvent::from_json(&frame) {
+                    Some(event) => event,
+                    None => continue,
+                };
+                if event.ph == "M" {
+                    if event.name == WALL_CLOCK_EVENT {
+                        shift = self.shift(&event);
+                        continue;
+                    }
+                    Message::Event(event, Route::All)
+                } else {
+                    event.ts = (event.ts as i64).saturating_add(shift).max(0) as u64;
+                    Message::Event(event, Route::Default)
+                }
+            };
+            self.shutdown.start();
+            if !self.stats.send(&self.tx.lock().unwrap(), msg) {
+                return;
+            }
+        }
+    }
+
+    /// What to add to the child's timestamps, given its `wall_clock`
+    /// record, to put them on this trace's timeline.
+    fn shift(&self, wall_clock: &TraceEvent) -> i64 {
+        let arg = |key| wall_clock.args.get(key).and_then(Value::as_u64).unwrap_or(0) as i64;
+        arg("unix_start_us") - arg("ts_start_us") - self.epoch as i64
+    }
+}
+
+/// A pipe whose ends aren't inherited by spawned processes, as (read, write).
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn pipe() -> io::Result<(RawFd, RawFd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((fds[0], fds[1]))
+}
+
+/// A pipe whose ends aren't inherited by spawned processes, as (read, write).
+/// Not atomic: see `ParentCollector::spawn`.
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn pipe() -> io::Result<(RawFd, RawFd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    unsafe {
+        libc::fcntl(fds[0], libc::F_SETFD, libc::FD_CLOEXEC);
+        libc::fcntl(fds[1], libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+    Ok((fds[0], fds[1]))
+}
@@ -0,0 +1,194 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::time::Duration;
+
+use crate::{in_micros, ClosedSpan, TraceEvent, TraceSink};
+
+enum Window {
+    /// Microseconds before the latest timestamp seen.
+    Last(u64),
+    /// Microseconds since the subscriber started.
+    Between(u64, u64),
+}
+
+enum Item {
+    Event(TraceEvent),
+    /// A begin event and the end that closed it, kept or dropped together.
+    Pair(Box<(TraceEvent, TraceEvent)>),
+    Span(ClosedSpan),
+}
+
+impl Item {
+    fn ts(&self) -> u64 {
+        match *self {
+            Item::Event(ref event) => event.ts,
+            Item::Pair(ref pair) => pair.0.ts,
+            Item::Span(ref span) => span.start,
+        }
+    }
+
+    /// When the item's slice ends, or `ts` if it isn't one.
+    fn end(&self) -> u64 {
+        match *self {
+            Item::Event(ref event) => event.ts + event.dur.unwrap_or(0),
+            Item::Pair(ref pair) => pair.1.ts,
+            Item::Span(ref span) => span.start + span.duration,
+        }
+    }
+}
+
+/// What begin and end events are matched up by.
+#[derive(PartialEq, Eq, Hash)]
+enum PairKey {
+    /// `B` and `E`, by pid and tid.
+    Thread(u32, u64),
+    /// `b` and `e`, by pid, category and id.
+    Async(u32, Cow<'static, str>, u64),
+}
+
+/// The key `event` pairs up by, and whether it's the begin event.
+fn pair_key(event: &TraceEvent) -> Option<(PairKey, bool)> {
+    let key = match event.ph {
+        "B" | "E" => PairKey::Thread(event.pid, event.tid),
+        "b" | "e" => PairKey::Async(event.pid, event.cat.clone(), event.id?),
+        _ => return None,
+    };
+    Some((key, event.ph == "B" || event.ph == "b"))
+}
+
+/// Holds events back until the trace finishes, then hands only those in a
+/// time window to another sink, so a long soak run yields a small trace of
+/// the part that matters. Process and thread metadata is always kept.
+/// Slices are kept if any of them falls in the window, and begin and end
+/// events are kept or dropped in pairs, so none is left without the other.
+/// Checkpoints are skipped, since nothing is written until the end.
+pub struct ClippedSink<S> {
+    inner: S,
+    window: Window,
+    metadata: Vec<TraceEvent>,
+    items: VecDeque<Item>,
+    /// Begin events whose end hasn't been seen yet, innermost last.
+    open: HashMap<PairKey, Vec<TraceEvent>>,
+    latest: u64,
+}
+
+impl<S: TraceSink> ClippedSink<S> {
+    /// Keep only the final `keep` of the trace.
+    pub fn last(inner: S, keep: Duration) -> Self {
+        ClippedSink::new(inner, Window::Last(in_micros(keep)))
+    }
+
+    /// Keep only what happened between `from` and `to` after the subscriber
+    /// started.
+    pub fn between(inner: S, from: Duration, to: Duration) -> Self {
+        ClippedSink::new(inner, Window::Between(in_micros(from), in_micros(to)))
+    }
+
+    fn new(inner: S, window: Window) -> Self {
+        ClippedSink {
+            inner,
+            window,
+            metadata: Vec::new(),
+            items: VecDeque::new(),
+            open: HashMap::new(),
+            latest: 0,
+        }
+    }
+
+    fn push(&mut self, item: Item) {
+        let end = item.end();
+        match self.window {
+            Window::Last(keep) => {
+                self.latest = self.latest.max(end);
+                let cutoff = self.latest.saturating_sub(keep);
+                // Events arrive roughly in order, so trimming from the front
+                // keeps memory bounded by the window.
+                while self.items.front().is_some_and(|item| item.end() < cutoff) {
+                    self.items.pop_front();
+                }
+                if end >= cutoff {
+                    self.items.push_back(item);
+                }
+            }
+            Window::Between(from, to) => {
+                if end >= from && item.ts() <= to {
+                    self.items.push_back(item);
+                }
+            }
+        }
+    }
+}
+
+impl<S: TraceSink> TraceSink for ClippedSink<S> {
+    fn start(&mut self) -> io::Result<()> {
+        self.inner.start()
+    }
+
+    fn write_event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        if event.ph == "M" {
+            self.metadata.push(event.clone());
+            return Ok(());
+        }
+        let item = match pair_key(event) {
+            Some((key, true)) => {
+                self.open.entry(key).or_default().push(event.clone());
+                return Ok(());
+            }
+            Some((key, false)) => match self.open.get_mut(&key).and_then(Vec::pop) {
+                Some(begin) => Item::Pair(Box::new((begin, event.clone()))),
+                None => Item::Event(event.clone()),
+            },
+            None => Item::Event(event.clone()),
+        };
+        self.push(item);
+        Ok(())
+    }
+
+    fn wants_spans(&self) -> bool {
+        self.inner.wants_spans()
+    }
+
+    fn span_closed(&mut self, span: &ClosedSpan) -> io::Result<()> {
+        self.push(Item::Span(span.clone()));
+        Ok(())
+    }
+
+    fn system_trace(&mut self, text: &str) -> io::Result<()> {
+        self.inner.system_trace(text)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let (cutoff, to) = match self.window {
+            Window::Last(keep) => (self.latest.saturating_sub(keep), u64::MAX),
+            Window::Between(from, to) => (from, to),
+        };
+        for event in self.metadata.drain(..) {
+            self.inner.write_event(&event)?;
+        }
+        for item in self.items.drain(..) {
+            if item.end() < cutoff {
+                continue;
+            }
+            match item {
+                Item::Event(event) => self.inner.write_event(&event)?,
+                Item::Pair(pair) => {
+                    self.inner.write_event(&pair.0)?;
+                    self.inner.write_event(&pair.1)?;
+                }
+                Item::Span(span) => self.inner.span_closed(&span)?,
+            }
+        }
+        // Never closed, so they run to the end of the trace.
+        for (_, begins) in self.open.drain() {
+            for begin in begins.iter().filter(|begin| begin.ts <= to) {
+                self.inner.write_event(begin)?;
+            }
+        }
+        self.inner.finish()
+    }
+}
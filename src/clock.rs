@@ -0,0 +1,7 @@
+// std's clocks panic on wasm32-unknown-unknown; web-time reads the
+// browser's (`performance.now()` and `Date.now()`) there, and is std's
+// everywhere else.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use std::time::{Instant, SystemTime, UNIX_EPOCH};
+#[cfg(target_arch = "wasm32")]
+pub(crate) use web_time::{Instant, SystemTime, UNIX_EPOCH};
@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+
+use crate::json::{Map, Value};
+use crate::writer::Route;
+use crate::{TraceEvent, SUMMARY_EVENT};
+
+/// Short slices of the same name, one after the other on one thread.
+struct Run {
+    /// The first slice, which the summary slice is made from.
+    first: TraceEvent,
+    route: Route,
+    count: u64,
+    /// The slices' durations, added up.
+    total: u64,
+    end: u64,
+}
+
+/// Replaces runs of short, identically named slices on a thread with one
+/// summary slice, see `ChromeTraceBuilder::summarize_short_spans`. Runs on
+/// the writer thread, before dedup and any transforms, on timestamps as
+/// recorded.
+pub(crate) struct Coalesce {
+    threshold: u64,
+    /// The open run, by pid and tid.
+    runs: HashMap<(u32, u64), Run>,
+    /// Slices replaced by summary slices so far.
+    folded: u64,
+}
+
+impl Coalesce {
+    pub fn new(threshold: u64) -> Self {
+        Coalesce {
+            threshold: threshold.max(1),
+            runs: HashMap::new(),
+            folded: 0,
+        }
+    }
+
+    /// Takes `event`, returning the events ready to write: the run it ends,
+    /// if any, and `event` itself unless it's a short slice.
+    pub fn push(&mut self, event: TraceEvent, route: Route) -> Vec<(TraceEvent, Route)> {
+        if event.ph == "M" {
+            return vec![(event, route)];
+        }
+        // End every run first, so the summary's count covers them.
+        if event.name == SUMMARY_EVENT {
+            let mut ready = self.drain();
+            ready.push((event, route));
+            return ready;
+        }
+        let thread = (event.pid, event.tid);
+        let mut ready = Vec::new();
+        let short = event.ph == "X" && event.dur.is_some_and(|dur| dur < self.threshold);
+        if !short {
+            if let Some(run) = self.runs.remove(&thread) {
+                ready.push(self.close(run));
+            }
+            ready.push((event, route));
+            return ready;
+        }
+        let dur = event.dur.unwrap_or(0);
+        if let Some(run) = self.runs.get_mut(&thread) {
+            let joins = run.first.name == event.name
+                && run.first.cat == event.cat
+                && run.route == route
+                && event.ts <= run.end + self.threshold;
+            if joins {
+                run.count += 1;
+                run.total += dur;
+                run.end = run.end.max(event.ts + dur);
+                return ready;
+            }
+        }
+        let run = Run {
+            end: event.ts + dur,
+            first: event,
+            route,
+            count: 1,
+            total: dur,
+        };
+        if let Some(run) = self.runs.insert(thread, run) {
+            ready.push(self.close(run));
+        }
+        ready
+    }
+
+    /// The runs no later slice can join any more by `now`.
+    pub fn release(&mut self, now: u64) -> Vec<(TraceEvent, Route)> {
+        let threshold = self.threshold;
+        let over: Vec<_> = self
+            .runs
+            .iter()
+            .filter(|(_, run)| run.end + threshold < now)
+            .map(|(&thread, _)| thread)
+            .collect();
+        let runs: Vec<_> = over.iter().filter_map(|thread| self.runs.remove(thread)).collect();
+        runs.into_iter().map(|run| self.close(run)).collect()
+    }
+
+    /// Every open run, e.g. before a flush.
+    pub fn drain(&mut self) -> Vec<(TraceEvent, Route)> {
+        let runs: Vec<_> = self.runs.drain().map(|(_, run)| run).collect();
+        runs.into_iter().map(|run| self.close(run)).collect()
+    }
+
+    pub fn folded(&self) -> u64 {
+        self.folded
+    }
+
+    /// A run of one is written as it was.
+    fn close(&mut self, run: Run) -> (TraceEvent, Route) {
+        let mut event = run.first;
+        if run.count > 1 {
+            self.folded += run.count;
+            event.dur = Some(run.end - event.ts);
+            let mut args = Map::new();
+            args.insert("summarized_count".to_owned(), Value::from(run.count));
+            args.insert("summarized_dur_us".to_owned(), Value::from(run.total));
+            event.args = args;
+        }
+        (event, run.route)
+    }
+}
@@ -0,0 +1,148 @@
+use arrow::array::{ArrayRef, StringBuilder, UInt32Builder, UInt64Builder};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use std::error::Error;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use crate::json;
+use crate::{TraceEvent, TraceSink};
+
+/// Events are buffered into record batches of this many rows.
+const BATCH: usize = 8192;
+
+struct Columns {
+    ts: UInt64Builder,
+    dur: UInt64Builder,
+    ph: StringBuilder,
+    name: StringBuilder,
+    cat: StringBuilder,
+    target: StringBuilder,
+    level: StringBuilder,
+    pid: UInt32Builder,
+    tid: UInt64Builder,
+    span_id: UInt64Builder,
+    args: StringBuilder,
+    rows: usize,
+}
+
+impl Columns {
+    fn new() -> Self {
+        Columns {
+            ts: UInt64Builder::new(),
+            dur: UInt64Builder::new(),
+            ph: StringBuilder::new(),
+            name: StringBuilder::new(),
+            cat: StringBuilder::new(),
+            target: StringBuilder::new(),
+            level: StringBuilder::new(),
+            pid: UInt32Builder::new(),
+            tid: UInt64Builder::new(),
+            span_id: UInt64Builder::new(),
+            args: StringBuilder::new(),
+            rows: 0,
+        }
+    }
+
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        self.rows = 0;
+        vec![
+            Arc::new(self.ts.finish()),
+            Arc::new(self.dur.finish()),
+            Arc::new(self.ph.finish()),
+            Arc::new(self.name.finish()),
+            Arc::new(self.cat.finish()),
+            Arc::new(self.target.finish()),
+            Arc::new(self.level.finish()),
+            Arc::new(self.pid.finish()),
+            Arc::new(self.tid.finish()),
+            Arc::new(self.span_id.finish()),
+            Arc::new(self.args.finish()),
+        ]
+    }
+}
+
+fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("ts", DataType::UInt64, false),
+        Field::new("dur", DataType::UInt64, true),
+        Field::new("ph", DataType::Utf8, false),
+        Field::new("name", DataType::Utf8, false),
+        Field::new("cat", DataType::Utf8, false),
+        Field::new("target", DataType::Utf8, false),
+        Field::new("level", DataType::Utf8, true),
+        Field::new("pid", DataType::UInt32, false),
+        Field::new("tid", DataType::UInt64, false),
+        Field::new("span_id", DataType::UInt64, true),
+        Field::new("args", DataType::Utf8, false),
+    ]))
+}
+
+fn to_io<E: Error + Send + Sync + 'static>(e: E) -> io::Error {
+    io::Error::other(e)
+}
+
+/// Writes events to a Parquet file with typed columns (`ts`, `dur`, `ph`,
+/// `name`, `cat`, `target`, `level`, `pid`, `tid`, `span_id`, with `args`
+/// as JSON text), for DuckDB, Polars and the like. The file is only
+/// readable once the sink is finished.
+pub struct ParquetSink<W: Write + Send> {
+    writer: Option<ArrowWriter<W>>,
+    schema: SchemaRef,
+    columns: Columns,
+}
+
+impl<W: Write + Send> ParquetSink<W> {
+    pub fn new(writer: W) -> io::Result<Self> {
+        let schema = schema();
+        let writer = ArrowWriter::try_new(writer, schema.clone(), None).map_err(to_io)?;
+        Ok(ParquetSink {
+            writer: Some(writer),
+            schema,
+            columns: Columns::new(),
+        })
+    }
+
+    fn write_batch(&mut self) -> io::Result<()> {
+        if self.columns.rows == 0 {
+            return Ok(());
+        }
+        let batch = RecordBatch::try_new(self.schema.clone(), self.columns.finish()).map_err(to_io)?;
+        match self.writer {
+            Some(ref mut writer) => writer.write(&batch).map_err(to_io),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write + Send> TraceSink for ParquetSink<W> {
+    fn write_event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        let args = json::map_to_string(&event.args);
+        let columns = &mut self.columns;
+        columns.ts.append_value(event.ts);
+        columns.dur.append_option(event.dur);
+        columns.ph.append_value(event.ph);
+        columns.name.append_value(&event.name);
+        columns.cat.append_value(&event.cat);
+        columns.target.append_value(event.target);
+        columns.level.append_option(event.level);
+        columns.pid.append_value(event.pid);
+        columns.tid.append_value(event.tid);
+        columns.span_id.append_option(event.span);
+        columns.args.append_value(args);
+        columns.rows += 1;
+        if columns.rows >= BATCH {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.write_batch()?;
+        match self.writer.take() {
+            Some(writer) => writer.close().map(drop).map_err(to_io),
+            None => Ok(()),
+        }
+    }
+}
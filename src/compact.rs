@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+#[cfg(feature = "serde_json")]
+use std::io::{self, BufRead, Write};
+
+use crate::json::{Map, Value};
+use crate::TraceEvent;
+#[cfg(feature = "serde_json")]
+use crate::{JsonSink, TraceSink};
+
+/// Metadata records carrying newly interned strings, keyed by index.
+pub const DICTIONARY_RECORD: &str = "chrome_trace_dictionary";
+
+/// Short forms of the top-level event keys.
+const KEYS: &[(&str, &str)] = &[
+    ("name", "n"),
+    ("cat", "c"),
+    ("ph", "p"),
+    ("ts", "t"),
+    ("dur", "d"),
+    ("pid", "P"),
+    ("tid", "T"),
+    ("id", "i"),
+    ("s", "s"),
+    ("cname", "C"),
+    ("args", "a"),
+];
+
+fn short_key(key: &str) -> &str {
+    KEYS.iter().find(|&&(long, _)| long == key).map_or(key, |&(_, short)| short)
+}
+
+#[cfg(feature = "serde_json")]
+fn long_key(key: &str) -> &str {
+    KEYS.iter().find(|&&(_, short)| short == key).map_or(key, |&(long, _)| long)
+}
+
+/// Interns event names and categories for `JsonSink::compact`.
+#[derive(Default)]
+pub(crate) struct Dictionary {
+    strings: HashMap<String, usize>,
+}
+
+impl Dictionary {
+    /// Encodes `event`, returning the dictionary record that has to be
+    /// written before it, if it introduces new strings.
+    pub fn encode(&mut self, event: &TraceEvent) -> (Option<Value>, Value) {
+        let mut added = Map::new();
+        let mut obj = Map::new();
+        if let Value::Object(full) = event.to_json() {
+            for (key, value) in full {
+                let value = match (key.as_str(), value) {
+                    ("name", Value::String(s)) | ("cat", Value::String(s)) => {
+                        Value::from(self.intern(s, &mut added))
+                    }
+                    (_, value) => value,
+                };
+                obj.insert(short_key(&key).to_owned(), value);
+            }
+        }
+        let record = if added.is_empty() {
+            None
+        } else {
+            let mut record = Map::new();
+            record.insert(short_key("name").to_owned(), Value::from(DICTIONARY_RECORD));
+            record.insert(short_key("ph").to_owned(), Value::from("M"));
+            record.insert(short_key("args").to_owned(), Value::Object(added));
+            Some(Value::Object(record))
+        };
+        (record, Value::Object(obj))
+    }
+
+    fn intern(&mut self, s: String, added: &mut Map<String, Value>) -> usize {
+        if let Some(&index) = self.strings.get(&s) {
+            return index;
+        }
+        let index = self.strings.len();
+        added.insert(index.to_string(), Value::from(s.as_str()));
+        self.strings.insert(s, index);
+        index
+    }
+}
+
+/// Turns a trace written by `JsonSink::compact` back into one viewers can
+/// load, in the same (array or object) format.
+#[cfg(feature = "serde_json")]
+pub fn decode_compact<R: BufRead, W: Write + Send>(input: R, output: W) -> io::Result<()> {
+    let mut sink: Option<JsonSink<W>> = None;
+    let mut output = Some(output);
+    let mut strings: HashMap<String, Value> = HashMap::new();
+    for line in input.lines() {
+        let line = line?;
+        let trimmed = line.trim().trim_end_matches(',');
+        if sink.is_none() {
+            // The first line opens the array or object.
+            let mut opened = JsonSink::from_writer(output.take().unwrap());
+            if trimmed.starts_with('{') {
+                opened = opened.object_format();
+            }
+            opened.start()?;
+            sink = Some(opened);
+            continue;
+        }
+        let sink = sink.as_mut().unwrap();
+        if let Some(rest) = trimmed.strip_prefix("],\"systemTraceEvents\":") {
+            let text: Value = serde_json::from_str(rest.trim_end_matches('}'))?;
+            if let Some(text) = text.as_str() {
+                sink.system_trace(text)?;
+            }
+            continue;
+        }
+        let event = match serde_json::from_str(trimmed) {
+            Ok(Value::Object(event)) => event,
+            // The closing bracket.
+            _ => continue,
+        };
+        let mut decoded = Map::new();
+        for (key, value) in event {
+            decoded.insert(long_key(&key).to_owned(), value);
+        }
+        if decoded.get("name").and_then(Value::as_str) == Some(DICTIONARY_RECORD) {
+            if let Some(Value::Object(added)) = decoded.remove("args") {
+                strings.extend(added);
+            }
+            continue;
+        }
+        for &key in &["name", "cat"] {
+            let index = decoded.get(key).and_then(Value::as_u64);
+            if let Some(s) = index.and_then(|index| strings.get(&index.to_string())) {
+                decoded.insert(key.to_owned(), s.clone());
+            }
+        }
+        sink.write_json(&serde_json::to_vec(&Value::Object(decoded))?)?;
+    }
+    match sink {
+        Some(mut sink) => sink.finish(),
+        None => Ok(()),
+    }
+}
+
+#[cfg(all(test, feature = "serde_json"))]
+mod tests {
+    use super::*;
+    use crate::JsonFormat;
+
+    fn events() -> Vec<TraceEvent> {
+        let mut args = Map::new();
+        args.insert("name".to_owned(), Value::from("main"));
+        let mut events = vec![TraceEvent::metadata("thread_name", args)];
+        for ts in 0..3 {
+            let mut event = TraceEvent::new("X", "poll".to_owned(), "runtime".to_owned(), ts * 10);
+            event.dur = Some(5);
+            event.args.insert("n".to_owned(), Value::from(ts));
+            event.args.insert("ratio".to_owned(), Value::from(0.5));
+            events.push(event);
+        }
+        let mut event = TraceEvent::new("i", "naïve, \"quoted\"".to_owned(), "runtime".to_owned(), 40);
+        event.scope = Some("t");
+        event.id = Some(7);
+        events.push(event);
+        events
+    }
+
+    fn write(format: JsonFormat, compact: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut sink = JsonSink::from_writer(&mut out).format(format);
+        if compact {
+            sink = sink.compact();
+        }
+        sink.start().unwrap();
+        for event in events() {
+            sink.write_event(&event).unwrap();
+        }
+        sink.system_trace("# tracer: nop\nend}").unwrap();
+        sink.finish().unwrap();
+        drop(sink);
+        out
+    }
+
+    #[test]
+    fn decodes_to_the_plain_trace() {
+        for &format in &[JsonFormat::Array, JsonFormat::Object] {
+            let compact = write(format, true);
+            let plain = write(format, false);
+            assert_ne!(compact, plain);
+            let mut decoded = Vec::new();
+            decode_compact(&compact[..], &mut decoded).unwrap();
+            let decoded: Value = serde_json::from_slice(&decoded).unwrap();
+            assert_eq!(decoded, serde_json::from_slice::<Value>(&plain).unwrap(), "{:?}", format);
+        }
+    }
+}
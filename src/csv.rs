@@ -0,0 +1,94 @@
+use std::io::{self, Write};
+
+use crate::json::Value;
+use crate::{TraceEvent, TraceSink};
+
+/// Writes one row per event, for spreadsheets, pandas or SQL: `ts`, `dur`,
+/// `name`, `target`, `level` and `tid`, followed by a column for each arg
+/// chosen with `arg`. Metadata records are skipped.
+pub struct CsvSink<W> {
+    writer: W,
+    delimiter: u8,
+    args: Vec<String>,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(writer: W) -> Self {
+        CsvSink {
+            writer,
+            delimiter: b',',
+            args: Vec::new(),
+        }
+    }
+
+    /// Separate columns with tabs instead of commas.
+    pub fn tsv(mut self) -> Self {
+        self.delimiter = b'\t';
+        self
+    }
+
+    /// Add a column with the value of arg `name`, empty for events that
+    /// don't have it.
+    pub fn arg(mut self, name: &str) -> Self {
+        self.args.push(name.to_owned());
+        self
+    }
+
+    fn row<'a, I: IntoIterator<Item = &'a str>>(&mut self, fields: I) -> io::Result<()> {
+        let mut line = Vec::new();
+        for (i, field) in fields.into_iter().enumerate() {
+            if i > 0 {
+                line.push(self.delimiter);
+            }
+            let delimiter = self.delimiter as char;
+            if field.contains(&[delimiter, '"', '\n', '\r'][..]) {
+                line.push(b'"');
+                line.extend_from_slice(field.replace('"', "\"\"").as_bytes());
+                line.push(b'"');
+            } else {
+                line.extend_from_slice(field.as_bytes());
+            }
+        }
+        line.push(b'\n');
+        self.writer.write_all(&line)
+    }
+}
+
+impl<W: Write + Send> TraceSink for CsvSink<W> {
+    fn start(&mut self) -> io::Result<()> {
+        let mut header = vec!["ts", "dur", "name", "target", "level", "tid"];
+        let args = self.args.clone();
+        header.extend(args.iter().map(String::as_str));
+        self.row(header)
+    }
+
+    fn write_event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        if event.ph == "M" {
+            return Ok(());
+        }
+        let mut fields = vec![
+            event.ts.to_string(),
+            event.dur.map(|dur| dur.to_string()).unwrap_or_default(),
+            event.name.to_string(),
+            event.target.to_owned(),
+            event.level.unwrap_or("").to_owned(),
+            event.tid.to_string(),
+        ];
+        for arg in &self.args {
+            fields.push(match event.args.get(arg) {
+                Some(Value::String(s)) => s.clone(),
+                Some(value) => value.to_string(),
+                None => String::new(),
+            });
+        }
+        self.row(fields.iter().map(String::as_str))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,96 @@
+use std::borrow::Cow;
+
+use crate::json::{self, Value};
+use crate::writer::Route;
+use crate::{TraceEvent, SUMMARY_EVENT};
+
+/// An instant event waiting to see whether duplicates follow it.
+struct Held {
+    /// The callsite (target, category and name), serialized args and time
+    /// bucket duplicates share.
+    key: (&'static str, Cow<'static, str>, Cow<'static, str>, String, u64),
+    event: TraceEvent,
+    route: Route,
+    count: u64,
+}
+
+/// Folds repeats of the same instant event into one, see
+/// `ChromeTraceBuilder::dedup_window`. Runs on the writer thread, before
+/// any transforms, on timestamps as recorded.
+pub(crate) struct Dedup {
+    window: u64,
+    held: Vec<Held>,
+    /// Events folded into an earlier one so far.
+    folded: u64,
+}
+
+impl Dedup {
+    pub fn new(window: u64) -> Self {
+        Dedup {
+            window: window.max(1),
+            held: Vec::new(),
+            folded: 0,
+        }
+    }
+
+    /// Takes `event`, returning the events ready to write: those from
+    /// windows that have passed, and `event` itself if it isn't a candidate.
+    pub fn push(&mut self, event: TraceEvent, route: Route) -> Vec<(TraceEvent, Route)> {
+        let instant = event.ph == "i" || event.ph == "I";
+        if !instant || event.name == SUMMARY_EVENT {
+            let mut ready = self.release(event.ts);
+            ready.push((event, route));
+            return ready;
+        }
+        let ready = self.release(event.ts);
+        let key = (
+            event.target,
+            event.cat.clone(),
+            event.name.clone(),
+            json::map_to_string(&event.args),
+            event.ts / self.window,
+        );
+        match self.held.iter_mut().find(|held| held.key == key) {
+            Some(held) => {
+                held.count += 1;
+                self.folded += 1;
+            }
+            None => self.held.push(Held {
+                key,
+                event,
+                route,
+                count: 1,
+            }),
+        }
+        ready
+    }
+
+    /// The held events whose window ended before `now`.
+    pub fn release(&mut self, now: u64) -> Vec<(TraceEvent, Route)> {
+        let bucket = now / self.window;
+        if self.held.iter().all(|held| held.key.4 >= bucket) {
+            return Vec::new();
+        }
+        let (ready, held): (Vec<_>, Vec<_>) = self.held.drain(..).partition(|held| held.key.4 < bucket);
+        self.held = held;
+        ready.into_iter().map(Held::into_event).collect()
+    }
+
+    /// Every held event, e.g. before a flush.
+    pub fn drain(&mut self) -> Vec<(TraceEvent, Route)> {
+        self.held.drain(..).map(Held::into_event).collect()
+    }
+
+    pub fn folded(&self) -> u64 {
+        self.folded
+    }
+}
+
+impl Held {
+    fn into_event(mut self) -> (TraceEvent, Route) {
+        if self.count > 1 {
+            self.event.args.insert("dedup_count".to_owned(), Value::from(self.count));
+        }
+        (self.event, self.route)
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read};
+
+use crate::json::Value;
+
+/// How often a span (or any other slice) ran in a trace, and for how long.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SliceStats {
+    pub count: u64,
+    pub total_us: u64,
+}
+
+impl SliceStats {
+    pub fn mean_us(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.total_us as f64 / self.count as f64
+        }
+    }
+}
+
+/// One slice name's stats in two traces, see `compare_slices`.
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub name: String,
+    pub base: SliceStats,
+    pub new: SliceStats,
+}
+
+impl Change {
+    /// The new mean duration over the base one, so `1.2` is 20% slower.
+    pub fn ratio(&self) -> f64 {
+        let base = self.base.mean_us();
+        if base == 0.0 {
+            1.0
+        } else {
+            self.new.mean_us() / base
+        }
+    }
+}
+
+/// Reads a trace in any of the formats `JsonSink` writes (or the Chrome
+/// tracing format in general) and totals its slices by name: complete
+/// (`X`) events and matched `B`/`E` pairs.
+pub fn summarize_slices<R: Read>(mut trace: R) -> io::Result<BTreeMap<String, SliceStats>> {
+    let mut text = String::new();
+    trace.read_to_string(&mut text)?;
+    let value: Value = match serde_json::from_str(&text) {
+        Ok(value) => value,
+        // `JsonFormat::LenientArray` leaves a comma before the bracket.
+        Err(_) => serde_json::from_str(&lenient(&text))?,
+    };
+    let events = match value {
+        Value::Array(events) => events,
+        Value::Object(mut object) => match object.remove("traceEvents") {
+            Some(Value::Array(events)) => events,
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    };
+    let mut stats: BTreeMap<String, SliceStats> = BTreeMap::new();
+    let mut open: HashMap<(u64, u64), Vec<(String, u64)>> = HashMap::new();
+    for event in &events {
+        let field = |key: &str| event.get(key).and_then(Value::as_u64).unwrap_or(0);
+        let name = event.get("name").and_then(Value::as_str).unwrap_or("");
+        let slice = match event.get("ph").and_then(Value::as_str) {
+            Some("X") => Some((name.to_owned(), field("dur"))),
+            Some("B") => {
+                let thread = open.entry((field("pid"), field("tid"))).or_default();
+                thread.push((name.to_owned(), field("ts")));
+                None
+            }
+            Some("E") => open
+                .get_mut(&(field("pid"), field("tid")))
+                .and_then(Vec::pop)
+                .map(|(name, begin)| (name, field("ts").saturating_sub(begin))),
+            _ => None,
+        };
+        if let Some((name, dur)) = slice {
+            let entry = stats.entry(name).or_default();
+            entry.count += 1;
+            entry.total_us += dur;
+        }
+    }
+    Ok(stats)
+}
+
+fn lenient(text: &str) -> String {
+    let trimmed = text.trim_end();
+    let last = trimmed.char_indices().last().map_or(0, |(at, _)| at);
+    let (body, close) = trimmed.split_at(last);
+    format!("{}{}", body.trim_end().trim_end_matches(','), close)
+}
+
+/// The slices found in both traces, the biggest slowdown first.
+pub fn compare_slices(base: &BTreeMap<String, SliceStats>, new: &BTreeMap<String, SliceStats>) -> Vec<Change> {
+    let mut changes: Vec<Change> = base
+        .iter()
+        .filter_map(|(name, &base)| {
+            new.get(name).map(|&new| Change {
+                name: name.clone(),
+                base,
+                new,
+            })
+        })
+        .collect();
+    changes.sort_by(|a, b| b.ratio().partial_cmp(&a.ratio()).unwrap_or(std::cmp::Ordering::Equal));
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summarize(trace: &str) -> BTreeMap<String, SliceStats> {
+        summarize_slices(trace.as_bytes()).unwrap()
+    }
+
+    fn stats(count: u64, total_us: u64) -> SliceStats {
+        SliceStats { count, total_us }
+    }
+
+    const EVENTS: &str = r#"{"name":"a","ph":"X","dur":10},
+{"name":"a","ph":"X","dur":30},
+{"name":"b","ph":"X","dur":5},
+{"name":"m","ph":"M"}"#;
+
+    #[test]
+    fn array() {
+        let summary = summarize(&format!("[\n{}\n]\n", EVENTS));
+        assert_eq!(summary.len(), 2);
+        assert_eq!(summary["a"], stats(2, 40));
+        assert_eq!(summary["b"], stats(1, 5));
+    }
+
+    #[test]
+    fn object() {
+        let trace = format!("{{\"traceEvents\":[\n{}\n],\"systemTraceEvents\":\"\"}}", EVENTS);
+        assert_eq!(summarize(&trace), summarize(&format!("[{}]", EVENTS)));
+    }
+
+    #[test]
+    fn lenient_array() {
+        assert_eq!(lenient("[1,\n2,\n]\n"), "[1,\n2]");
+        let summary = summarize(&format!("[\n{},\n]\n", EVENTS));
+        assert_eq!(summary["a"], stats(2, 40));
+    }
+
+    #[test]
+    fn begin_end_pairs_by_thread() {
+        let trace = r#"[
+{"name":"a","ph":"B","pid":1,"tid":1,"ts":0},
+{"name":"b","ph":"B","pid":1,"tid":2,"ts":5},
+{"name":"inner","ph":"B","pid":1,"tid":1,"ts":6},
+{"ph":"E","pid":1,"tid":1,"ts":8},
+{"ph":"E","pid":1,"tid":1,"ts":10},
+{"ph":"E","pid":1,"tid":2,"ts":20},
+{"ph":"E","pid":1,"tid":3,"ts":30}
+]"#;
+        let summary = summarize(trace);
+        assert_eq!(summary.len(), 3);
+        assert_eq!(summary["a"], stats(1, 10));
+        assert_eq!(summary["b"], stats(1, 15));
+        assert_eq!(summary["inner"], stats(1, 2));
+    }
+
+    #[test]
+    fn biggest_slowdown_first() {
+        let base: BTreeMap<_, _> = vec![
+            ("faster".to_owned(), stats(2, 20)),
+            ("slower".to_owned(), stats(1, 10)),
+            ("same".to_owned(), stats(1, 10)),
+            ("gone".to_owned(), stats(1, 10)),
+        ]
+        .into_iter()
+        .collect();
+        let new: BTreeMap<_, _> = vec![
+            ("faster".to_owned(), stats(2, 10)),
+            ("slower".to_owned(), stats(2, 40)),
+            ("same".to_owned(), stats(3, 30)),
+            ("added".to_owned(), stats(1, 10)),
+        ]
+        .into_iter()
+        .collect();
+        let changes = compare_slices(&base, &new);
+        let names: Vec<&str> = changes.iter().map(|change| change.name.as_str()).collect();
+        assert_eq!(names, ["slower", "same", "faster"]);
+        assert_eq!(changes[0].ratio(), 2.0);
+        assert_eq!(changes[2].ratio(), 0.5);
+    }
+}
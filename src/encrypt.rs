@@ -0,0 +1,73 @@
+use age::stream::StreamWriter;
+use age::{x25519, Encryptor, Recipient};
+use std::io::{self, Write};
+
+use crate::JsonSink;
+
+/// Encrypts everything written through it to a set of age X25519
+/// recipients, so traces with sensitive args can only be read by holders of
+/// the matching identities (`age -d -i key.txt trace.json.age`).
+///
+/// A `JsonSink` made with `JsonSink::encrypted` finalizes the age stream
+/// when the trace finishes; otherwise call `finish`, or it's finalized when
+/// the writer is dropped, ignoring any error.
+pub struct EncryptedWriter<W: Write> {
+    inner: Option<StreamWriter<W>>,
+}
+
+impl<W: Write> EncryptedWriter<W> {
+    /// `recipients` are age public keys, e.g. `age1ql3z7hjy54pw3hyww5ay...`.
+    pub fn new(output: W, recipients: &[&str]) -> io::Result<Self> {
+        let mut keys: Vec<Box<dyn Recipient + Send>> = Vec::with_capacity(recipients.len());
+        for recipient in recipients {
+            let key = recipient
+                .parse::<x25519::Recipient>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            keys.push(Box::new(key));
+        }
+        let encryptor = Encryptor::with_recipients(keys).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no encryption recipients given")
+        })?;
+        Ok(EncryptedWriter {
+            inner: Some(encryptor.wrap_output(output).map_err(io::Error::other)?),
+        })
+    }
+
+    /// Finalizes the age stream. Nothing more may be written after.
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self.inner.take() {
+            Some(inner) => inner.finish()?.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Write for EncryptedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.inner {
+            Some(ref mut inner) => inner.write(buf),
+            None => Err(io::Error::other("encrypted stream already finished")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.inner {
+            Some(ref mut inner) => inner.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Drop for EncryptedWriter<W> {
+    fn drop(&mut self) {
+        drop(self.finish());
+    }
+}
+
+impl<W: Write> JsonSink<EncryptedWriter<W>> {
+    /// A `JsonSink` whose output is encrypted to `recipients` as it's written.
+    pub fn encrypted(output: W, recipients: &[&str]) -> io::Result<Self> {
+        let writer = EncryptedWriter::new(output, recipients)?;
+        Ok(JsonSink::from_writer(writer).ending_with(EncryptedWriter::finish))
+    }
+}
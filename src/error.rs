@@ -0,0 +1,43 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Why a subscriber couldn't be set up as asked, from the fallible
+/// constructors like `ChromeTraceBuilder::try_build`.
+#[derive(Debug)]
+pub enum Error {
+    /// Creating or writing an output failed.
+    Io(io::Error),
+    /// A setting that can't work as given, e.g. a zero sampling interval.
+    Config(String),
+    /// A filter directive that doesn't parse, e.g. `hyper=loud`.
+    Filter(String),
+    /// Something asked for that this platform or build can't do.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "trace output failed: {}", e),
+            Error::Config(ref what) => write!(f, "invalid trace configuration: {}", what),
+            Error::Filter(ref directive) => write!(f, "invalid trace filter directive `{}`", directive),
+            Error::Unsupported(what) => write!(f, "not supported here: {}", what),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
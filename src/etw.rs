@@ -0,0 +1,78 @@
+use std::io;
+use std::sync::Once;
+use tracelogging as tlg;
+
+use crate::json;
+use crate::{TraceEvent, TraceSink};
+
+tlg::define_provider!(PROVIDER, "TokioTraceChromeTrace");
+
+static REGISTER: Once = Once::new();
+
+/// Writes each event as a TraceLogging event from the
+/// `TokioTraceChromeTrace` ETW provider, for Windows Performance Analyzer
+/// and xperf. Enable it in a session with e.g.
+/// `wpr -start provider.wprp` or `xperf -on *TokioTraceChromeTrace`.
+///
+/// Events are written from the writer thread, so the originating thread is
+/// in the `tid` field rather than the ETW event header, and `ts` is in
+/// microseconds since the subscriber started.
+pub struct EtwSink {
+    _private: (),
+}
+
+impl EtwSink {
+    pub fn new() -> Self {
+        // The provider stays registered until the process exits, so any
+        // number of sinks can share it.
+        REGISTER.call_once(|| unsafe {
+            PROVIDER.register();
+        });
+        EtwSink { _private: () }
+    }
+}
+
+impl Default for EtwSink {
+    fn default() -> Self {
+        EtwSink::new()
+    }
+}
+
+impl TraceSink for EtwSink {
+    fn write_event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        let args = json::map_to_string(&event.args);
+        let dur = event.dur.unwrap_or(0);
+        let id = event.id.unwrap_or(0);
+        // The opcode is part of the event's static metadata, so each phase
+        // kind needs its own call site.
+        macro_rules! emit {
+            ($opcode:ident) => {
+                tlg::write_event!(
+                    PROVIDER,
+                    "TraceEvent",
+                    level(Informational),
+                    opcode($opcode),
+                    str8("name", &event.name),
+                    str8("cat", &event.cat),
+                    str8("ph", event.ph),
+                    u64("ts", &event.ts),
+                    u64("dur", &dur),
+                    u32("pid", &event.pid),
+                    u64("tid", &event.tid),
+                    u64("id", &id),
+                    str8("args", &args),
+                )
+            };
+        }
+        match event.ph {
+            "B" | "b" => emit!(Start),
+            "E" | "e" => emit!(Stop),
+            _ => emit!(Info),
+        };
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+use std::borrow::Cow;
+#[cfg(not(target_arch = "wasm32"))]
+use std::process;
+#[cfg(target_arch = "wasm32")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::json::{self, Map, Value};
+
+/// Every phase this crate writes, so events read back in (or named by
+/// `PHASE_FIELD`) can keep `ph` as a `&'static str`.
+const PHASES: &[&str] = &[
+    "B", "E", "X", "i", "I", "C", "b", "n", "e", "s", "t", "f", "M", "N", "O", "D", "c", "P",
+];
+
+/// `ph` as one of `PHASES`, if it's one.
+pub(crate) fn known_phase(ph: &str) -> Option<&'static str> {
+    PHASES.iter().find(|&&known| known == ph).cloned()
+}
+
+/// The id events from this process are written with. There's no process
+/// id to be had on wasm32, where it's always 1.
+pub(crate) fn pid() -> u32 {
+    #[cfg(not(target_arch = "wasm32"))]
+    return process::id();
+    #[cfg(target_arch = "wasm32")]
+    return 1;
+}
+
+/// The id events from the current thread are written with. On wasm32,
+/// where thread-id doesn't build, threads are numbered from 1 as they
+/// first record something.
+pub(crate) fn tid() -> u64 {
+    #[cfg(not(target_arch = "wasm32"))]
+    return thread_id::get() as u64;
+    #[cfg(target_arch = "wasm32")]
+    {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        thread_local!(static TID: u64 = NEXT.fetch_add(1, Ordering::Relaxed));
+        TID.with(|&tid| tid)
+    }
+}
+
+/// A single record in the Trace Event Format, as handed to each sink.
+#[derive(Clone, Debug)]
+pub struct TraceEvent {
+    /// Borrowed when it's a callsite's or span's own name.
+    pub name: Cow<'static, str>,
+    pub cat: Cow<'static, str>,
+    pub ph: &'static str,
+    pub ts: u64,
+    pub dur: Option<u64>,
+    pub pid: u32,
+    pub tid: u64,
+    pub id: Option<u64>,
+    pub scope: Option<&'static str>,
+    /// One of the viewer's reserved color names.
+    pub cname: Option<String>,
+    pub args: Map<String, Value>,
+    /// The target of the callsite that produced this event, if any. Not
+    /// part of the JSON output; `cat` covers it there.
+    pub target: &'static str,
+    pub level: Option<&'static str>,
+    /// The span the event was recorded in.
+    pub span: Option<u64>,
+}
+
+impl TraceEvent {
+    pub fn new<N, C>(ph: &'static str, name: N, cat: C, ts: u64) -> Self
+    where
+        N: Into<Cow<'static, str>>,
+        C: Into<Cow<'static, str>>,
+    {
+        TraceEvent {
+            name: name.into(),
+            cat: cat.into(),
+            ph,
+            ts,
+            dur: None,
+            pid: pid(),
+            tid: tid(),
+            id: None,
+            scope: None,
+            cname: None,
+            args: Map::new(),
+            target: "",
+            level: None,
+            span: None,
+        }
+    }
+
+    /// A `ph: "M"` metadata record such as `process_name` or `thread_name`.
+    pub fn metadata(name: &str, args: Map<String, Value>) -> Self {
+        let mut event = TraceEvent::new("M", name.to_owned(), String::new(), 0);
+        event.args = args;
+        event
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut obj = Map::new();
+        obj.insert("name".to_owned(), Value::from(&*self.name));
+        if !self.cat.is_empty() {
+            obj.insert("cat".to_owned(), Value::from(&*self.cat));
+        }
+        obj.insert("ph".to_owned(), Value::from(self.ph));
+        if self.ph != "M" {
+            obj.insert("ts".to_owned(), Value::from(self.ts));
+        }
+        if let Some(dur) = self.dur {
+            obj.insert("dur".to_owned(), Value::from(dur));
+        }
+        if let Some(id) = self.id {
+            obj.insert("id".to_owned(), Value::from(id));
+        }
+        if let Some(scope) = self.scope {
+            obj.insert("s".to_owned(), Value::from(scope));
+        }
+        if let Some(ref cname) = self.cname {
+            obj.insert("cname".to_owned(), Value::from(cname.as_str()));
+        }
+        obj.insert("pid".to_owned(), Value::from(self.pid));
+        obj.insert("tid".to_owned(), Value::from(self.tid));
+        obj.insert("args".to_owned(), Value::Object(self.args.clone()));
+        Value::Object(obj)
+    }
+
+    /// Writes the same JSON as `to_json`, keys in the same order, straight
+    /// to `out` without copying the args into a temporary `Value`. With
+    /// `ascii`, everything outside ASCII is `\u`-escaped.
+    pub(crate) fn write_json(&self, out: &mut Vec<u8>, ascii: bool) {
+        out.extend_from_slice(b"{\"args\":");
+        json::write_map(out, &self.args, ascii);
+        if !self.cat.is_empty() {
+            json::write_field(out, "cat", &self.cat, ascii);
+        }
+        if let Some(ref cname) = self.cname {
+            json::write_field(out, "cname", cname, ascii);
+        }
+        if let Some(dur) = self.dur {
+            json::write_u64(out, "dur", dur);
+        }
+        if let Some(id) = self.id {
+            json::write_u64(out, "id", id);
+        }
+        json::write_field(out, "name", &self.name, ascii);
+        json::write_field(out, "ph", self.ph, ascii);
+        json::write_u64(out, "pid", u64::from(self.pid));
+        if let Some(scope) = self.scope {
+            json::write_field(out, "s", scope, ascii);
+        }
+        json::write_u64(out, "tid", self.tid);
+        if self.ph != "M" {
+            json::write_u64(out, "ts", self.ts);
+        }
+        out.push(b'}');
+    }
+
+    /// Reads back an event written by `to_json`, or `None` if it isn't
+    /// one: a phase this crate doesn't write, or a missing `name` or `ph`.
+    #[cfg(all(unix, feature = "serde_json"))]
+    pub(crate) fn from_json(value: &Value) -> Option<Self> {
+        let name = value.get("name")?.as_str()?;
+        let ph = value.get("ph")?.as_str()?;
+        let ph = known_phase(ph)?;
+        let str_field = |key| value.get(key).and_then(Value::as_str);
+        let u64_field = |key| value.get(key).and_then(Value::as_u64);
+        let cat = str_field("cat").unwrap_or("").to_owned();
+        let mut event = TraceEvent::new(ph, name.to_owned(), cat, 0);
+        event.ts = u64_field("ts").unwrap_or(0);
+        event.dur = u64_field("dur");
+        event.id = u64_field("id");
+        event.scope = match str_field("s") {
+            Some("g") => Some("g"),
+            Some("p") => Some("p"),
+            Some("t") => Some("t"),
+            _ => None,
+        };
+        event.cname = str_field("cname").map(str::to_owned);
+        event.pid = u64_field("pid").unwrap_or(0) as u32;
+        event.tid = u64_field("tid").unwrap_or(0);
+        if let Some(Value::Object(args)) = value.get("args") {
+            event.args = args.clone();
+        }
+        Some(event)
+    }
+}
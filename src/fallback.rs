@@ -0,0 +1,114 @@
+use std::io;
+
+use crate::{ClosedSpan, TraceEvent, TraceSink};
+
+/// The OS errors for a full or read-only destination.
+#[cfg(unix)]
+const UNUSABLE: &[i32] = &[libc::ENOSPC, libc::EROFS];
+/// `ERROR_WRITE_PROTECT`, `ERROR_HANDLE_DISK_FULL` and `ERROR_DISK_FULL`.
+#[cfg(windows)]
+const UNUSABLE: &[i32] = &[19, 39, 112];
+#[cfg(not(any(unix, windows)))]
+const UNUSABLE: &[i32] = &[];
+
+/// Whether `e` means the destination won't take any more writes, however
+/// often they're retried.
+fn unusable(e: &io::Error) -> bool {
+    match e.raw_os_error() {
+        Some(code) => UNUSABLE.contains(&code),
+        None => false,
+    }
+}
+
+/// Wraps each configured sink so that once its destination turns out to be
+/// read-only or full, everything after is discarded (and counted) rather
+/// than failing again on every event.
+pub(crate) struct Fallback {
+    sink: Box<dyn TraceSink>,
+    /// Events discarded since the sink was switched off.
+    discarded: Option<usize>,
+}
+
+impl Fallback {
+    pub fn new(sink: Box<dyn TraceSink>) -> Self {
+        Fallback { sink, discarded: None }
+    }
+
+    fn guard(&mut self, result: io::Result<()>) -> io::Result<()> {
+        match result {
+            Err(ref e) if unusable(e) => {
+                self.discarded = Some(0);
+                Err(io::Error::new(
+                    e.kind(),
+                    format!("{}; discarding the rest of the trace for this sink", e),
+                ))
+            }
+            result => result,
+        }
+    }
+}
+
+impl TraceSink for Fallback {
+    fn start(&mut self) -> io::Result<()> {
+        let result = self.sink.start();
+        self.guard(result)
+    }
+
+    fn write_event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        if let Some(ref mut discarded) = self.discarded {
+            *discarded += 1;
+            return Ok(());
+        }
+        let result = self.sink.write_event(event);
+        self.guard(result)
+    }
+
+    fn system_trace(&mut self, text: &str) -> io::Result<()> {
+        if self.discarded.is_some() {
+            return Ok(());
+        }
+        let result = self.sink.system_trace(text);
+        self.guard(result)
+    }
+
+    fn wants_spans(&self) -> bool {
+        self.sink.wants_spans()
+    }
+
+    fn span_closed(&mut self, span: &ClosedSpan) -> io::Result<()> {
+        if self.discarded.is_some() {
+            return Ok(());
+        }
+        let result = self.sink.span_closed(span);
+        self.guard(result)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.discarded.is_some() {
+            return Ok(());
+        }
+        let result = self.sink.flush();
+        self.guard(result)
+    }
+
+    fn checkpoint(&mut self) -> io::Result<()> {
+        if self.discarded.is_some() {
+            return Ok(());
+        }
+        let result = self.sink.checkpoint();
+        self.guard(result)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.discarded {
+            Some(discarded) => Err(io::Error::other(format!(
+                "discarded {} events after the destination became unusable",
+                discarded
+            ))),
+            None => {
+                let result = self.sink.finish();
+                self.guard(result)
+            }
+        }
+    }
+}
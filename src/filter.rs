@@ -0,0 +1,172 @@
+use std::cmp::Reverse;
+use tracing_core::{Level, Metadata};
+
+use crate::{target_matches, verbosity};
+
+/// The environment variable `ChromeTraceBuilder::filter_from_env` reads.
+pub const FILTER_VAR: &str = "CHROME_TRACE_FILTER";
+
+/// Which targets are recorded at which levels, parsed from directives like
+/// `my_crate=debug,hyper=warn,info`. A directive names a target (covering the
+/// modules under it) and the most verbose level to record there, or `off`;
+/// a bare level applies to everything else. The longest matching target
+/// wins. A bare word that looks like a misspelled level, like `inof`, is
+/// rejected rather than taken for a target.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Filter {
+    spec: String,
+    /// `None` levels are `off`.
+    directives: Vec<(String, Option<Level>)>,
+    default: Option<Option<Level>>,
+}
+
+impl Filter {
+    /// Parses `spec`, skipping directives it doesn't understand.
+    pub fn parse(spec: &str) -> Self {
+        let mut filter = Filter {
+            spec: spec.to_owned(),
+            ..Filter::default()
+        };
+        for directive in directives(spec) {
+            match parse_directive(directive) {
+                Some((Some(target), level)) => filter.directives.push((target.to_owned(), level)),
+                Some((None, level)) => filter.default = Some(level),
+                None => {}
+            }
+        }
+        // Longest first, so the first match is the most specific.
+        filter.directives.sort_by_key(|directive| Reverse(directive.0.len()));
+        filter
+    }
+
+    /// The first directive in `spec` that `parse` would skip, if any.
+    pub fn check(spec: &str) -> Result<(), String> {
+        match directives(spec).find(|directive| parse_directive(directive).is_none()) {
+            Some(directive) => Err(directive.to_owned()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn spec(&self) -> &str {
+        &self.spec
+    }
+
+    pub fn enabled(&self, meta: &Metadata) -> bool {
+        self.allows(meta.target(), meta.level())
+    }
+
+    fn allows(&self, target: &str, level: &Level) -> bool {
+        let max = self
+            .directives
+            .iter()
+            .find(|(prefix, _)| target_matches(target, prefix))
+            .map(|(_, max)| max)
+            .or(self.default.as_ref());
+        match max {
+            Some(Some(max)) => verbosity(level) <= verbosity(max),
+            Some(None) => false,
+            // With no default, only the targets named are recorded.
+            None => self.default.is_none() && self.directives.is_empty(),
+        }
+    }
+}
+
+fn directives(spec: &str) -> impl Iterator<Item = &str> {
+    spec.split(',').map(str::trim).filter(|d| !d.is_empty())
+}
+
+/// The target (`None` for the default) and level `directive` sets, or
+/// `None` if it doesn't parse.
+fn parse_directive(directive: &str) -> Option<(Option<&str>, Option<Level>)> {
+    match directive.find('=') {
+        Some(eq) => {
+            let target = directive[..eq].trim();
+            if !is_target(target) {
+                return None;
+            }
+            Some((Some(target), parse_level(&directive[eq + 1..])?))
+        }
+        None => match parse_level(directive) {
+            Some(level) => Some((None, level)),
+            // A bare target records everything under it. A misspelled level
+            // would otherwise be taken for one and turn every other target
+            // off.
+            None if is_target(directive) && !like_level(directive) => {
+                Some((Some(directive), Some(Level::TRACE)))
+            }
+            None => None,
+        },
+    }
+}
+
+/// Whether `s` looks like a target: a module path such as `my_crate::db`.
+fn is_target(s: &str) -> bool {
+    let word_char = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    s.split("::").all(|word| !word.is_empty() && word.chars().all(word_char))
+}
+
+/// Whether `s` looks like a typo for a level: its letters rearranged, or
+/// cut short, like `inof` or `debu`.
+fn like_level(s: &str) -> bool {
+    let s = s.to_ascii_lowercase();
+    let sorted = |w: &str| {
+        let mut letters: Vec<char> = w.chars().collect();
+        letters.sort_unstable();
+        letters
+    };
+    ["error", "warn", "info", "debug", "trace"].iter().any(|level| {
+        sorted(level) == sorted(&s) || (s.len() >= 3 && level.starts_with(s.as_str()))
+    })
+}
+
+/// `Some(None)` is `off`.
+fn parse_level(s: &str) -> Option<Option<Level>> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "off" => Some(None),
+        "error" => Some(Some(Level::ERROR)),
+        "warn" => Some(Some(Level::WARN)),
+        "info" => Some(Some(Level::INFO)),
+        "debug" => Some(Some(Level::DEBUG)),
+        "trace" => Some(Some(Level::TRACE)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_and_default() {
+        let filter = Filter::parse("my_crate=debug, my_crate::db=off ,warn");
+        assert!(filter.allows("my_crate", &Level::DEBUG));
+        assert!(!filter.allows("my_crate", &Level::TRACE));
+        assert!(filter.allows("my_crate::http", &Level::DEBUG));
+        assert!(!filter.allows("my_crate::db", &Level::ERROR));
+        assert!(filter.allows("my_crate::dbx", &Level::DEBUG));
+        assert!(filter.allows("hyper", &Level::WARN));
+        assert!(!filter.allows("hyper", &Level::INFO));
+    }
+
+    #[test]
+    fn bare_target_without_default() {
+        let filter = Filter::parse("my_crate");
+        assert!(filter.allows("my_crate::db", &Level::TRACE));
+        assert!(!filter.allows("hyper", &Level::ERROR));
+    }
+
+    #[test]
+    fn empty_spec_records_everything() {
+        assert!(Filter::parse("").allows("hyper", &Level::TRACE));
+    }
+
+    #[test]
+    fn misspelled_levels_are_rejected() {
+        for spec in &["inof", "debgu", "deb", "hyper=loud", "=info", "my crate"] {
+            assert_eq!(Filter::check(spec), Err((*spec).to_owned()), "{}", spec);
+        }
+        assert_eq!(Filter::check("my-app::db=info,warp,TRACE"), Ok(()));
+        // A typo is skipped rather than turning everything else off.
+        assert!(Filter::parse("inof").allows("hyper", &Level::INFO));
+    }
+}
@@ -0,0 +1,97 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+
+use crate::json::{Map, Value};
+use crate::TraceEvent;
+
+/// What to do with events from threads Rust didn't start, like a C
+/// library's workers, see `ChromeTraceBuilder::foreign_threads`.
+///
+/// A thread counts as foreign if it has no name, isn't `main` and hasn't
+/// joined a thread pool by the time it first records something. Unnamed
+/// threads spawned from Rust look the same, so name them to keep them apart.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ForeignThreads {
+    /// Leave them on tracks of their own, under their raw thread ids.
+    #[default]
+    Keep,
+    /// Draw all of them on one `foreign threads` track.
+    Group,
+    /// Give each a small id of its own and a `foreign thread <n>` name.
+    Remap,
+}
+
+/// Foreign thread tids start here, clear of real thread ids and of task
+/// lanes.
+const FOREIGN_BASE: u64 = 1 << 49;
+
+thread_local! {
+    // The subscriber instance this thread was last looked at for, and the
+    // tid it's drawn under there, or 0 if it isn't foreign.
+    static TRACK: Cell<(usize, u64)> = const { Cell::new((0, 0)) };
+}
+
+pub(crate) struct Foreign {
+    policy: ForeignThreads,
+    /// The last tid `Remap` handed out.
+    next: AtomicU64,
+    /// Whether the `Group` track has been named.
+    named: AtomicBool,
+}
+
+impl Foreign {
+    pub fn new(policy: ForeignThreads) -> Option<Self> {
+        if policy == ForeignThreads::Keep {
+            return None;
+        }
+        Some(Foreign {
+            policy,
+            next: AtomicU64::new(FOREIGN_BASE),
+            named: AtomicBool::new(false),
+        })
+    }
+
+    /// The tid to draw the current thread under if it's foreign, and the
+    /// records naming that track if they haven't been written yet.
+    pub fn track(&self, instance: usize, worker: bool) -> Option<(u64, Vec<TraceEvent>)> {
+        let (seen_for, tid) = TRACK.with(Cell::get);
+        if seen_for == instance {
+            return if tid == 0 { None } else { Some((tid, Vec::new())) };
+        }
+        let foreign = thread::current().name().is_none() && !worker;
+        let (tid, name) = match self.policy {
+            _ if !foreign => (0, None),
+            ForeignThreads::Group => {
+                let first = !self.named.swap(true, Ordering::Relaxed);
+                (FOREIGN_BASE, if first { Some("foreign threads".to_owned()) } else { None })
+            }
+            _ => {
+                let tid = self.next.fetch_add(1, Ordering::Relaxed) + 1;
+                (tid, Some(format!("foreign thread {}", tid - FOREIGN_BASE)))
+            }
+        };
+        TRACK.with(|track| track.set((instance, tid)));
+        if tid == 0 {
+            return None;
+        }
+        let records = match name {
+            Some(name) => naming(tid, name),
+            None => Vec::new(),
+        };
+        Some((tid, records))
+    }
+}
+
+/// Names track `tid` and sorts it after the threads that have names.
+fn naming(tid: u64, name: String) -> Vec<TraceEvent> {
+    let mut args = Map::new();
+    args.insert("name".to_owned(), Value::from(name));
+    let mut thread_name = TraceEvent::metadata("thread_name", args);
+    thread_name.tid = tid;
+    let mut args = Map::new();
+    args.insert("sort_index".to_owned(), Value::from(1000));
+    let mut sort = TraceEvent::metadata("thread_sort_index", args);
+    sort.tid = tid;
+    vec![thread_name, sort]
+}
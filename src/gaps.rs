@@ -0,0 +1,73 @@
+use crate::json::Value;
+use crate::TraceEvent;
+
+/// Name of the instant event marking where an idle gap was cut out.
+pub const GAP_EVENT: &str = "gap elided";
+
+/// Cuts long stretches with no events out of the timeline, see
+/// `ChromeTraceBuilder::elide_idle_gaps`. Runs on the writer thread, before
+/// the time base offset is added.
+pub(crate) struct GapElider {
+    /// Gaps longer than this are shortened to this.
+    threshold: u64,
+    /// The gaps cut out so far, as (start, length) in recorded time, in
+    /// order.
+    gaps: Vec<(u64, u64)>,
+    /// The latest time any event reached, start or end.
+    latest: u64,
+}
+
+impl GapElider {
+    pub fn new(threshold: u64) -> Self {
+        GapElider {
+            threshold,
+            gaps: Vec::new(),
+            latest: 0,
+        }
+    }
+
+    /// Notes `event`, returning a marker to write first if it comes after
+    /// a long gap.
+    pub fn observe(&mut self, event: &TraceEvent) -> Option<TraceEvent> {
+        if event.ph == "M" {
+            return None;
+        }
+        let end = event.ts + event.dur.unwrap_or(0);
+        let mut marker = None;
+        if self.latest > 0 && event.ts > self.latest + self.threshold {
+            let start = self.latest + self.threshold;
+            let len = event.ts - start;
+            self.gaps.push((start, len));
+            let mut event = TraceEvent::new("I", GAP_EVENT.to_owned(), "chrome_trace".to_owned(), start);
+            event.scope = Some("g");
+            event.args.insert("elided_us".to_owned(), Value::from(len));
+            marker = Some(event);
+        }
+        self.latest = self.latest.max(end);
+        marker
+    }
+
+    /// Moves `event` back by the gaps cut out before it, shortening slices
+    /// that span one.
+    pub fn apply(&self, event: &mut TraceEvent) {
+        if event.ph == "M" || self.gaps.is_empty() {
+            return;
+        }
+        let start = self.map(event.ts);
+        if let Some(dur) = event.dur {
+            event.dur = Some(self.map(event.ts + dur) - start);
+        }
+        event.ts = start;
+    }
+
+    fn map(&self, ts: u64) -> u64 {
+        let mut cut = 0;
+        for &(start, len) in &self.gaps {
+            if ts <= start {
+                break;
+            }
+            cut += len.min(ts - start);
+        }
+        ts - cut
+    }
+}
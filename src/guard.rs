@@ -0,0 +1,176 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread::JoinHandle;
+
+use crate::channel::Tx;
+use crate::stats::Stats;
+#[cfg(target_arch = "wasm32")]
+use crate::writer::Inline;
+use crate::Message;
+
+/// Why a trace ended, recorded as `reason` in the `chrome_trace_summary`
+/// event so a short trace explains itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// `finish` was called.
+    Finish,
+    /// The subscriber was dropped.
+    Drop,
+    /// A `FlushGuard` was dropped.
+    Guard,
+    /// The subscriber or guard was dropped while unwinding from a panic.
+    Panic,
+    /// The application caught a signal, see `finish_because`.
+    Signal,
+}
+
+impl ShutdownReason {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            ShutdownReason::Finish => "finish",
+            ShutdownReason::Drop => "drop",
+            ShutdownReason::Guard => "guard",
+            ShutdownReason::Panic => "panic",
+            ShutdownReason::Signal => "signal",
+        }
+    }
+
+    /// `reason`, unless the current thread is panicking.
+    pub(crate) fn unless_panicking(reason: ShutdownReason) -> Self {
+        if thread::panicking() {
+            ShutdownReason::Panic
+        } else {
+            reason
+        }
+    }
+}
+
+/// Produces the records written at the very end of the trace.
+pub(crate) type Closing = Box<dyn Fn(ShutdownReason) -> Vec<Message> + Send + Sync>;
+
+/// The writer once it's started: its thread, or on wasm32 the writer that
+/// messages are handed to as they're sent.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) type Running = JoinHandle<()>;
+#[cfg(target_arch = "wasm32")]
+pub(crate) type Running = Arc<Inline>;
+
+/// Starts the writer.
+pub(crate) type Start = Box<dyn FnOnce() -> Running + Send>;
+
+/// Runs once the writer thread has exited and the trace is complete.
+pub(crate) type Finished = Box<dyn FnOnce() + Send>;
+
+/// What's needed to drain the channel and stop the writer thread, shared by
+/// the subscriber and its `FlushGuard`s.
+pub(crate) struct Shutdown {
+    tx: Arc<Mutex<Tx>>,
+    stats: Arc<Stats>,
+    writer: Mutex<Option<Running>>,
+    /// The writer thread, until it's started, see
+    /// `ChromeTraceBuilder::lazy`.
+    start: Mutex<Option<Start>>,
+    started: AtomicBool,
+    closing: Closing,
+    finished: Mutex<Option<Finished>>,
+}
+
+impl Shutdown {
+    pub fn new(tx: Arc<Mutex<Tx>>, stats: Arc<Stats>, closing: Closing) -> Self {
+        Shutdown {
+            tx,
+            stats,
+            writer: Mutex::new(None),
+            start: Mutex::new(None),
+            started: AtomicBool::new(false),
+            closing,
+            finished: Mutex::new(None),
+        }
+    }
+
+    /// Has the next call to `start` spawn the writer thread with `start`.
+    pub fn arm(&self, start: Start) {
+        *self.start.lock().unwrap() = Some(start);
+    }
+
+    pub fn on_finished(&self, finished: Finished) {
+        *self.finished.lock().unwrap() = Some(finished);
+    }
+
+    /// Spawns the writer thread, if it's armed and not running yet.
+    pub fn start(&self) {
+        if self.started.load(Ordering::Acquire) {
+            return;
+        }
+        let mut start = self.start.lock().unwrap();
+        if let Some(start) = start.take() {
+            *self.writer.lock().unwrap() = Some(start());
+            self.started.store(true, Ordering::Release);
+        }
+    }
+
+    /// Blocks until everything sent so far has been handed to the sinks and
+    /// flushed.
+    pub fn flush(&self) {
+        if !self.started.load(Ordering::Acquire) {
+            return;
+        }
+        let (ack, flushed) = mpsc::channel();
+        if self.stats.send(&self.tx.lock().unwrap(), Message::Flush(ack)) {
+            let _ = flushed.recv();
+        }
+    }
+
+    /// Writes the closing records, finishes every sink and waits for the
+    /// writer thread to exit. Only the first call does anything. A writer
+    /// thread that never started isn't started now: nothing is written.
+    pub fn finish(&self, reason: ShutdownReason) {
+        let start = self.start.lock().unwrap().take();
+        drop(start);
+        let writer = self.writer.lock().unwrap().take();
+        if let Some(writer) = writer {
+            let tx = self.tx.lock().unwrap();
+            for msg in (self.closing)(reason) {
+                self.stats.send(&tx, msg);
+            }
+            self.stats.send(&tx, Message::Done);
+            drop(tx);
+            wait(writer);
+            if let Some(finished) = self.finished.lock().unwrap().take() {
+                finished();
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn wait(writer: Running) {
+    let _ = writer.join();
+}
+
+// Everything was written as it was sent.
+#[cfg(target_arch = "wasm32")]
+fn wait(_: Running) {}
+
+/// Finishes the trace when dropped, for when the subscriber itself never is,
+/// e.g. because it was installed as the global default. Keep it alive until
+/// the end of `main`.
+pub struct FlushGuard {
+    pub(crate) shutdown: Arc<Shutdown>,
+}
+
+impl FlushGuard {
+    /// Blocks until everything recorded so far has been written out.
+    pub fn flush(&self) {
+        self.shutdown.flush()
+    }
+}
+
+impl Drop for FlushGuard {
+    fn drop(&mut self) {
+        self.shutdown.finish(ShutdownReason::unless_panicking(ShutdownReason::Guard))
+    }
+}
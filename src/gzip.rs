@@ -0,0 +1,76 @@
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::clock::Instant;
+use crate::JsonSink;
+
+/// Compressed output is pushed out at most this often. Every flush ends a
+/// deflate block, so flushing each time the writer thread catches up would
+/// cost most of the compression on a quiet trace.
+const FLUSH_EVERY: Duration = Duration::from_secs(1);
+
+/// Gzips everything written through it, for `.json.gz` traces that both
+/// chrome://tracing and Perfetto load directly.
+///
+/// A `JsonSink` made with `JsonSink::gzip` writes the gzip trailer when the
+/// trace finishes; otherwise call `finish`, or it's written when the writer
+/// is dropped, ignoring any error.
+pub struct GzipWriter<W: Write> {
+    inner: GzEncoder<W>,
+    flushed: Instant,
+}
+
+impl<W: Write> GzipWriter<W> {
+    pub fn new(output: W) -> Self {
+        GzipWriter {
+            inner: GzEncoder::new(output, Compression::default()),
+            flushed: Instant::now(),
+        }
+    }
+
+    /// Writes out the rest of the compressed stream and the gzip trailer.
+    /// Nothing more may be written after.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.inner.try_finish()?;
+        self.inner.get_mut().flush()
+    }
+}
+
+impl<W: Write> Write for GzipWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.flushed.elapsed() < FLUSH_EVERY {
+            return Ok(());
+        }
+        self.flushed = Instant::now();
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for GzipWriter<W> {
+    fn drop(&mut self) {
+        drop(self.inner.try_finish());
+    }
+}
+
+impl<W: Write> JsonSink<GzipWriter<W>> {
+    /// A `JsonSink` whose output is gzipped as it's written.
+    pub fn gzip(output: W) -> Self {
+        JsonSink::from_writer(GzipWriter::new(output)).ending_with(GzipWriter::finish)
+    }
+}
+
+impl JsonSink<GzipWriter<File>> {
+    /// Creates (or truncates) the file at `path` and writes a gzipped trace
+    /// there.
+    pub fn gzip_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(JsonSink::gzip(File::create(path)?))
+    }
+}
@@ -0,0 +1,83 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use tracing_core::Metadata;
+
+use crate::channel::Tx;
+use crate::clock::Instant;
+use crate::filter::Filter;
+use crate::stats::Stats;
+use crate::{in_micros, rotation, Config, Error, Message, TraceSink};
+
+/// What a `ChromeTraceHandle` changes, read by the subscriber.
+#[derive(Default)]
+pub(crate) struct Control {
+    paused: AtomicBool,
+    /// Replaces the builder's `filter` once set.
+    filter: RwLock<Option<Filter>>,
+}
+
+impl Control {
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn enabled(&self, config: &Config, meta: &Metadata) -> bool {
+        match *self.filter.read().unwrap() {
+            Some(ref filter) => config.enabled_with(meta, Some(filter)),
+            None => config.enabled(meta),
+        }
+    }
+}
+
+/// Controls a running `ChromeTraceSubscriber` from anywhere in the program,
+/// e.g. to switch tracing on in production only while diagnosing a problem.
+/// Made by `ChromeTraceBuilder::build_with_handle`; cheap to clone.
+#[derive(Clone)]
+pub struct ChromeTraceHandle {
+    pub(crate) control: Arc<Control>,
+    pub(crate) tx: Arc<Mutex<Tx>>,
+    pub(crate) stats: Arc<Stats>,
+    /// When the subscriber started, for the events the handle writes.
+    pub(crate) start: Instant,
+}
+
+impl ChromeTraceHandle {
+    /// Stop writing events and spans until `resume`. Process and thread
+    /// names are still kept, so what's captured later is labelled.
+    pub fn pause(&self) {
+        self.control.paused.store(true, Ordering::Relaxed)
+    }
+
+    pub fn resume(&self) {
+        self.control.paused.store(false, Ordering::Relaxed)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.control.paused()
+    }
+
+    /// Replace the filter with `spec`, in the syntax of
+    /// `ChromeTraceBuilder::filter`. Takes effect for every callsite from
+    /// its next use. Leaves the filter as it was if a directive in `spec`
+    /// doesn't parse, for specs typed in by an operator.
+    pub fn set_filter(&self, spec: &str) -> Result<(), Error> {
+        Filter::check(spec).map_err(Error::Filter)?;
+        *self.control.filter.write().unwrap() = Some(Filter::parse(spec));
+        Ok(())
+    }
+
+    /// Like `ChromeTraceSubscriber::attach`, e.g. to switch to a fresh
+    /// file. Routed sinks are left alone.
+    pub fn set_output<S: TraceSink + 'static>(&self, sink: S) {
+        self.stats.send(&self.tx.lock().unwrap(), Message::SetOutput(Box::new(sink), None));
+    }
+
+    /// See `ChromeTraceSubscriber::rotate`.
+    pub fn rotate<P: AsRef<Path>>(&self, new_path: P) -> io::Result<()> {
+        let rotation = rotation(new_path.as_ref(), in_micros(self.start.elapsed()))?;
+        self.stats.send(&self.tx.lock().unwrap(), rotation);
+        Ok(())
+    }
+}
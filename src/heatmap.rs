@@ -0,0 +1,76 @@
+use crate::json::Value;
+use crate::TraceEvent;
+
+/// Name of the counter `ChromeTraceBuilder::activity_track` writes.
+pub const ACTIVITY_TRACK: &str = "activity";
+
+/// Counts events written per fixed-length bucket of time, see
+/// `ChromeTraceBuilder::activity_track`. Runs on the writer thread, on
+/// events as written.
+pub(crate) struct Heatmap {
+    /// Bucket length, in microseconds.
+    bucket: u64,
+    /// The bucket being counted, by index.
+    current: Option<u64>,
+    count: u64,
+    /// The pid and tid the counter is drawn under: the first event's, as
+    /// written, so it's on a track of the trace even when pids and tids are
+    /// rewritten (e.g. by `ChromeTraceBuilder::anonymize`).
+    track: (u32, u64),
+}
+
+impl Heatmap {
+    pub fn new(bucket: u64) -> Self {
+        Heatmap {
+            bucket,
+            current: None,
+            count: 0,
+            track: (0, 0),
+        }
+    }
+
+    /// Counts `event` against the bucket it ended in, returning samples for
+    /// the buckets that are now over. Events come roughly in order: one
+    /// ending in a bucket already sampled is counted in the current one.
+    pub fn observe(&mut self, event: &TraceEvent) -> Vec<TraceEvent> {
+        if event.ph == "M" || (event.ph == "C" && event.name == ACTIVITY_TRACK) {
+            return Vec::new();
+        }
+        let at = (event.ts + event.dur.unwrap_or(0)) / self.bucket;
+        let mut samples = Vec::new();
+        match self.current {
+            Some(current) if at > current => {
+                samples.push(self.sample(current, self.count));
+                // Drop back to zero over a stretch of empty buckets, rather
+                // than drawing the last count across it.
+                if at > current + 1 {
+                    samples.push(self.sample(current + 1, 0));
+                }
+                self.current = Some(at);
+                self.count = 0;
+            }
+            Some(_) => {}
+            None => {
+                self.current = Some(at);
+                self.track = (event.pid, event.tid);
+            }
+        }
+        self.count += 1;
+        samples
+    }
+
+    /// The sample for the last bucket, once the trace is finished.
+    pub fn finish(&mut self) -> Option<TraceEvent> {
+        let current = self.current.take()?;
+        Some(self.sample(current, self.count))
+    }
+
+    fn sample(&self, index: u64, count: u64) -> TraceEvent {
+        let ts = index * self.bucket;
+        let mut event = TraceEvent::new("C", ACTIVITY_TRACK.to_owned(), "chrome_trace".to_owned(), ts);
+        event.pid = self.track.0;
+        event.tid = self.track.1;
+        event.args.insert("events".to_owned(), Value::from(count));
+        event
+    }
+}
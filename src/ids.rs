@@ -0,0 +1,45 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use tracing_core::Metadata;
+
+/// How span ids are assigned, see `ChromeTraceBuilder::span_ids`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SpanIds {
+    /// From one counter, in creation order.
+    #[default]
+    Sequential,
+    /// From the callsite (its name, target and source location) and how
+    /// many spans it has created before, so the same span gets the same id
+    /// in every run that creates spans in the same order per callsite.
+    ByCallsite,
+}
+
+/// Bits of the id given to the callsite. With 32 for the count, ids stay
+/// below 2^53 and so survive the viewer's floating point numbers.
+const CALLSITE_BITS: u32 = 21;
+
+/// Hands out `SpanIds::ByCallsite` ids. Callsites whose hashes collide
+/// share a count, so ids stay unique either way.
+#[derive(Default)]
+pub(crate) struct CallsiteIds {
+    counts: Mutex<HashMap<u64, u32>>,
+}
+
+impl CallsiteIds {
+    pub fn next(&self, meta: &Metadata) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        meta.name().hash(&mut hasher);
+        meta.target().hash(&mut hasher);
+        meta.file().hash(&mut hasher);
+        meta.line().hash(&mut hasher);
+        let callsite = hasher.finish() & ((1 << CALLSITE_BITS) - 1);
+        let mut counts = self.counts.lock().unwrap();
+        // Counts start at 1, as span ids can't be 0.
+        let count = counts.entry(callsite).or_insert(1);
+        let id = callsite << 32 | u64::from(*count);
+        *count = count.checked_add(1).unwrap_or(1);
+        id
+    }
+}
@@ -0,0 +1,59 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::json::{self, Map, Value};
+use crate::{in_micros, TraceEvent};
+
+/// Byte offsets into a sink's output by time bucket, for the sidecar index
+/// written when it finishes. See `JsonSink::index`.
+pub(crate) struct Index {
+    path: PathBuf,
+    bucket_us: u64,
+    written: u64,
+    /// The start of each bucket, and the offset of the first event written
+    /// with a timestamp in it or later.
+    buckets: Vec<(u64, u64)>,
+}
+
+impl Index {
+    pub fn new(path: PathBuf, bucket: Duration) -> Self {
+        Index {
+            path,
+            bucket_us: in_micros(bucket).max(1),
+            written: 0,
+            buckets: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.written += bytes.len() as u64;
+    }
+
+    /// Notes `event`, about to be written `skip` bytes from here.
+    pub fn event(&mut self, event: &TraceEvent, skip: u64) {
+        if event.ph == "M" {
+            return;
+        }
+        let bucket = event.ts / self.bucket_us * self.bucket_us;
+        if self.buckets.last().is_none_or(|&(start, _)| bucket > start) {
+            self.buckets.push((bucket, self.written + skip));
+        }
+    }
+
+    pub fn write(self) -> io::Result<()> {
+        let mut index = Map::new();
+        index.insert("bucket_us".to_owned(), Value::from(self.bucket_us));
+        index.insert("size".to_owned(), Value::from(self.written));
+        let buckets = self
+            .buckets
+            .into_iter()
+            .map(|(start, offset)| Value::from(vec![start, offset]))
+            .collect();
+        index.insert("buckets".to_owned(), Value::Array(buckets));
+        let mut file = File::create(&self.path)?;
+        file.write_all(&json::to_vec(&Value::Object(index)))?;
+        Ok(())
+    }
+}
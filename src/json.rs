@@ -0,0 +1,178 @@
+#[cfg(feature = "serde_json")]
+pub use serde_json::{Map, Number, Value};
+#[cfg(not(feature = "serde_json"))]
+pub use crate::value::{Map, Number, Value};
+
+/// Compact JSON for `value`, without going through serde.
+pub(crate) fn to_vec(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_value(&mut out, value, false);
+    out
+}
+
+pub(crate) fn map_to_string(map: &Map<String, Value>) -> String {
+    let mut out = Vec::new();
+    write_map(&mut out, map, false);
+    String::from_utf8(out).unwrap()
+}
+
+/// Writes `value` as compact JSON, the same bytes `serde_json::to_vec`
+/// would produce unless `ascii` asks for everything outside ASCII to be
+/// `\u`-escaped.
+pub(crate) fn write_value(out: &mut Vec<u8>, value: &Value, ascii: bool) {
+    match *value {
+        Value::Null => out.extend_from_slice(b"null"),
+        Value::Bool(true) => out.extend_from_slice(b"true"),
+        Value::Bool(false) => out.extend_from_slice(b"false"),
+        Value::Number(ref number) => out.extend_from_slice(number.to_string().as_bytes()),
+        Value::String(ref s) => write_str(out, s, ascii),
+        Value::Array(ref values) => {
+            out.push(b'[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_value(out, value, ascii);
+            }
+            out.push(b']');
+        }
+        Value::Object(ref map) => write_map(out, map, ascii),
+    }
+}
+
+pub(crate) fn write_map(out: &mut Vec<u8>, map: &Map<String, Value>, ascii: bool) {
+    out.push(b'{');
+    for (i, (key, value)) in map.iter().enumerate() {
+        if i > 0 {
+            out.push(b',');
+        }
+        write_str(out, key, ascii);
+        out.push(b':');
+        write_value(out, value, ascii);
+    }
+    out.push(b'}');
+}
+
+pub(crate) fn write_str(out: &mut Vec<u8>, s: &str, ascii: bool) {
+    out.push(b'"');
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        let escape: &[u8] = match c {
+            '"' => b"\\\"",
+            '\\' => b"\\\\",
+            '\n' => b"\\n",
+            '\r' => b"\\r",
+            '\t' => b"\\t",
+            '\u{8}' => b"\\b",
+            '\u{c}' => b"\\f",
+            '\u{0}'..='\u{1f}' => b"",
+            _ if ascii && !c.is_ascii() => b"",
+            _ => continue,
+        };
+        out.extend_from_slice(&s.as_bytes()[start..i]);
+        if escape.is_empty() {
+            let mut units = [0; 2];
+            for unit in c.encode_utf16(&mut units).iter() {
+                out.extend_from_slice(format!("\\u{:04x}", unit).as_bytes());
+            }
+        } else {
+            out.extend_from_slice(escape);
+        }
+        start = i + c.len_utf8();
+    }
+    out.extend_from_slice(&s.as_bytes()[start..]);
+    out.push(b'"');
+}
+
+/// Writes `"key":value` for a number, preceded by a comma.
+pub(crate) fn write_u64(out: &mut Vec<u8>, key: &str, value: u64) {
+    out.push(b',');
+    write_str(out, key, false);
+    out.push(b':');
+    out.extend_from_slice(value.to_string().as_bytes());
+}
+
+/// Writes `"key":"value"`, preceded by a comma.
+pub(crate) fn write_field(out: &mut Vec<u8>, key: &str, value: &str, ascii: bool) {
+    out.push(b',');
+    write_str(out, key, false);
+    out.push(b':');
+    write_str(out, value, ascii);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(value: &Value, ascii: bool) -> String {
+        let mut out = Vec::new();
+        write_value(&mut out, value, ascii);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[cfg(feature = "serde_json")]
+    fn serde(value: &Value) -> String {
+        String::from_utf8(serde_json::to_vec(value).unwrap()).unwrap()
+    }
+
+    // The stand-in types can't be handed to serde_json, so check the
+    // hand-written form is what serde_json writes for it once parsed.
+    #[cfg(not(feature = "serde_json"))]
+    fn serde(value: &Value) -> String {
+        let parsed: serde_json::Value = serde_json::from_str(&encoded(value, false)).unwrap();
+        serde_json::to_string(&parsed).unwrap()
+    }
+
+    fn sample() -> Value {
+        let control: String = (0..0x20u8).map(char::from).chain("\"\\\u{7f}".chars()).collect();
+        let mut map = Map::new();
+        map.insert("control".to_owned(), Value::from(control));
+        map.insert("non-ascii é \u{2028}".to_owned(), Value::from("naïve 日本 😀"));
+        let numbers = vec![
+            Value::from(0),
+            Value::from(-1),
+            Value::from(u64::MAX),
+            Value::from(i64::MIN),
+            Value::from(0.1),
+            Value::from(-2.5e-8),
+            Value::from(1e300),
+            Value::from(3.0),
+            Value::from(1e15),
+            Value::from(1e16),
+            Value::from(1e21),
+            Value::from(1e-5),
+            Value::from(1e-7),
+            Value::from(123456.789),
+            Value::from(-0.0),
+            Value::from(0.000123),
+            Value::from(f64::MAX),
+            Value::from(f64::MIN_POSITIVE),
+        ];
+        map.insert("numbers".to_owned(), Value::Array(numbers));
+        let nested = vec![Value::Null, Value::Bool(true), Value::Object(Map::new())];
+        map.insert("nested".to_owned(), Value::Array(nested));
+        Value::Object(map)
+    }
+
+    #[test]
+    fn matches_serde_json() {
+        let value = sample();
+        assert_eq!(encoded(&value, false), serde(&value));
+        for s in &["", "plain", "\u{0}\u{1f}", "tab\there", "日本 😀"] {
+            let mut out = Vec::new();
+            write_str(&mut out, s, false);
+            assert_eq!(String::from_utf8(out).unwrap(), serde(&Value::from(*s)));
+        }
+    }
+
+    #[test]
+    fn ascii_escapes_everything_else() {
+        let value = Value::from("é\u{1}😀");
+        assert_eq!(encoded(&value, true), r#""\u00e9\u0001\ud83d\ude00""#);
+        let value = sample();
+        let ascii = encoded(&value, true);
+        assert!(ascii.is_ascii());
+        let parsed: serde_json::Value = serde_json::from_str(&ascii).unwrap();
+        assert_eq!(parsed, serde_json::from_str::<serde_json::Value>(&serde(&value)).unwrap());
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+use crate::json::{Map, Value};
+use crate::TraceEvent;
+
+/// What groups spans and events into one lane of the viewer, see
+/// `ChromeTraceBuilder::task_lanes`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaskLanes {
+    /// Everything under the same outermost span.
+    RootSpan,
+    /// Everything under the nearest span with this field, keyed by its
+    /// value, e.g. `Field("task_id")`.
+    Field(&'static str),
+}
+
+/// Lane tids start here, well clear of real thread ids but below 2^53.
+const LANE_BASE: u64 = 1 << 50;
+
+/// A span's lane: its key and the name it's shown under.
+#[derive(Clone, Debug)]
+pub(crate) struct Lane {
+    pub key: u64,
+    pub name: String,
+    /// Whether the lane started at this span, rather than an ancestor.
+    pub owner: bool,
+}
+
+impl Lane {
+    /// The lane started by a span with field `field` set to `value`.
+    pub fn from_field(field: &str, value: &Value) -> Self {
+        let (key, shown) = match *value {
+            Value::Number(ref n) if n.is_u64() => (n.as_u64().unwrap_or(0), n.to_string()),
+            Value::String(ref s) => (hash(s), s.clone()),
+            ref other => (hash(&other.to_string()), other.to_string()),
+        };
+        Lane {
+            key,
+            name: format!("{}={}", field, shown),
+            owner: true,
+        }
+    }
+}
+
+fn hash(value: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Default)]
+struct Tids {
+    by_key: HashMap<u64, u64>,
+    next: u64,
+}
+
+/// The lanes in use, each with a made-up thread id, by lane key.
+#[derive(Default)]
+pub(crate) struct Lanes {
+    tids: Mutex<Tids>,
+}
+
+impl Lanes {
+    /// The tid for `lane`, and the records naming it if it's new.
+    pub fn tid(&self, lane: &Lane) -> (u64, Option<[TraceEvent; 2]>) {
+        let mut tids = self.tids.lock().unwrap();
+        if let Some(&tid) = tids.by_key.get(&lane.key) {
+            return (tid, None);
+        }
+        tids.next += 1;
+        let tid = LANE_BASE + tids.next;
+        tids.by_key.insert(lane.key, tid);
+        let name = lane.name.as_str();
+        let mut args = Map::new();
+        args.insert("name".to_owned(), Value::from(name));
+        let mut thread_name = TraceEvent::metadata("thread_name", args);
+        thread_name.tid = tid;
+        // Below the real threads.
+        let mut args = Map::new();
+        args.insert("sort_index".to_owned(), Value::from(1));
+        let mut sort = TraceEvent::metadata("thread_sort_index", args);
+        sort.tid = tid;
+        (tid, Some([thread_name, sort]))
+    }
+
+    /// Forgets lane `key` once the span that started it has closed.
+    pub fn release(&self, key: u64) {
+        self.tids.lock().unwrap().by_key.remove(&key);
+    }
+}
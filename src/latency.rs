@@ -0,0 +1,39 @@
+use hdrhistogram::Histogram;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::in_micros;
+use crate::json::{Map, Value};
+
+/// Streaming histograms of span lifetimes (creation to close), by name.
+#[derive(Default)]
+pub(crate) struct Latencies {
+    names: Mutex<HashMap<&'static str, Histogram<u64>>>,
+}
+
+impl Latencies {
+    pub fn record(&self, name: &'static str, lifetime: Duration) {
+        let mut names = self.names.lock().unwrap();
+        let histogram = names
+            .entry(name)
+            .or_insert_with(|| Histogram::new(3).expect("3 significant figures is valid"));
+        // Histograms grow to fit, so recording can't fail.
+        let _ = histogram.record(in_micros(lifetime));
+    }
+
+    pub fn to_args(&self) -> Map<String, Value> {
+        let names = self.names.lock().unwrap();
+        let mut args = Map::new();
+        for (name, histogram) in names.iter() {
+            let mut entry = Map::new();
+            entry.insert("count".to_owned(), Value::from(histogram.len()));
+            for &(key, quantile) in &[("p50_us", 0.5), ("p90_us", 0.9), ("p99_us", 0.99)] {
+                entry.insert(key.to_owned(), Value::from(histogram.value_at_quantile(quantile)));
+            }
+            entry.insert("max_us".to_owned(), Value::from(histogram.max()));
+            args.insert((*name).to_owned(), Value::Object(entry));
+        }
+        args
+    }
+}
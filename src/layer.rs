@@ -0,0 +1,108 @@
+use tracing_core::span::{Attributes, Id, Record};
+use tracing_core::{Event, Interest, Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+use crate::guard::{FlushGuard, ShutdownReason};
+use crate::{ChromeTraceSubscriber, TraceSink};
+
+/// A `tracing-subscriber` layer writing the same trace as
+/// `ChromeTraceSubscriber`, for stacking with `fmt`, `EnvFilter` and the
+/// like. Make one with `ChromeTraceBuilder::build_layer`.
+///
+/// Spans and events go through the same code as with the subscriber, with
+/// span ids from the registry. The builder's `max_level`, `filter` and
+/// `categories` settings decide what this layer writes, without hiding
+/// anything from the other layers.
+pub struct ChromeTraceLayer {
+    pub(crate) subscriber: ChromeTraceSubscriber,
+}
+
+impl ChromeTraceLayer {
+    /// See `ChromeTraceSubscriber::flush`.
+    pub fn flush(&self) {
+        self.subscriber.flush()
+    }
+
+    /// See `ChromeTraceSubscriber::finish`.
+    pub fn finish(&self) {
+        self.subscriber.finish()
+    }
+
+    /// See `ChromeTraceSubscriber::finish_because`.
+    pub fn finish_because(&self, reason: ShutdownReason) {
+        self.subscriber.finish_because(reason)
+    }
+
+    /// See `ChromeTraceSubscriber::attach`.
+    pub fn attach<S: TraceSink + 'static>(&self, sink: S) {
+        self.subscriber.attach(sink)
+    }
+
+    /// See `ChromeTraceSubscriber::annotate`.
+    pub fn annotate(&self, label: &str) {
+        self.subscriber.annotate(label)
+    }
+
+    /// See `ChromeTraceSubscriber::flush_guard`.
+    pub fn flush_guard(&self) -> FlushGuard {
+        self.subscriber.flush_guard()
+    }
+
+    /// Whether this layer kept span `id`: spans it filtered out, or shed,
+    /// are still entered and closed for the other layers.
+    fn kept(&self, id: &Id) -> bool {
+        self.subscriber.spans.contains(id.into_u64())
+    }
+}
+
+impl<S: Subscriber> Layer<S> for ChromeTraceLayer {
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if self.subscriber.config.debug_callsites {
+            let enabled = self.subscriber.enabled(metadata);
+            self.subscriber.callsite_registered(metadata, enabled);
+        }
+        // Whether anything is recorded is up to the other layers too, so
+        // the filter is applied to each span and event instead.
+        Interest::always()
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, _ctx: Context<'_, S>) {
+        if self.subscriber.enabled(attrs.metadata()) {
+            self.subscriber.open_span(id.into_u64(), attrs);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+        self.subscriber.record(id, values)
+    }
+
+    fn on_follows_from(&self, id: &Id, follows: &Id, _ctx: Context<'_, S>) {
+        if self.kept(follows) {
+            self.subscriber.record_follows_from(id, follows)
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if self.subscriber.enabled(event.metadata()) {
+            self.subscriber.event(event)
+        }
+    }
+
+    fn on_enter(&self, id: &Id, _ctx: Context<'_, S>) {
+        if self.kept(id) {
+            self.subscriber.enter(id)
+        }
+    }
+
+    fn on_exit(&self, id: &Id, _ctx: Context<'_, S>) {
+        if self.kept(id) {
+            self.subscriber.exit(id)
+        }
+    }
+
+    fn on_close(&self, id: Id, _ctx: Context<'_, S>) {
+        // The registry only closes a span once, and this layer never
+        // clones it, so this drops the only reference.
+        self.subscriber.try_close(id);
+    }
+}
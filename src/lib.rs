@@ -1,180 +1,3315 @@
-use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::env;
 use std::fmt;
 use std::fs::File;
-use std::io::Write;
-use std::process;
+use std::mem;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
-use std::sync::mpsc::{self, Receiver, Sender};
 use std::thread;
-use std::time::{Instant, Duration};
-use tokio_trace_core::{field, Event, Metadata, Span};
+use std::time::Duration;
+use tracing_core::span::{Attributes, Id, Record};
+use tracing_core::{field, Event, Interest, Level, Metadata};
+
+#[cfg(not(any(feature = "serde_json", feature = "minimal")))]
+compile_error!("enable either the default `serde_json` feature or `minimal`");
+
+mod alloc;
+mod anonymize;
+mod args;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+mod atrace;
+mod bench;
+mod blocking;
+mod budget;
+mod channel;
+#[cfg(all(unix, feature = "serde_json"))]
+mod child;
+mod clip;
+mod clock;
+mod coalesce;
+#[cfg(feature = "columnar")]
+mod columnar;
+mod compact;
+mod csv;
+mod dedup;
+#[cfg(feature = "serde_json")]
+mod diff;
+#[cfg(feature = "encryption")]
+mod encrypt;
+mod error;
+#[cfg(all(windows, feature = "etw"))]
+mod etw;
+mod event;
+mod fallback;
+mod filter;
+mod foreign;
+mod gaps;
+mod guard;
+#[cfg(feature = "gzip")]
+mod gzip;
+mod handle;
+mod heatmap;
+mod ids;
+mod index;
+mod json;
+mod lanes;
+#[cfg(feature = "hdrhistogram")]
+mod latency;
+#[cfg(feature = "tracing")]
+mod layer;
+#[cfg(feature = "manifest")]
+mod manifest;
+mod memory;
+mod overhead;
+#[cfg(feature = "perfetto")]
+mod perfetto;
+mod pool;
+pub mod prelude;
+mod quota;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+mod procfs;
+mod rate;
+mod rotate;
+mod runtime;
+mod sampler;
+#[cfg(target_os = "macos")]
+mod signpost;
+mod sink;
+mod sizes;
+mod span;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "backtrace")]
+mod stack;
+mod stats;
+mod stream;
+mod threads;
+#[cfg(all(feature = "perfetto", any(target_os = "android", target_os = "linux")))]
+mod traced;
+#[cfg(not(feature = "serde_json"))]
+mod value;
+mod viewer;
+#[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+mod wasm;
+#[cfg(feature = "websocket")]
+mod websocket;
+mod writer;
+mod zipkin;
+
+pub use crate::alloc::CountingAlloc;
+pub use crate::anonymize::Anonymizer;
+pub use crate::bench::BenchTrace;
+pub use crate::budget::ShedPolicy;
+pub use crate::channel::ChannelPolicy;
+#[cfg(all(unix, feature = "serde_json"))]
+pub use crate::child::{ChildTraceWriter, ParentCollector, CHILD_TRACE_FD_VAR};
+pub use crate::args::ArgLayout;
+pub use crate::clip::ClippedSink;
+#[cfg(feature = "columnar")]
+pub use crate::columnar::ParquetSink;
+pub use crate::compact::DICTIONARY_RECORD;
+#[cfg(feature = "serde_json")]
+pub use crate::compact::decode_compact;
+pub use crate::csv::CsvSink;
+#[cfg(feature = "serde_json")]
+pub use crate::diff::{compare_slices, summarize_slices, Change, SliceStats};
+#[cfg(feature = "encryption")]
+pub use crate::encrypt::EncryptedWriter;
+#[cfg(all(windows, feature = "etw"))]
+pub use crate::etw::EtwSink;
+pub use crate::error::Error;
+pub use crate::event::TraceEvent;
+pub use crate::filter::FILTER_VAR;
+pub use crate::foreign::ForeignThreads;
+pub use crate::gaps::GAP_EVENT;
+pub use crate::guard::{FlushGuard, ShutdownReason};
+#[cfg(feature = "gzip")]
+pub use crate::gzip::GzipWriter;
+pub use crate::handle::ChromeTraceHandle;
+pub use crate::heatmap::ACTIVITY_TRACK;
+pub use crate::ids::SpanIds;
+pub use crate::lanes::TaskLanes;
+#[cfg(feature = "tracing")]
+pub use crate::layer::ChromeTraceLayer;
+pub use crate::memory::{EventBuffer, MemorySink, Query};
+pub use crate::quota::Quota;
+#[cfg(feature = "perfetto")]
+pub use crate::perfetto::PerfettoSink;
+pub use crate::rate::RateLimit;
+pub use crate::rotate::{RotateAfter, RotatingSink};
+pub use crate::runtime::RuntimePreset;
+pub use crate::sink::{Escaping, JsonFormat, JsonSink, LazyFile, TraceSink};
+pub use crate::span::ClosedSpan;
+#[cfg(feature = "sqlite")]
+pub use crate::sqlite::SqliteSink;
+#[cfg(feature = "serde_json")]
+pub use crate::stream::reassemble_stream;
+pub use crate::stream::StreamSink;
+#[cfg(all(feature = "perfetto", any(target_os = "android", target_os = "linux")))]
+pub use crate::traced::TracedSink;
+pub use crate::viewer::ViewerLinks;
+#[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+pub use crate::wasm::{download_trace, log_trace, trace_json, BrowserSink};
+#[cfg(feature = "websocket")]
+pub use crate::websocket::WebSocketSink;
+pub use crate::zipkin::ZipkinSink;
+
+/// The versions of the crates this one is built against, so their types
+/// can be named without a dependency of your own to keep in step.
+pub use tracing_core;
+#[cfg(feature = "tracing")]
+pub use tracing_subscriber;
+#[cfg(target_arch = "wasm32")]
+pub use web_time;
+/// The JSON types event arguments are made of: serde_json's, or a
+/// hand-written stand-in when built with `minimal` instead.
+pub use crate::json::{Map, Number, Value};
+use crate::budget::Shed;
+use crate::clock::{Instant, SystemTime, UNIX_EPOCH};
+use crate::stats::Suppressed;
+use crate::writer::Route;
+
+thread_local! {
+    static PARKED: Cell<bool> = const { Cell::new(false) };
+    static SEQUENCE: Cell<u64> = const { Cell::new(0) };
+    // The subscriber instance this thread last wrote its name for.
+    static NAMED_FOR: Cell<usize> = const { Cell::new(0) };
+}
+
+static NEXT_INSTANCE: AtomicUsize = AtomicUsize::new(1);
+
+/// The made-up thread `ChromeTraceBuilder::annotation_track` draws on.
+const ANNOTATION_TID: u64 = 0;
+
+/// Name of the instant event written at shutdown with the run's duration,
+/// event count, dropped events, peak queue depth and the `reason` it ended
+/// (see `ShutdownReason`).
+pub const SUMMARY_EVENT: &str = "chrome_trace_summary";
+
+/// Name of the instant event written at startup with the configuration the
+/// trace was captured with.
+pub const CONFIG_EVENT: &str = "chrome_trace_config";
+
+/// The metadata record tying `ts` to wall-clock time, whatever the
+/// `TimeBase`. It has the Unix time the subscriber started at, and the `ts`
+/// it started at, both in microseconds; a merging tool adds the difference
+/// to each `ts` to put traces from several processes on one timeline.
+pub const WALL_CLOCK_EVENT: &str = "wall_clock";
+
+/// An event with this field (a number of microseconds) is written as a
+/// complete event covering that much time up to the event, e.g. for code
+/// that times itself and reports when it's done.
+pub const DURATION_FIELD: &str = "trace.dur_us";
+
+/// An event with this field is written as a sample of the counter named by
+/// its message, with its numeric fields as the series. The field's value is
+/// ignored.
+pub const COUNTER_FIELD: &str = "trace.counter";
+
+/// The name and category of the flow events drawn for `follows_from`.
+const FLOW_NAME: &str = "follows_from";
+
+/// The category of the flow events drawn for links, see
+/// `ChromeTraceSubscriber::link`.
+const LINK_CATEGORY: &str = "link";
+
+/// A span with this field (the id of another span) gets an arrow from that
+/// span, drawn when it's first entered, as if linked with
+/// `ChromeTraceSubscriber::link`. The arrow is labeled with the
+/// `LINK_LABEL_FIELD` field if there is one, or `"link"`.
+pub const LINK_FIELD: &str = "trace.link";
+
+/// The label for the arrow drawn for `LINK_FIELD`.
+pub const LINK_LABEL_FIELD: &str = "trace.link_label";
+
+/// A span with this field is also tracked as an object: it's written as a
+/// created (`N`) and destroyed (`D`) object, with a snapshot (`O`) of its
+/// fields each time they're recorded, so changing state can be followed over
+/// the object's lifetime. The field's value is ignored.
+pub const OBJECT_FIELD: &str = "trace.object";
+
+/// A span or event with this field (one of the viewer's reserved color
+/// names, like `thread_state_iowait`) is drawn in that color, whatever
+/// `ChromeTraceBuilder::color_fn` says. A span can set it after it's
+/// created, if it declares the field.
+pub const COLOR_FIELD: &str = "trace.cname";
+
+/// An event with this field is written with that phase (`"B"`, `"n"`,
+/// `"X"`, ...) instead of as an instant, for the parts of the Trace Event
+/// Format this crate has no other way to reach. `"X"` needs
+/// `DURATION_FIELD` and the async, flow and object phases need `ID_FIELD`;
+/// an event without them, or with a phase that isn't one, is written as an
+/// instant and reported to the internal logger.
+pub const PHASE_FIELD: &str = "trace.ph";
+
+/// The id an event written with `PHASE_FIELD` pairs up by, a number.
+pub const ID_FIELD: &str = "trace.id";
 
 enum Message {
     Done,
-    Event(Value),
+    Event(TraceEvent, Route),
+    SystemTrace(String),
+    StartCapture(u64, Box<dyn TraceSink>),
+    Captured(TraceEvent, u64),
+    EndCapture(u64),
+    SpanClosed(ClosedSpan),
+    /// Flush every sink, then acknowledge.
+    Flush(Sender<()>),
+    /// Replace the default sinks, see `ChromeTraceHandle::set_output`,
+    /// ending the old ones with the event given, if any.
+    SetOutput(Box<dyn TraceSink>, Option<TraceEvent>),
 }
 
 pub struct MaybeChromeTraceSubscriber(pub Option<ChromeTraceSubscriber>);
 
 pub struct ChromeTraceSubscriber {
+    /// Tells this subscriber apart from others in the same process, for
+    /// per-thread state.
+    instance: usize,
     start: Instant,
+    /// `start` in microseconds since the Unix epoch.
+    epoch: u64,
     next_span: Arc<AtomicUsize>,
-    tx: Arc<Mutex<Sender<Message>>>,
+    /// Set for `SpanIds::ByCallsite`.
+    callsite_ids: Option<ids::CallsiteIds>,
+    /// Set for `ChromeTraceBuilder::task_lanes`.
+    lanes: Option<lanes::Lanes>,
+    /// Set unless `ChromeTraceBuilder::foreign_threads` keeps them as is.
+    foreign: Option<foreign::Foreign>,
+    next_flow: AtomicUsize,
+    /// When spans were last checked against `span_ttl`.
+    last_eviction: Mutex<Instant>,
+    tx: Arc<Mutex<channel::Tx>>,
+    stats: Arc<stats::Stats>,
+    config: Config,
+    spans: span::Registry,
+    tasks: Vec<runtime::TaskCounts>,
+    pools: pool::Pools,
+    open_spans: Mutex<HashMap<String, usize>>,
+    polls: Option<Arc<blocking::Polls>>,
+    sampler: Option<rate::Sampler>,
+    /// Only held here; sampler threads stop once it's dropped.
+    _alive: Arc<()>,
+    span_times: Arc<span::TimeSummary>,
+    #[cfg(feature = "hdrhistogram")]
+    latencies: Arc<latency::Latencies>,
+    shutdown: Arc<guard::Shutdown>,
+    /// Set when built with a `ChromeTraceHandle`.
+    control: Option<Arc<handle::Control>>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    atrace: Option<atrace::Marker>,
+    #[cfg(target_os = "macos")]
+    signposts: Option<signpost::Signposts>,
+}
+
+/// How integers that don't fit in an IEEE double (above 2^53) are written.
+/// JavaScript-based viewers silently round such numbers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LargeIntEncoding {
+    /// Write them as plain JSON numbers.
+    #[default]
+    Number,
+    /// Write them as decimal strings.
+    String,
+    /// Write them as `{"hi": <upper 32 bits>, "lo": <lower 32 bits>}`.
+    HiLo,
+}
+
+/// What open spans are grouped by for in-flight counter tracks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpanCountKey {
+    Name,
+    Target,
+}
+
+fn wall_clock(unix_start: u64, ts_start: u64) -> Map<String, Value> {
+    let mut args = Map::new();
+    args.insert("unix_start_us".to_owned(), Value::from(unix_start));
+    args.insert("ts_start_us".to_owned(), Value::from(ts_start));
+    args
+}
+
+/// What event timestamps count from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimeBase {
+    /// When the subscriber was built.
+    #[default]
+    SubscriberStart,
+    /// The Unix epoch, so traces from different processes line up.
+    UnixEpoch,
+    /// When the process started, so events recorded elsewhere before the
+    /// subscriber was built line up. Only known on Linux and Android;
+    /// elsewhere this is the same as `SubscriberStart`.
+    ProcessStart,
+    /// Any earlier moment, e.g. one captured at the top of `main`. On
+    /// wasm32 that's a `web_time::Instant`, as std's can't be read there.
+    At(Instant),
+}
+
+/// How far up the viewer draws the line for instant events.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InstantScope {
+    Thread,
+    #[default]
+    Process,
+    Global,
+}
+
+impl InstantScope {
+    fn code(self) -> &'static str {
+        match self {
+            InstantScope::Thread => "t",
+            InstantScope::Process => "p",
+            InstantScope::Global => "g",
+        }
+    }
+}
+
+/// Where `ChromeTraceBuilder::span_slices` draws spans.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrackStyle {
+    /// Each entry into a span is a complete (`X`) slice on the thread that
+    /// entered it.
+    #[default]
+    Threaded,
+    /// Each span is one `b`/`e` slice on its own async track, from creation
+    /// to close, with a nested `busy` slice for each time it was entered.
+    Async,
+    /// Both of the above.
+    Both,
+}
+
+impl TrackStyle {
+    fn threaded(self) -> bool {
+        self != TrackStyle::Async
+    }
+
+    fn async_tracks(self) -> bool {
+        self != TrackStyle::Threaded
+    }
+}
+
+/// A piece of callsite metadata that contributes to an event's `cat`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CategorySource {
+    Target,
+    Level,
+    /// The first path segment of the module path (or target).
+    Crate,
+}
+
+#[derive(Clone)]
+struct ParkEvents {
+    park: String,
+    unpark: String,
+}
+
+type CategoryFn = Arc<dyn Fn(&Metadata) -> String + Send + Sync>;
+type ColorFn = Arc<dyn Fn(&Metadata) -> Option<&'static str> + Send + Sync>;
+type ContextFn = Arc<dyn Fn(&mut Map<String, Value>) + Send + Sync>;
+
+#[derive(Clone)]
+struct Config {
+    large_ints: LargeIntEncoding,
+    categories: Vec<CategorySource>,
+    category_fn: Option<CategoryFn>,
+    color_fn: Option<ColorFn>,
+    context_fn: Option<ContextFn>,
+    level_suffix: bool,
+    annotation_track: bool,
+    max_level: Option<Level>,
+    filter: Option<filter::Filter>,
+    args: bool,
+    time_base: TimeBase,
+    ts_offset: i64,
+    channel_capacity: Option<usize>,
+    channel_policy: ChannelPolicy,
+    memory_limit: Option<budget::Budget>,
+    rate_limit: Option<RateLimit>,
+    quotas: Vec<(String, Quota)>,
+    span_ids: SpanIds,
+    task_lanes: Option<TaskLanes>,
+    start_paused: bool,
+    measure_overhead: bool,
+    callsite_drops: bool,
+    backfill_threads: bool,
+    lazy: bool,
+    shared_sender: bool,
+    idle_gap: Option<Duration>,
+    activity_bucket: Option<Duration>,
+    dedup_window: Option<Duration>,
+    short_span_threshold: Option<Duration>,
+    backlog: Option<usize>,
+    max_open_spans: Option<usize>,
+    span_ttl: Option<Duration>,
+    instant_scope: InstantScope,
+    only_within: Option<String>,
+    args_min_level: Option<Level>,
+    slim: bool,
+    max_event_size: Option<usize>,
+    sequence_numbers: bool,
+    debug_callsites: bool,
+    locations: bool,
+    durations_as_micros: bool,
+    checkpoint_every: Option<Duration>,
+    runtimes: Vec<RuntimePreset>,
+    pool_prefixes: Vec<String>,
+    pool_processes: bool,
+    foreign_threads: ForeignThreads,
+    count_open_spans: Option<SpanCountKey>,
+    park_events: Option<ParkEvents>,
+    blocking_threshold: Option<Duration>,
+    alloc_interval: Option<Duration>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    thread_cpu_interval: Option<Duration>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    context_switch_interval: Option<Duration>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fd_interval: Option<Duration>,
+    span_summary: bool,
+    span_slices: bool,
+    track_style: TrackStyle,
+    #[cfg(feature = "hdrhistogram")]
+    latency_percentiles: bool,
+    #[cfg(feature = "backtrace")]
+    backtrace_level: Option<Level>,
+    #[cfg(feature = "backtrace")]
+    backtrace_targets: Vec<String>,
+    routes: Vec<String>,
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    atrace: bool,
+    #[cfg(target_os = "macos")]
+    signpost_subsystem: Option<String>,
+    export_spans: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            large_ints: LargeIntEncoding::default(),
+            categories: vec![CategorySource::Target],
+            category_fn: None,
+            color_fn: None,
+            context_fn: None,
+            level_suffix: false,
+            annotation_track: false,
+            max_level: None,
+            filter: None,
+            args: true,
+            time_base: TimeBase::default(),
+            ts_offset: 0,
+            channel_capacity: None,
+            channel_policy: ChannelPolicy::default(),
+            memory_limit: None,
+            rate_limit: None,
+            quotas: Vec::new(),
+            span_ids: SpanIds::default(),
+            task_lanes: None,
+            start_paused: false,
+            measure_overhead: false,
+            callsite_drops: false,
+            backfill_threads: false,
+            lazy: false,
+            shared_sender: false,
+            idle_gap: None,
+            activity_bucket: None,
+            dedup_window: None,
+            short_span_threshold: None,
+            backlog: None,
+            max_open_spans: None,
+            span_ttl: None,
+            instant_scope: InstantScope::default(),
+            only_within: None,
+            args_min_level: None,
+            slim: false,
+            max_event_size: None,
+            sequence_numbers: false,
+            debug_callsites: false,
+            locations: true,
+            durations_as_micros: false,
+            checkpoint_every: None,
+            runtimes: Vec::new(),
+            pool_prefixes: Vec::new(),
+            pool_processes: false,
+            foreign_threads: ForeignThreads::Keep,
+            count_open_spans: None,
+            park_events: None,
+            blocking_threshold: None,
+            alloc_interval: None,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            thread_cpu_interval: None,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            context_switch_interval: None,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            fd_interval: None,
+            span_summary: false,
+            span_slices: true,
+            track_style: TrackStyle::default(),
+            #[cfg(feature = "hdrhistogram")]
+            latency_percentiles: false,
+            #[cfg(feature = "backtrace")]
+            backtrace_level: None,
+            #[cfg(feature = "backtrace")]
+            backtrace_targets: Vec::new(),
+            routes: Vec::new(),
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            atrace: false,
+            #[cfg(target_os = "macos")]
+            signpost_subsystem: None,
+            export_spans: false,
+        }
+    }
+}
+
+impl Config {
+    /// Checks for settings `ChromeTraceBuilder::build` would quietly make
+    /// the best of.
+    fn validate(&self) -> Result<(), Error> {
+        if let Some(ref filter) = self.filter {
+            filter::Filter::check(filter.spec()).map_err(Error::Filter)?;
+        }
+        #[cfg_attr(not(any(target_os = "android", target_os = "linux")), allow(unused_mut))]
+        let mut intervals = vec![
+            ("checkpoint_every", self.checkpoint_every),
+            ("allocation_counters", self.alloc_interval),
+            ("detect_blocking", self.blocking_threshold),
+            ("activity_track", self.activity_bucket),
+            ("summarize_short_spans", self.short_span_threshold),
+        ];
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        intervals.extend(vec![
+            ("thread_cpu_counters", self.thread_cpu_interval),
+            ("context_switch_counters", self.context_switch_interval),
+            ("fd_counters", self.fd_interval),
+        ]);
+        for (setting, interval) in intervals {
+            if interval == Some(Duration::from_secs(0)) {
+                return Err(Error::Config(format!("{} needs a non-zero interval", setting)));
+            }
+        }
+        for &(ref pattern, quota) in &self.quotas {
+            if let Quota::Percent(percent) = quota {
+                if percent > 100 {
+                    return Err(Error::Config(format!("quota for `{}` is over 100%", pattern)));
+                }
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            if self.blocking_threshold.is_some() {
+                return Err(Error::Unsupported("detect_blocking needs threads"));
+            }
+            if self.alloc_interval.is_some() {
+                return Err(Error::Unsupported("allocation_counters needs threads"));
+            }
+        }
+        if self.time_base == TimeBase::ProcessStart && process_age().is_none() {
+            return Err(Error::Unsupported("TimeBase::ProcessStart needs Linux or Android"));
+        }
+        Ok(())
+    }
+
+    /// The settings that shape the trace, for `CONFIG_EVENT`.
+    fn to_args(&self) -> Map<String, Value> {
+        fn micros(interval: Option<Duration>) -> Value {
+            interval.map_or(Value::Null, |interval| Value::from(in_micros(interval)))
+        }
+        fn level(level: &Option<Level>) -> Value {
+            level.as_ref().map_or(Value::Null, |level| Value::from(level_name(level)))
+        }
+        let mut args = Map::new();
+        let categories: Vec<String> = self.categories.iter().map(|c| format!("{:?}", c)).collect();
+        args.insert("categories".to_owned(), Value::from(categories));
+        args.insert("custom_categories".to_owned(), Value::from(self.category_fn.is_some()));
+        args.insert("custom_colors".to_owned(), Value::from(self.color_fn.is_some()));
+        args.insert("context_args".to_owned(), Value::from(self.context_fn.is_some()));
+        args.insert("level_suffix".to_owned(), Value::from(self.level_suffix));
+        args.insert("annotation_track".to_owned(), Value::from(self.annotation_track));
+        args.insert("max_level".to_owned(), level(&self.max_level));
+        args.insert(
+            "filter".to_owned(),
+            self.filter.as_ref().map_or(Value::Null, |filter| Value::from(filter.spec())),
+        );
+        args.insert("only_within".to_owned(), self.only_within.clone().map_or(Value::Null, Value::from));
+        args.insert("args".to_owned(), Value::from(self.args && !self.slim));
+        args.insert("args_min_level".to_owned(), level(&self.args_min_level));
+        args.insert("large_ints".to_owned(), Value::from(format!("{:?}", self.large_ints)));
+        args.insert("time_base".to_owned(), Value::from(format!("{:?}", self.time_base)));
+        args.insert("ts_offset_us".to_owned(), Value::from(self.ts_offset));
+        args.insert("backlog".to_owned(), self.backlog.map_or(Value::Null, Value::from));
+        args.insert("measure_overhead".to_owned(), Value::from(self.measure_overhead));
+        args.insert("thread_pool_groups".to_owned(), Value::from(self.pool_processes));
+        args.insert("foreign_threads".to_owned(), Value::from(format!("{:?}", self.foreign_threads)));
+        args.insert("lazy".to_owned(), Value::from(self.lazy));
+        args.insert("callsite_drop_stats".to_owned(), Value::from(self.callsite_drops));
+        args.insert("backfill_thread_metadata".to_owned(), Value::from(self.backfill_threads));
+        args.insert(
+            "idle_gap_us".to_owned(),
+            self.idle_gap.map_or(Value::Null, |gap| Value::from(in_micros(gap))),
+        );
+        args.insert(
+            "activity_bucket_us".to_owned(),
+            self.activity_bucket.map_or(Value::Null, |bucket| Value::from(in_micros(bucket))),
+        );
+        args.insert(
+            "dedup_window_us".to_owned(),
+            self.dedup_window.map_or(Value::Null, |window| Value::from(in_micros(window))),
+        );
+        args.insert(
+            "short_span_threshold_us".to_owned(),
+            self.short_span_threshold.map_or(Value::Null, |threshold| Value::from(in_micros(threshold))),
+        );
+        args.insert(
+            "task_lanes".to_owned(),
+            self.task_lanes.map_or(Value::Null, |lanes| Value::from(format!("{:?}", lanes))),
+        );
+        args.insert("span_ids".to_owned(), Value::from(format!("{:?}", self.span_ids)));
+        args.insert("instant_scope".to_owned(), Value::from(format!("{:?}", self.instant_scope)));
+        args.insert("channel_capacity".to_owned(), self.channel_capacity.map_or(Value::Null, Value::from));
+        args.insert("channel_policy".to_owned(), Value::from(format!("{:?}", self.channel_policy)));
+        args.insert(
+            "rate_limit".to_owned(),
+            self.rate_limit.map_or(Value::Null, |limit| Value::from(format!("{:?}", limit))),
+        );
+        let quotas = self
+            .quotas
+            .iter()
+            .map(|(pattern, quota)| (pattern.clone(), Value::from(format!("{:?}", quota))))
+            .collect();
+        args.insert("quotas".to_owned(), Value::Object(quotas));
+        args.insert("max_event_size".to_owned(), self.max_event_size.map_or(Value::Null, Value::from));
+        args.insert("checkpoint_every_us".to_owned(), micros(self.checkpoint_every));
+        args.insert("span_slices".to_owned(), Value::from(self.span_slices));
+        args.insert("track_style".to_owned(), Value::from(format!("{:?}", self.track_style)));
+        args.insert("source_locations".to_owned(), Value::from(self.locations && !self.slim));
+        args.insert("sequence_numbers".to_owned(), Value::from(self.sequence_numbers));
+        args.insert("durations_as_micros".to_owned(), Value::from(self.durations_as_micros));
+        args.insert("blocking_threshold_us".to_owned(), micros(self.blocking_threshold));
+        args.insert("allocation_interval_us".to_owned(), micros(self.alloc_interval));
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            args.insert("thread_cpu_interval_us".to_owned(), micros(self.thread_cpu_interval));
+            args.insert("context_switch_interval_us".to_owned(), micros(self.context_switch_interval));
+            args.insert("fd_interval_us".to_owned(), micros(self.fd_interval));
+        }
+        args.insert("routes".to_owned(), Value::from(self.routes.clone()));
+        args
+    }
+
+    /// Builds the comma-separated `cat` string, skipping repeated entries.
+    fn category(&self, meta: &'static Metadata<'static>) -> Cow<'static, str> {
+        let category = self.base_category(meta);
+        if self.level_suffix {
+            Cow::Owned(with_level(category.into_owned(), level_name(meta.level())))
+        } else {
+            category
+        }
+    }
+
+    fn color(&self, meta: &Metadata) -> Option<String> {
+        let color = self.color_fn.as_ref()?;
+        color(meta).map(str::to_owned)
+    }
+
+    /// Adds the args from `ChromeTraceBuilder::context_fn`.
+    pub(crate) fn add_context(&self, args: &mut Map<String, Value>) {
+        if let Some(ref context) = self.context_fn {
+            let mut extra = Map::new();
+            context(&mut extra);
+            for (key, value) in extra {
+                args.entry(key).or_insert(value);
+            }
+        }
+    }
+
+    /// Borrowed from the callsite when there's one source, as by default.
+    fn base_category(&self, meta: &'static Metadata<'static>) -> Cow<'static, str> {
+        if let Some(ref category) = self.category_fn {
+            return Cow::Owned(category(meta));
+        }
+        let part = |source: &CategorySource| match source {
+            CategorySource::Target => meta.target(),
+            CategorySource::Level => level_name(meta.level()),
+            CategorySource::Crate => crate_name(meta),
+        };
+        if let [ref source] = self.categories[..] {
+            return Cow::Borrowed(part(source));
+        }
+        let mut parts: Vec<&str> = Vec::with_capacity(self.categories.len());
+        for part in self.categories.iter().map(part) {
+            if !parts.contains(&part) {
+                parts.push(part);
+            }
+        }
+        Cow::Owned(parts.join(","))
+    }
+
+    /// Whether a callsite is recorded at all; decided once per callsite,
+    /// unless there's a `ChromeTraceHandle`.
+    fn enabled(&self, meta: &Metadata) -> bool {
+        self.enabled_with(meta, self.filter.as_ref())
+    }
+
+    fn enabled_with(&self, meta: &Metadata, filter: Option<&filter::Filter>) -> bool {
+        let by_level = match self.max_level {
+            Some(ref max) => verbosity(meta.level()) <= verbosity(max),
+            None => true,
+        };
+        by_level && filter.is_none_or(|filter| filter.enabled(meta))
+    }
+
+    fn include_args(&self, meta: &Metadata) -> bool {
+        if self.slim || !self.args {
+            return false;
+        }
+        match self.args_min_level {
+            Some(ref min) => verbosity(meta.level()) <= verbosity(min),
+            None => true,
+        }
+    }
+
+    /// Whether a callsite's level and source location go in its args.
+    fn include_location(&self, meta: &Metadata) -> bool {
+        self.locations && self.include_args(meta)
+    }
+
+    #[cfg(feature = "backtrace")]
+    fn wants_backtrace(&self, meta: &Metadata) -> bool {
+        let by_level = match self.backtrace_level {
+            Some(ref level) => verbosity(meta.level()) <= verbosity(level),
+            None => false,
+        };
+        by_level
+            || self
+                .backtrace_targets
+                .iter()
+                .any(|prefix| target_matches(meta.target(), prefix))
+    }
+
+    fn route(&self, target: &str) -> Route {
+        self.routes
+            .iter()
+            .position(|prefix| target_matches(target, prefix))
+            .map_or(Route::Default, Route::Sink)
+    }
+
+    /// A span matches a pattern naming either the span itself or its target
+    /// (or a parent module of its target).
+    fn matches_within(&self, meta: &Metadata) -> bool {
+        match self.only_within {
+            Some(ref pattern) => {
+                meta.name() == pattern.as_str() || target_matches(meta.target(), pattern)
+            }
+            None => false,
+        }
+    }
+}
+
+/// Appends `level` to the comma-separated `category`, unless it's already
+/// one of its entries.
+pub(crate) fn with_level(mut category: String, level: &str) -> String {
+    if category.split(',').any(|part| part == level) {
+        return category;
+    }
+    if !category.is_empty() {
+        category.push(',');
+    }
+    category.push_str(level);
+    category
+}
+
+/// Adds a callsite's level and source location to `args`, keeping any
+/// field of the same name.
+fn add_location(args: &mut Map<String, Value>, meta: &Metadata) {
+    let location = [
+        ("level", Some(Value::from(level_name(meta.level())))),
+        ("module_path", meta.module_path().map(Value::from)),
+        ("file", meta.file().map(Value::from)),
+        ("line", meta.line().map(Value::from)),
+    ];
+    for (key, value) in location.iter().cloned() {
+        if let Some(value) = value {
+            args.entry(key.to_owned()).or_insert(value);
+        }
+    }
+}
+
+/// The executable's name, without its extension.
+fn process_name() -> String {
+    env::current_exe()
+        .ok()
+        .and_then(|exe| exe.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+/// Whether `target` is `prefix` itself or a module nested under it.
+pub(crate) fn target_matches(target: &str, prefix: &str) -> bool {
+    target == prefix
+        || (target.starts_with(prefix) && target[prefix.len()..].starts_with("::"))
+}
+
+pub(crate) fn level_name(level: &Level) -> &'static str {
+    if *level == Level::ERROR {
+        "error"
+    } else if *level == Level::WARN {
+        "warn"
+    } else if *level == Level::INFO {
+        "info"
+    } else if *level == Level::DEBUG {
+        "debug"
+    } else {
+        "trace"
+    }
+}
+
+/// Orders levels from least (`ERROR`) to most (`TRACE`) verbose.
+pub(crate) fn verbosity(level: &Level) -> u8 {
+    if *level == Level::ERROR {
+        0
+    } else if *level == Level::WARN {
+        1
+    } else if *level == Level::INFO {
+        2
+    } else if *level == Level::DEBUG {
+        3
+    } else {
+        4
+    }
+}
+
+fn crate_name<'a>(meta: &Metadata<'a>) -> &'a str {
+    let path = meta.module_path().unwrap_or_else(|| meta.target());
+    path.split("::").next().unwrap_or(path)
+}
+
+/// Configures a `ChromeTraceSubscriber`. Every event goes through a single
+/// channel and writer thread, which hands it to each configured sink in turn.
+#[derive(Default)]
+pub struct ChromeTraceBuilder {
+    sinks: Vec<Box<dyn TraceSink>>,
+    routed: Vec<Box<dyn TraceSink>>,
+    transforms: Vec<writer::Transform>,
+    ts_map: Option<writer::TsMap>,
+    anonymizer: Option<Anonymizer>,
+    arg_shape: args::Shape,
+    viewer_links: Option<(PathBuf, viewer::Report)>,
+    config: Config,
+}
+
+impl ChromeTraceBuilder {
+    pub fn new() -> Self {
+        ChromeTraceBuilder::default()
+    }
+
+    /// Add an output. May be called more than once to write several formats
+    /// at the same time.
+    pub fn sink<S: TraceSink + 'static>(mut self, sink: S) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Add a gzipped JSON output at `path`, e.g. `trace.json.gz`.
+    /// Compression happens on the writer thread like the rest of the I/O.
+    #[cfg(feature = "gzip")]
+    pub fn file_gz<P: AsRef<Path>>(self, path: P) -> Result<Self, Error> {
+        Ok(self.sink(JsonSink::gzip_file(path)?))
+    }
+
+    /// Pass every event through `transform` on the writer thread before it
+    /// is written, e.g. to rename events, rewrite categories or drop noise
+    /// (by returning `None`). Transforms run in the order they're added.
+    pub fn transform<F>(mut self, transform: F) -> Self
+    where
+        F: FnMut(TraceEvent) -> Option<TraceEvent> + Send + 'static,
+    {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    /// Move every timestamp by `offset_us` microseconds, on top of the
+    /// `time_base`, e.g. to put a trace of replayed traffic back at the
+    /// wall-clock time it was recorded at. The `wall_clock` record moves
+    /// with it. Timestamps that would go below zero are clamped to zero.
+    pub fn ts_offset(mut self, offset_us: i64) -> Self {
+        self.config.ts_offset = offset_us;
+        self
+    }
+
+    /// Rewrite every timestamp with `map` on the writer thread, after the
+    /// `time_base` and `ts_offset` and before any transforms, for replays
+    /// whose timing doesn't reduce to a constant offset. A complete event's
+    /// end is mapped too. The `wall_clock` record isn't.
+    pub fn ts_map<F>(mut self, map: F) -> Self
+    where
+        F: FnMut(u64) -> u64 + Send + 'static,
+    {
+        self.ts_map = Some(Box::new(map));
+        self
+    }
+
+    /// Anonymize everything written, after any transforms. See
+    /// `Anonymizer`.
+    pub fn anonymize(mut self, anonymizer: Anonymizer) -> Self {
+        self.anonymizer = Some(anonymizer);
+        self
+    }
+
+    /// Write events whose target is under `prefix` (e.g. `"hyper"`) to
+    /// `sink` instead of the default sinks. The first matching route wins;
+    /// process and thread metadata goes to every sink.
+    pub fn route<S: TraceSink + 'static>(mut self, prefix: &str, sink: S) -> Self {
+        self.config.routes.push(prefix.to_owned());
+        self.routed.push(Box::new(sink));
+        self
+    }
+
+    pub fn large_ints(mut self, encoding: LargeIntEncoding) -> Self {
+        self.config.large_ints = encoding;
+        self
+    }
+
+    /// Compose each event's category from these sources, in order. The
+    /// default is just the target.
+    pub fn categories(mut self, sources: &[CategorySource]) -> Self {
+        self.config.categories = sources.to_vec();
+        self
+    }
+
+    /// Append the level to every category, after `categories` or
+    /// `category_fn`, e.g. `my_crate::db,error`, so the viewer's category
+    /// filter can show or hide severities.
+    pub fn level_suffix(mut self, enabled: bool) -> Self {
+        self.config.level_suffix = enabled;
+        self
+    }
+
+    /// Also draw each `ChromeTraceSubscriber::annotate` label on its own
+    /// `annotations` track at the top of the process.
+    pub fn annotation_track(mut self, enabled: bool) -> Self {
+        self.config.annotation_track = enabled;
+        self
+    }
+
+    /// Only record while inside a span whose name or target matches
+    /// `pattern` (e.g. `"request"` or `"my_crate::db"`), or one of its
+    /// descendants.
+    pub fn only_within(mut self, pattern: &str) -> Self {
+        self.config.only_within = Some(pattern.to_owned());
+        self
+    }
+
+    /// Ignore spans and events less severe than `level` entirely. Their
+    /// callsites are disabled, so they cost next to nothing.
+    pub fn max_level(mut self, level: Level) -> Self {
+        self.config.max_level = Some(level);
+        self
+    }
+
+    /// Only record the targets and levels `spec` lets through, e.g.
+    /// `my_crate=debug,hyper=warn,info`: each directive names a target and
+    /// the most verbose level to record under it (or `off`), and a bare
+    /// level covers every other target. Filtered-out callsites are disabled.
+    /// Directives that don't parse, including bare words that look like a
+    /// misspelled level (`inof`), are skipped and reported as `build`
+    /// describes; `try_build` fails on them.
+    pub fn filter(mut self, spec: &str) -> Self {
+        self.config.filter = Some(filter::Filter::parse(spec));
+        self
+    }
+
+    /// Like `filter`, with the directives read from `CHROME_TRACE_FILTER`.
+    /// Changes nothing if that isn't set.
+    pub fn filter_from_env(self) -> Self {
+        match env::var(FILTER_VAR) {
+            Ok(spec) => self.filter(&spec),
+            Err(_) => self,
+        }
+    }
+
+    /// How args are shaped: as recorded (the default), flattened into
+    /// dot-separated keys, or nested by the dots in field names.
+    pub fn arg_layout(mut self, layout: ArgLayout) -> Self {
+        self.arg_shape.layout = layout;
+        self
+    }
+
+    /// Write args nested more than `depth` objects or arrays deep as JSON
+    /// strings instead, for viewers that don't expand deep structures.
+    pub fn max_arg_depth(mut self, depth: usize) -> Self {
+        self.arg_shape.max_depth = Some(depth);
+        self
+    }
+
+    /// Cut string args longer than `chars` characters short, marking the
+    /// cut with `...`, so one huge field can't bloat the trace.
+    pub fn max_arg_string(mut self, chars: usize) -> Self {
+        self.arg_shape.max_string = Some(chars);
+        self
+    }
+
+    /// Bring args with well-known names to common units, so traces from
+    /// crates with different habits chart the same: numeric `*_ns` and
+    /// `*_ms` fields are converted to microseconds and renamed `*_us`.
+    /// Counter tracks whose series are all times, all `*bytes` or all
+    /// `*count` fields get the unit in their name, e.g. `heap (bytes)`.
+    pub fn normalize_units(mut self, enabled: bool) -> Self {
+        self.arg_shape.units = enabled;
+        self
+    }
+
+    /// Whether to write event fields as args at all. On by default.
+    pub fn include_args(mut self, enabled: bool) -> Self {
+        self.config.args = enabled;
+        self
+    }
+
+    /// Drop `args` from events less severe than `level`, keeping only their
+    /// names and timestamps.
+    pub fn args_min_level(mut self, level: Level) -> Self {
+        self.config.args_min_level = Some(level);
+        self
+    }
+
+    /// Measure every event's serialized size: events over `bytes` get an
+    /// `oversized event` marker naming their target, and the end-of-trace
+    /// summary gets an `event_sizes` histogram. Costs an extra
+    /// serialization per event on the writer thread.
+    pub fn max_event_size(mut self, bytes: usize) -> Self {
+        self.config.max_event_size = Some(bytes);
+        self
+    }
+
+    /// Give every event an `args.seq` that counts up per thread, so events
+    /// with the same timestamp can still be put in order.
+    pub fn sequence_numbers(mut self, enabled: bool) -> Self {
+        self.config.sequence_numbers = enabled;
+        self
+    }
+
+    /// Write a `callsite registered` marker, with the callsite's location and
+    /// whether it was enabled, the first time each span or event callsite is
+    /// hit. Helps track down why expected events are missing.
+    pub fn debug_callsites(mut self, enabled: bool) -> Self {
+        self.config.debug_callsites = enabled;
+        self
+    }
+
+    /// Add each callsite's `level`, `module_path`, `file` and `line` to the
+    /// args of its events and span slices, so they can be traced back to
+    /// the code. On by default; fields of the same name win.
+    pub fn source_locations(mut self, enabled: bool) -> Self {
+        self.config.locations = enabled;
+        self
+    }
+
+    /// Turn fields holding a `Duration` into numbers of microseconds, in an
+    /// arg with `_us` appended to the field's name (`elapsed` becomes
+    /// `elapsed_us`), so they can be sorted and charted.
+    pub fn durations_as_micros(mut self, enabled: bool) -> Self {
+        self.config.durations_as_micros = enabled;
+        self
+    }
+
+    /// The smallest output that still shows timing: no args or source
+    /// locations on any event, and categories cut down to the crate name.
+    pub fn slim(mut self) -> Self {
+        self.config.slim = true;
+        self.config.categories = vec![CategorySource::Crate];
+        self
+    }
+
+    /// Every `interval`, make sure the output on disk is a complete trace
+    /// (closing bracket included) so a copy taken from a live process loads.
+    pub fn checkpoint_every(mut self, interval: Duration) -> Self {
+        self.config.checkpoint_every = Some(interval);
+        self
+    }
+
+    /// Recognize an async runtime's task spans and emit counters of live and
+    /// currently-polled tasks for it.
+    pub fn runtime_preset(mut self, preset: RuntimePreset) -> Self {
+        self.config.runtimes.push(preset);
+        self
+    }
+
+    /// Treat threads whose name starts with `prefix` (e.g. `"rayon"`) as
+    /// workers of a thread pool: their tracks are grouped together and an
+    /// active-worker counter is kept for the pool.
+    pub fn thread_pool_prefix(mut self, prefix: &str) -> Self {
+        self.config.pool_prefixes.push(prefix.to_owned());
+        self
+    }
+
+    /// Show each thread pool's workers (see `thread_pool_prefix`) under a
+    /// process of their own, named after the pool, instead of among the
+    /// process's other threads. The viewer collapses a process to one row,
+    /// so a pool of hundreds of threads can be folded away in one click.
+    pub fn thread_pool_groups(mut self, enabled: bool) -> Self {
+        self.config.pool_processes = enabled;
+        self
+    }
+
+    /// How to draw threads started outside Rust, e.g. by a C library, whose
+    /// raw ids would otherwise each get an unnamed track. Kept as they are by
+    /// default; see `ForeignThreads` for how they're told apart.
+    pub fn foreign_threads(mut self, policy: ForeignThreads) -> Self {
+        self.config.foreign_threads = policy;
+        self
+    }
+
+    /// Keep a count of currently-open spans per name or target and emit it
+    /// as counter tracks, giving an in-flight operations view.
+    pub fn count_open_spans(mut self, key: SpanCountKey) -> Self {
+        self.config.count_open_spans = Some(key);
+        self
+    }
+
+    /// Treat events with these messages as a runtime worker parking and
+    /// unparking, and draw the time in between as an idle slice instead.
+    pub fn park_events(mut self, park: &str, unpark: &str) -> Self {
+        self.config.park_events = Some(ParkEvents {
+            park: park.to_owned(),
+            unpark: unpark.to_owned(),
+        });
+        self
+    }
+
+    /// Watch for threads that stay inside a single poll (their outermost
+    /// span) for longer than `threshold`, and emit a prominent warning naming
+    /// the span. Catches accidental blocking calls in async code.
+    pub fn detect_blocking(mut self, threshold: Duration) -> Self {
+        self.config.blocking_threshold = Some(threshold);
+        self
+    }
+
+    /// Every `interval`, write counters of allocations and bytes allocated
+    /// since the last sample, and of live heap bytes. Only counts anything
+    /// if `CountingAlloc` is the global allocator.
+    pub fn allocation_counters(mut self, interval: Duration) -> Self {
+        self.config.alloc_interval = Some(interval);
+        self
+    }
+
+    /// Every `interval`, write each of the process's threads' cumulative CPU
+    /// time, from `/proc/self/task`, as a counter named after the thread.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub fn thread_cpu_counters(mut self, interval: Duration) -> Self {
+        self.config.thread_cpu_interval = Some(interval);
+        self
+    }
+
+    /// Every `interval`, write how many voluntary (blocking) and involuntary
+    /// (preempted) context switches the process's threads made since the
+    /// last sample, to tell the scheduler getting in the way apart from
+    /// slowness in the process itself.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub fn context_switch_counters(mut self, interval: Duration) -> Self {
+        self.config.context_switch_interval = Some(interval);
+        self
+    }
+
+    /// Every `interval`, write how many file descriptors, and how many
+    /// sockets, the process has open, so leaks can be lined up with the
+    /// spans that caused them.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub fn fd_counters(mut self, interval: Duration) -> Self {
+        self.config.fd_interval = Some(interval);
+        self
+    }
+
+    /// At the end of the trace, write a `span_times` metadata record with
+    /// the count, total time and self time (excluding nested spans) of
+    /// closed spans, by name.
+    pub fn span_summary(mut self, enabled: bool) -> Self {
+        self.config.span_summary = enabled;
+        self
+    }
+
+    /// Draw spans (on by default, see `track_style` for where): each time a
+    /// span is entered becomes a slice on the thread's track, and its whole
+    /// lifetime, from creation to close, an async slice with its busy and
+    /// idle time. For a task that's polled many times, the first shows where
+    /// it ran and the second how long it took overall. Both carry the span's
+    /// entered time so far as `total_us` and, less nested spans, `self_us`.
+    pub fn span_slices(mut self, enabled: bool) -> Self {
+        self.config.span_slices = enabled;
+        self
+    }
+
+    /// Compute each event's `cat` with `category` instead of from
+    /// `categories`.
+    pub fn category_fn<F>(mut self, category: F) -> Self
+    where
+        F: Fn(&Metadata) -> String + Send + Sync + 'static,
+    {
+        self.config.category_fn = Some(Arc::new(category));
+        self
+    }
+
+    /// Color events and span slices with the viewer's reserved color name
+    /// (`cname`) `color` picks, e.g. `terrible` for every `ERROR` event.
+    /// `None` leaves the viewer's own choice.
+    pub fn color_fn<F>(mut self, color: F) -> Self
+    where
+        F: Fn(&Metadata) -> Option<&'static str> + Send + Sync + 'static,
+    {
+        self.config.color_fn = Some(Arc::new(color));
+        self
+    }
+
+    /// Add the args `context` puts in the map to every event and span slice
+    /// that carries args, e.g. a tenant or request id the application keeps
+    /// in a task-local or thread-local for the request being served, so
+    /// callsites don't have to repeat them. `context` runs on the thread
+    /// recording the event; fields recorded at the callsite win over its
+    /// args.
+    pub fn context_fn<F>(mut self, context: F) -> Self
+    where
+        F: Fn(&mut Map<String, Value>) + Send + Sync + 'static,
+    {
+        self.config.context_fn = Some(Arc::new(context));
+        self
+    }
+
+    /// What timestamps count from, by default when the subscriber was
+    /// built.
+    pub fn time_base(mut self, base: TimeBase) -> Self {
+        self.config.time_base = base;
+        self
+    }
+
+    /// Let at most `capacity` events wait for the writer thread; past that,
+    /// `policy` applies. Unbounded by default. Dropped events are counted in
+    /// the `chrome_trace_summary` event, and an `events lost` instant event
+    /// is written once the writer catches up.
+    pub fn channel_capacity(mut self, capacity: usize, policy: ChannelPolicy) -> Self {
+        self.config.channel_capacity = Some(capacity);
+        self.config.channel_policy = policy;
+        self
+    }
+
+    /// Keep the subscriber's memory — queued events and tracked spans,
+    /// roughly estimated — under `bytes`. Nearing the limit, new events
+    /// are shed as `policy` says, and at the limit new spans aren't tracked
+    /// (they still work, but don't show up). What was shed is counted in
+    /// the `chrome_trace_summary` event.
+    pub fn memory_limit(mut self, bytes: usize, policy: ShedPolicy) -> Self {
+        self.config.memory_limit = Some(budget::Budget { limit: bytes, policy });
+        self
+    }
+
+    /// Sample events (not spans or counters) to stay under `limit`. Once a
+    /// second the subscriber looks at how many events were recorded and keeps
+    /// one in every so many from then on; each change is written as a
+    /// `sampling_rate` metadata record, and the events sampled out are
+    /// counted in the `chrome_trace_summary` event.
+    pub fn adaptive_sampling(mut self, limit: RateLimit) -> Self {
+        self.config.rate_limit = Some(limit);
+        self
+    }
+
+    /// Cap how much of the trace events whose target or category is under
+    /// `pattern` (e.g. `hyper` or `hyper::*`) can take up, so a chatty
+    /// dependency can't crowd out the application's own events. Events
+    /// over the quota are dropped on the writer thread, and counted by
+    /// pattern in the `chrome_trace_summary` event as `quota_dropped`. An
+    /// event counts against the first quota it matches.
+    pub fn quota(mut self, pattern: &str, quota: Quota) -> Self {
+        self.config.quotas.push((pattern.to_owned(), quota));
+        self
+    }
+
+    /// Draw each task's spans and events in a lane of its own, named after
+    /// the task, instead of on the threads that happened to run it, so a
+    /// task hopping between a runtime's worker threads reads as one piece.
+    pub fn task_lanes(mut self, lanes: TaskLanes) -> Self {
+        self.config.task_lanes = Some(lanes);
+        self
+    }
+
+    /// How span ids, which show up as async slice, flow and object ids, are
+    /// assigned. `SpanIds::ByCallsite` makes them comparable across runs.
+    /// A `ChromeTraceLayer` uses the registry's ids instead.
+    pub fn span_ids(mut self, ids: SpanIds) -> Self {
+        self.config.span_ids = ids;
+        self
+    }
+
+    /// Track at most `max` open spans, forgetting the oldest beyond that, so
+    /// spans that are never closed (leaked, or abandoned futures) can't grow
+    /// the subscriber's state without bound. Each forgotten span is marked
+    /// with a `span evicted` event.
+    pub fn max_open_spans(mut self, max: usize) -> Self {
+        self.config.max_open_spans = Some(max);
+        self
+    }
+
+    /// Like `max_open_spans`, forgetting spans once they've been open for
+    /// `ttl`. Checked at most once a second.
+    pub fn span_ttl(mut self, ttl: Duration) -> Self {
+        self.config.span_ttl = Some(ttl);
+        self
+    }
+
+    /// How far up the viewer draws the line for instant events, by default
+    /// across the whole process.
+    pub fn instant_scope(mut self, scope: InstantScope) -> Self {
+        self.config.instant_scope = scope;
+        self
+    }
+
+    /// Choose which of the tracks `span_slices` draws spans on.
+    pub fn track_style(mut self, style: TrackStyle) -> Self {
+        self.config.track_style = style;
+        self
+    }
+
+    /// Keep a histogram of span lifetimes per span name and write their
+    /// p50/p90/p99 into a `span_latency` metadata record at the end.
+    #[cfg(feature = "hdrhistogram")]
+    pub fn latency_percentiles(mut self, enabled: bool) -> Self {
+        self.config.latency_percentiles = enabled;
+        self
+    }
+
+    /// Attach a short backtrace (`args.backtrace`) to events at `level` or
+    /// more severe.
+    #[cfg(feature = "backtrace")]
+    pub fn backtrace_level(mut self, level: Level) -> Self {
+        self.config.backtrace_level = Some(level);
+        self
+    }
+
+    /// Attach a short backtrace to events whose target is under `prefix`.
+    #[cfg(feature = "backtrace")]
+    pub fn backtrace_target(mut self, prefix: &str) -> Self {
+        self.config.backtrace_targets.push(prefix.to_owned());
+        self
+    }
+
+    /// Also mirror span enter/exit to ATrace begin/end sections via the
+    /// kernel's `trace_marker`, so spans show up in systrace and Perfetto
+    /// captures. Does nothing if `trace_marker` can't be opened.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub fn atrace(mut self, enabled: bool) -> Self {
+        self.config.atrace = enabled;
+        self
+    }
+
+    /// Add a `JsonSink` writing to `writer` in `format`.
+    pub fn json<W: Write + Send + 'static>(self, writer: W, format: JsonFormat) -> Self {
+        self.sink(JsonSink::from_writer(writer).format(format))
+    }
+
+    /// Add a `PerfettoSink` writing a Perfetto protobuf trace to `writer`.
+    #[cfg(feature = "perfetto")]
+    pub fn perfetto<W: Write + Send + 'static>(self, writer: W) -> Self {
+        self.sink(PerfettoSink::new(writer))
+    }
+
+    /// Also write every span, once it closes, to `writer` as Zipkin v2 JSON
+    /// with `service` as its service name, for loading into Zipkin or
+    /// Jaeger. Spans are nested under whichever span was current when they
+    /// were created.
+    pub fn zipkin<W: Write + Send + 'static>(self, writer: W, service: &str) -> Self {
+        self.sink(ZipkinSink::new(writer, service))
+    }
+
+    /// Also mirror spans to `os_signpost` intervals and events to signpost
+    /// events, logged under `subsystem` (e.g. `"com.example.app"`) in the
+    /// Points of Interest category, for Instruments.
+    #[cfg(target_os = "macos")]
+    pub fn signposts(mut self, subsystem: &str) -> Self {
+        self.config.signpost_subsystem = Some(subsystem.to_owned());
+        self
+    }
+
+    /// Builds the subscriber only if `cond` holds, e.g. when an environment
+    /// variable asks for a trace; otherwise the result does nothing.
+    pub fn build_if(self, cond: bool) -> MaybeChromeTraceSubscriber {
+        MaybeChromeTraceSubscriber(if cond { Some(self.build()) } else { None })
+    }
+
+    /// Builds a `tracing-subscriber` layer instead, for applications on the
+    /// `tracing` crates.
+    #[cfg(feature = "tracing")]
+    pub fn build_layer(self) -> ChromeTraceLayer {
+        ChromeTraceLayer { subscriber: self.build() }
+    }
+
+    /// Keep up to `capacity` events (the most recent) while no sink has been
+    /// added, and write them to the first one `ChromeTraceSubscriber::attach`
+    /// adds, e.g. once command line parsing has decided where the trace goes.
+    /// Process and thread names are always kept.
+    pub fn buffer_until_attached(mut self, capacity: usize) -> Self {
+        self.config.backlog = Some(capacity);
+        self
+    }
+
+    /// Measure what recording costs on this machine, to judge how much the
+    /// tracing itself skews the results: at startup, the nanoseconds it
+    /// takes to serialize and queue a typical event are written as
+    /// `chrome_trace_overhead` metadata, and the `chrome_trace_summary`
+    /// event gets the average time the sinks took to write one, as
+    /// `write_ns`.
+    pub fn measure_overhead(mut self, enabled: bool) -> Self {
+        self.config.measure_overhead = enabled;
+        self
+    }
+
+    /// Cut stretches longer than `threshold` with no events down to
+    /// `threshold`, marking each cut with a global `"gap elided"` instant
+    /// event whose `elided_us` arg says how much was left out, so a soak
+    /// test that sat idle overnight is still one navigable timeline. Slices
+    /// spanning a gap are shortened with it.
+    pub fn elide_idle_gaps(mut self, threshold: Duration) -> Self {
+        self.config.idle_gap = Some(threshold);
+        self
+    }
+
+    /// Add an `"activity"` counter track counting the events written in
+    /// each `bucket`-long stretch of time, e.g. 10ms, to find the busy
+    /// periods of a long trace before zooming in. An event counts towards
+    /// the bucket it ended in; empty stretches drop the counter to zero.
+    pub fn activity_track(mut self, bucket: Duration) -> Self {
+        self.config.activity_bucket = Some(bucket);
+        self
+    }
+
+    /// Fold instant events that repeat exactly — same callsite, same args —
+    /// within the same `window`-long stretch of time into the first of them,
+    /// with a `dedup_count` arg saying how many there were, so a retry loop
+    /// logging the same failure doesn't flood the trace. Each event is held
+    /// back until its window has passed, so it may be written after events
+    /// recorded later. The `chrome_trace_summary` event gets the number
+    /// folded away as `deduplicated`.
+    pub fn dedup_window(mut self, window: Duration) -> Self {
+        self.config.dedup_window = Some(window);
+        self
+    }
+
+    /// Replace runs of slices shorter than `threshold` with the same name,
+    /// one after the other on the same thread with gaps shorter than
+    /// `threshold`, with one slice covering the run, whose args are
+    /// `summarized_count` and the slices' total `summarized_dur_us`, so
+    /// thousands of tiny spans don't make the timeline unusable. Only
+    /// complete (`"X"`) slices are summarized, and a run is held back until
+    /// something else happens on its thread or it's `threshold` old. The
+    /// `chrome_trace_summary` event gets the number of slices replaced as
+    /// `summarized_spans`.
+    pub fn summarize_short_spans(mut self, threshold: Duration) -> Self {
+        self.config.short_span_threshold = Some(threshold);
+        self
+    }
+
+    /// Count, per callsite, the events left out by sampling, by filters set
+    /// through `ChromeTraceHandle::set_filter` or `only_within`, and while
+    /// paused, and add the table to the `chrome_trace_summary` event as
+    /// `suppressed_by_callsite`, to judge how representative the trace is.
+    /// Callsites turned away by the filter the subscriber was built with
+    /// never reach it and aren't counted.
+    pub fn callsite_drop_stats(mut self, enabled: bool) -> Self {
+        self.config.callsite_drops = enabled;
+        self
+    }
+
+    /// Keep track of every thread written to and, when the trace is
+    /// finished, add a `thread_name` (`thread <tid>`) and
+    /// `thread_sort_index` for any that never got one, e.g. because their
+    /// events were recorded on paths that don't name threads. Each thread
+    /// also gets a `thread_lifetime` metadata record with the `first_us`
+    /// and `last_us` timestamps it was seen at.
+    pub fn backfill_thread_metadata(mut self, enabled: bool) -> Self {
+        self.config.backfill_threads = enabled;
+        self
+    }
+
+    /// Once the trace is finished, hand `report` the ways to open the file
+    /// at `path` in a trace viewer. `path` isn't opened or checked: it
+    /// should be where one of the sinks writes.
+    pub fn viewer_links<P, F>(mut self, path: P, report: F) -> Self
+    where
+        P: Into<PathBuf>,
+        F: FnOnce(&ViewerLinks) + Send + 'static,
+    {
+        self.viewer_links = Some((path.into(), Box::new(report)));
+        self
+    }
+
+    /// Like `viewer_links`, printing them to stdout.
+    pub fn print_viewer_links<P: Into<PathBuf>>(self, path: P) -> Self {
+        self.viewer_links(path, |links| println!("{}", links))
+    }
+
+    /// Don't start the writer thread, and so don't touch any sink, until
+    /// the first span or event is recorded, for programs that install the
+    /// subscriber just in case. One that never records anything writes
+    /// nothing at all, not even an empty trace. Pair it with
+    /// `JsonSink::lazy_file` to not create the file either. Samplers and
+    /// monitors don't start until then either.
+    pub fn lazy(mut self, lazy: bool) -> Self {
+        self.config.lazy = lazy;
+        self
+    }
+
+    /// Send every event through the subscriber's one locked sender instead
+    /// of a per-thread one, to compare the two in `benches/contention.rs`.
+    #[doc(hidden)]
+    pub fn shared_sender(mut self, shared: bool) -> Self {
+        self.config.shared_sender = shared;
+        self
+    }
+
+    /// Have `build_with_handle` start with capture paused, so nothing is
+    /// written until `ChromeTraceHandle::resume`.
+    pub fn start_paused(mut self, paused: bool) -> Self {
+        self.config.start_paused = paused;
+        self
+    }
+
+    /// Builds the subscriber along with a handle for pausing it, changing
+    /// its filter and switching its output while it runs. Start paused with
+    /// `start_paused`.
+    pub fn build_with_handle(self) -> (ChromeTraceSubscriber, ChromeTraceHandle) {
+        let paused = self.config.start_paused;
+        let mut subscriber = self.build();
+        let control = Arc::new(handle::Control::default());
+        let handle = ChromeTraceHandle {
+            control: control.clone(),
+            tx: subscriber.tx.clone(),
+            stats: subscriber.stats.clone(),
+            start: subscriber.start,
+        };
+        if paused {
+            handle.pause();
+        }
+        subscriber.control = Some(control);
+        (subscriber, handle)
+    }
+
+    /// Like `build`, failing instead of making the best of settings that
+    /// don't work as given: a filter directive that doesn't parse, a zero
+    /// sampling interval, a quota over 100%, or a `TimeBase` this platform
+    /// can't provide.
+    pub fn try_build(self) -> Result<ChromeTraceSubscriber, Error> {
+        self.config.validate()?;
+        Ok(self.build())
+    }
+
+    /// Builds the subscriber, making the best of settings `try_build` would
+    /// refuse. The first of those is reported in the trace, as a global
+    /// `invalid configuration` instant at the start of every output.
+    pub fn build(mut self) -> ChromeTraceSubscriber {
+        let invalid = self.config.validate().err();
+        #[cfg(not(target_arch = "wasm32"))]
+        let (tx, rx) = channel::channel(self.config.channel_capacity, self.config.channel_policy);
+        #[cfg(target_arch = "wasm32")]
+        let (tx, inline) = channel::inline();
+        let start = Instant::now();
+        let epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(in_micros)
+            .unwrap_or(0);
+        let sinks = self.sinks.len() + self.routed.len();
+        let mut outputs = writer::Outputs::new(self.sinks, self.routed, self.transforms);
+        outputs.anonymizer = self.anonymizer;
+        outputs.start = start;
+        if !self.arg_shape.is_noop() {
+            outputs.arg_shape = Some(self.arg_shape);
+        }
+        let base = match self.config.time_base {
+            TimeBase::SubscriberStart => 0,
+            TimeBase::UnixEpoch => epoch,
+            TimeBase::ProcessStart => process_age().map_or(0, in_micros),
+            TimeBase::At(base) => in_micros(start.saturating_duration_since(base)),
+        };
+        let ts_offset = writer::shift(base, self.config.ts_offset);
+        outputs.ts_offset = (base as i64).saturating_add(self.config.ts_offset);
+        outputs.ts_map = self.ts_map;
+        outputs.sizes = self.config.max_event_size.map(sizes::EventSizes::new);
+        if self.config.measure_overhead {
+            outputs.write_time = Some(Default::default());
+        }
+        outputs.backlog = self.config.backlog.map(writer::Backlog::new);
+        if !self.config.quotas.is_empty() {
+            outputs.quotas = Some(quota::Quotas::new(&self.config.quotas));
+        }
+        outputs.gaps = self.config.idle_gap.map(|gap| gaps::GapElider::new(in_micros(gap)));
+        if self.config.backfill_threads {
+            outputs.threads = Some(Default::default());
+        }
+        outputs.heatmap = self.config.activity_bucket.map(|bucket| heatmap::Heatmap::new(in_micros(bucket)));
+        outputs.coalesce = self
+            .config
+            .short_span_threshold
+            .map(|threshold| coalesce::Coalesce::new(in_micros(threshold)));
+        outputs.dedup = self.config.dedup_window.map(|window| dedup::Dedup::new(in_micros(window)));
+        self.config.export_spans = outputs.wants_spans();
+        let checkpoint_every = self.config.checkpoint_every;
+        let mut stats = stats::Stats::default();
+        if self.config.callsite_drops {
+            stats.track_callsites();
+        }
+        let stats = Arc::new(stats);
+        let polls = self
+            .config
+            .blocking_threshold
+            .map(|threshold| Arc::new(blocking::Polls::new(threshold)));
+        let alive = Arc::new(());
+        // Started along with the writer thread, so a lazy subscriber has
+        // nothing filling the channel before then.
+        let mut monitors: Vec<Box<dyn FnOnce() + Send>> = Vec::new();
+        if let Some(ref polls) = polls {
+            let (polls, tx, stats) = (Arc::downgrade(polls), tx.clone(), stats.clone());
+            monitors.push(Box::new(move || blocking::spawn_monitor(polls, tx, stats, start)));
+        }
+        let mut sample_every = |interval, sample: sampler::Sample| {
+            let (alive, tx, stats) = (Arc::downgrade(&alive), tx.clone(), stats.clone());
+            monitors.push(Box::new(move || sampler::spawn(alive, interval, sample, tx, stats, start)));
+        };
+        if let Some(interval) = self.config.alloc_interval {
+            let mut allocs = alloc::AllocSampler::default();
+            sample_every(interval, Box::new(move |ts| allocs.sample(ts)));
+        }
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            if let Some(interval) = self.config.thread_cpu_interval {
+                sample_every(interval, Box::new(procfs::thread_cpu));
+            }
+            if let Some(interval) = self.config.context_switch_interval {
+                let mut switches = procfs::ContextSwitches::default();
+                sample_every(interval, Box::new(move |ts| switches.sample(ts)));
+            }
+            if let Some(interval) = self.config.fd_interval {
+                sample_every(interval, Box::new(procfs::open_fds));
+            }
+        }
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        let atrace = if self.config.atrace {
+            atrace::Marker::open().ok()
+        } else {
+            None
+        };
+        #[cfg(target_os = "macos")]
+        let signposts = self
+            .config
+            .signpost_subsystem
+            .as_ref()
+            .map(|subsystem| signpost::Signposts::new(subsystem));
+        let span_times = Arc::new(span::TimeSummary::default());
+        #[cfg(feature = "hdrhistogram")]
+        let latencies = Arc::new(latency::Latencies::default());
+        let closing = {
+            let span_summary = self.config.span_summary;
+            let span_times = span_times.clone();
+            #[cfg(feature = "hdrhistogram")]
+            let latencies = (self.config.latency_percentiles, latencies.clone());
+            let stats = stats.clone();
+            Box::new(move |reason: ShutdownReason| {
+                let mut closing = Vec::new();
+                if span_summary {
+                    let times = TraceEvent::metadata("span_times", span_times.to_args());
+                    closing.push(Message::Event(times, Route::All));
+                }
+                #[cfg(feature = "hdrhistogram")]
+                {
+                    if latencies.0 {
+                        let percentiles = TraceEvent::metadata("span_latency", latencies.1.to_args());
+                        closing.push(Message::Event(percentiles, Route::All));
+                    }
+                }
+                let run = start.elapsed();
+                let mut summary = TraceEvent::new("I", SUMMARY_EVENT.to_owned(), String::new(), in_micros(run));
+                summary.scope = Some("g");
+                summary.args = stats.summary(run);
+                summary.args.insert("reason".to_owned(), Value::from(reason.name()));
+                closing.push(Message::Event(summary, Route::Default));
+                closing
+            })
+        };
+        let tx = Arc::new(Mutex::new(tx));
+        let shutdown = Arc::new(guard::Shutdown::new(tx.clone(), stats.clone(), closing));
+        if let Some((path, report)) = self.viewer_links.take() {
+            shutdown.on_finished(Box::new(move || report(&ViewerLinks::new(path))));
+        }
+        let subscriber = ChromeTraceSubscriber {
+            instance: NEXT_INSTANCE.fetch_add(1, Ordering::Relaxed),
+            start,
+            epoch,
+            // Span ids can't be 0.
+            next_span: Arc::new(AtomicUsize::new(10)),
+            lanes: self.config.task_lanes.map(|_| lanes::Lanes::default()),
+            foreign: foreign::Foreign::new(self.config.foreign_threads),
+            callsite_ids: match self.config.span_ids {
+                SpanIds::Sequential => None,
+                SpanIds::ByCallsite => Some(ids::CallsiteIds::default()),
+            },
+            next_flow: AtomicUsize::new(1),
+            last_eviction: Mutex::new(start),
+            tx,
+            stats,
+            tasks: self.config.runtimes.iter().map(|_| Default::default()).collect(),
+            sampler: self.config.rate_limit.map(rate::Sampler::new),
+            config: self.config,
+            spans: span::Registry::default(),
+            pools: pool::Pools::default(),
+            open_spans: Mutex::new(HashMap::new()),
+            polls,
+            _alive: alive,
+            span_times,
+            #[cfg(feature = "hdrhistogram")]
+            latencies,
+            shutdown,
+            control: None,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            atrace,
+            #[cfg(target_os = "macos")]
+            signposts,
+        };
+        outputs.startup = subscriber.startup_records(sinks, epoch, ts_offset);
+        if let Some(error) = invalid {
+            outputs.startup.push((config_warning(&error), Route::Default));
+        }
+        let writer_stats = subscriber.stats.clone();
+        let writer = writer::Writer::new(outputs, checkpoint_every, writer_stats);
+        subscriber.shutdown.arm(Box::new(move || {
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                let writer = thread::spawn(move || writer::run(rx, writer));
+                for monitor in monitors {
+                    monitor();
+                }
+                writer
+            }
+            // Without threads, the monitors have nothing to run on, which
+            // `validate` reports.
+            #[cfg(target_arch = "wasm32")]
+            {
+                drop(monitors);
+                inline.start(writer);
+                inline
+            }
+        }));
+        if !subscriber.config.lazy {
+            subscriber.shutdown.start();
+        }
+        subscriber
+    }
 }
 
-impl ChromeTraceSubscriber {
-    pub fn new(writer: File) -> Self {
-        let (tx, rx) = mpsc::channel();
-        thread::spawn(move || {
-            writer_thread(rx, writer)
-        });
-        ChromeTraceSubscriber {
-            start: Instant::now(),
-            next_span: Arc::new(AtomicUsize::new(0)),
-            tx: Arc::new(Mutex::new(tx)),
+impl ChromeTraceSubscriber {
+    /// Writes a JSON array of events to `writer`, e.g. a file, a socket,
+    /// stdout or a `Vec<u8>`.
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        ChromeTraceBuilder::new().sink(JsonSink::from_writer(writer)).build()
+    }
+
+    /// Keeps the whole trace in memory instead, for tests: run the
+    /// instrumented code, call `flush` or `finish`, then look at
+    /// `EventBuffer::events` or `EventBuffer::query`.
+    pub fn in_memory() -> (Self, EventBuffer) {
+        let buffer = EventBuffer::unbounded();
+        let subscriber = ChromeTraceBuilder::new().sink(buffer.sink()).build();
+        (subscriber, buffer)
+    }
+
+    /// Creates (or truncates) the file at `path` and writes the trace there.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = File::create(path)?;
+        Ok(ChromeTraceBuilder::new().sink(JsonSink::new(file)).build())
+    }
+
+    /// Blocks until everything recorded so far has been written out and the
+    /// sinks flushed.
+    pub fn flush(&self) {
+        self.shutdown.flush()
+    }
+
+    /// Ends the trace: writes the closing records, finishes every sink (e.g.
+    /// closing the JSON array) and waits for the writer thread to exit.
+    /// Happens on drop too; anything recorded afterwards is dropped.
+    pub fn finish(&self) {
+        self.finish_because(ShutdownReason::Finish)
+    }
+
+    /// Like `finish`, recording `reason` as why the trace ended, e.g.
+    /// `ShutdownReason::Signal` from a signal handler's cleanup.
+    pub fn finish_because(&self, reason: ShutdownReason) {
+        self.shutdown.finish(reason)
+    }
+
+    /// Draws an arrow labeled `label` from span `from` to span `to`, e.g.
+    /// from a request to each subtask it fans out to, or from each subtask
+    /// to the span that gathers their results. Unlike `follows_from` it says
+    /// why the spans are related. The arrow starts now and ends when `to` is
+    /// next entered.
+    pub fn link(&self, from: &Id, to: &Id, label: &str) {
+        self.start_flow(from.into_u64(), to.into_u64(), LINK_CATEGORY, label.to_owned())
+    }
+
+    /// Starts a flow from span `from` now, to end when span `to` is next
+    /// entered.
+    fn start_flow(&self, from: u64, to: u64, cat: &'static str, name: String) {
+        let flow = self.next_flow.fetch_add(1, Ordering::Relaxed) as u64;
+        let pending = self.spans.with(to, |span| span.flows.push((flow, cat, name.clone())));
+        if pending.is_some() {
+            let mut start = TraceEvent::new("s", name, cat.to_owned(), self.now());
+            start.id = Some(flow);
+            start.span = Some(from);
+            self.send(start);
+        }
+    }
+
+    /// Write to `sink` from now on, in place of the sinks added with
+    /// `ChromeTraceBuilder::sink` (which are finished), starting with the
+    /// process and thread names and whatever `buffer_until_attached` held.
+    pub fn attach<S: TraceSink + 'static>(&self, sink: S) {
+        self.send_message(Message::SetOutput(Box::new(sink), None))
+    }
+
+    /// Cut the trace here and carry on in a new JSON file at `new_path`,
+    /// e.g. from an admin endpoint at an interesting boundary. Events
+    /// recorded before the call, even those still queued, go to the current
+    /// output, which ends with a summary event whose `reason` is
+    /// `"rotation"`, naming the new file; everything after goes to the new
+    /// file, which starts with the process and thread names. Fails, leaving
+    /// the output alone, if `new_path` can't be created.
+    pub fn rotate<P: AsRef<Path>>(&self, new_path: P) -> Result<(), Error> {
+        let rotation = rotation(new_path.as_ref(), self.now())?;
+        self.send_message(rotation);
+        Ok(())
+    }
+
+    /// A handle that finishes the trace when dropped, for a subscriber that
+    /// never is itself, such as the global default.
+    pub fn flush_guard(&self) -> FlushGuard {
+        FlushGuard {
+            shutdown: self.shutdown.clone(),
+        }
+    }
+
+    /// Attach labels (e.g. a role or shard id) that chrome://tracing shows
+    /// next to the process name.
+    pub fn set_process_labels(&self, labels: &[&str]) {
+        let mut args = Map::new();
+        args.insert("labels".to_owned(), Value::from(labels.join(",")));
+        self.send(TraceEvent::metadata("process_labels", args));
+    }
+
+    /// Have the subscriber report its own problems — dropped events, failed
+    /// or slow sink writes — to `logger`. It's called from whichever thread
+    /// hit the problem, including the writer thread, and must not itself
+    /// emit tracing events. By default nothing is reported.
+    pub fn set_internal_logger<F: Fn(&str) + Send + Sync + 'static>(&self, logger: F) {
+        self.stats.set_logger(Arc::new(logger));
+    }
+
+    /// Embed captured ftrace/atrace text in the trace's `systemTraceEvents`,
+    /// so kernel scheduling data shows up in the same viewer session. Only
+    /// sinks writing the JSON Object Format (see `JsonSink::object`) keep it.
+    pub fn add_system_trace_events(&self, text: String) {
+        self.send_message(Message::SystemTrace(text))
+    }
+
+    /// Additionally write everything recorded inside `span`, including spans
+    /// created inside it from now on, to `sink` — e.g. a small standalone
+    /// file for one problematic request. The sink is finished when the span
+    /// closes.
+    pub fn capture_span<S: TraceSink + 'static>(&self, span: &Id, sink: S) {
+        let id = span.into_u64();
+        if self.spans.with(id, |span| span.capture = Some(id)).is_some() {
+            self.send_message(Message::StartCapture(id, Box::new(sink)));
+        }
+    }
+
+    /// For launching child processes whose traces are merged into this one,
+    /// see `ParentCollector`.
+    #[cfg(all(unix, feature = "serde_json"))]
+    pub fn collector(&self) -> ParentCollector {
+        ParentCollector {
+            tx: self.tx.clone(),
+            stats: self.stats.clone(),
+            shutdown: self.shutdown.clone(),
+            epoch: self.epoch,
+        }
+    }
+
+    /// Mark the current thread as worker `index` of thread pool `pool`. Meant
+    /// to be called from a pool's start hook, e.g. rayon's
+    /// `ThreadPoolBuilder::start_handler`, for pools whose threads aren't
+    /// named.
+    pub fn register_pool_thread(&self, pool: &str, index: usize) {
+        let joined = self.pools.join(pool, index);
+        self.name_pool_thread(joined);
+    }
+
+    /// Report how many jobs are queued on `pool`, as a counter track.
+    pub fn record_pool_pending(&self, pool: &str, pending: usize) {
+        let mut event = TraceEvent::new("C", format!("{} jobs", pool), String::new(), self.now());
+        event.args.insert("pending".to_owned(), Value::from(pending));
+        self.send(event);
+    }
+
+    /// Write a clock sync marker with id `name`. A tool recording at the
+    /// same time (a browser, a device trace) writes a marker with the same
+    /// id, and trace merging lines the two captures up on it.
+    pub fn emit_clock_sync(&self, name: &str) {
+        let mut event = TraceEvent::new("c", "clock_sync".to_owned(), String::new(), self.now());
+        event.args.insert("sync_id".to_owned(), Value::from(name));
+        self.send(event);
+    }
+
+    /// Mark a phase of the run, like `warmup done` or `measurement start`,
+    /// with a global instant event in the `annotation` category that the
+    /// viewer draws across every track, for later analysis to cut on.
+    pub fn annotate(&self, label: &str) {
+        let now = self.now();
+        let mut event = TraceEvent::new("I", label.to_owned(), "annotation".to_owned(), now);
+        event.scope = Some("g");
+        if self.config.annotation_track {
+            let mut marker = event.clone();
+            marker.scope = Some("t");
+            marker.tid = ANNOTATION_TID;
+            self.send(marker);
+        }
+        self.send(event);
+    }
+
+    /// The innermost span the current thread is inside, if any. It's kept
+    /// by `enter` and `exit` in a thread-local stack, so this is cheap.
+    pub fn current_span(&self) -> Option<Id> {
+        span::current().map(Id::from_u64)
+    }
+
+    /// Write one sample of counter `name`.
+    pub fn record_counter(&self, name: &str, value: f64) {
+        self.record_counters(name, &[("value", value)])
+    }
+
+    /// Write one sample of counter `name` with several series, e.g.
+    /// `&[("allocated", a), ("freed", f)]`, which the viewer stacks on a
+    /// single track. Later samples should name the same series.
+    pub fn record_counters(&self, name: &str, series: &[(&str, f64)]) {
+        let mut event = TraceEvent::new("C", name.to_owned(), String::new(), self.now());
+        for &(series, value) in series {
+            event.args.insert(series.to_owned(), Value::from(value));
+        }
+        self.send(event);
+    }
+
+    fn name_pool_thread(&self, joined: pool::Joined) {
+        if self.config.pool_processes && joined.first {
+            let pid = pool::pool_pid(joined.pool);
+            let mut args = Map::new();
+            let name = format!("{}: {}", process_name(), self.pools.name(joined.pool));
+            args.insert("name".to_owned(), Value::from(name));
+            let mut name = TraceEvent::metadata("process_name", args);
+            name.pid = pid;
+            let mut args = Map::new();
+            args.insert("sort_index".to_owned(), Value::from(joined.pool + 1));
+            let mut sort = TraceEvent::metadata("process_sort_index", args);
+            sort.pid = pid;
+            self.send(name);
+            self.send(sort);
+        }
+        let mut args = Map::new();
+        args.insert("name".to_owned(), Value::from(joined.thread_name));
+        self.send(TraceEvent::metadata("thread_name", args));
+        let mut args = Map::new();
+        args.insert("sort_index".to_owned(), Value::from(joined.sort_index));
+        self.send(TraceEvent::metadata("thread_sort_index", args));
+    }
+
+
+    /// Names the current thread the first time this subscriber sees it,
+    /// sorting the main thread above the rest.
+    fn name_thread(&self) {
+        if NAMED_FOR.with(|named| named.replace(self.instance)) == self.instance {
+            return;
+        }
+        let thread = thread::current();
+        if let Some(name) = thread.name() {
+            let mut args = Map::new();
+            args.insert("name".to_owned(), Value::from(name));
+            self.send(TraceEvent::metadata("thread_name", args));
+        }
+        let mut args = Map::new();
+        let sort_index = if thread.name() == Some("main") { -1 } else { 0 };
+        args.insert("sort_index".to_owned(), Value::from(sort_index));
+        self.send(TraceEvent::metadata("thread_sort_index", args));
+    }
+
+    /// The records a trace starts with: the process name, the annotation
+    /// track's, the wall clock, the overhead estimate and the config banner.
+    /// They're handed to the writer thread directly rather than sent, so a
+    /// subscriber that isn't writing yet can't block on or drop them.
+    fn startup_records(&self, sinks: usize, epoch: u64, ts_offset: u64) -> Vec<(TraceEvent, Route)> {
+        let mut records = Vec::new();
+        let mut args = Map::new();
+        args.insert("name".to_owned(), Value::from(process_name()));
+        records.push(TraceEvent::metadata("process_name", args));
+        if self.config.annotation_track {
+            let mut args = Map::new();
+            args.insert("name".to_owned(), Value::from("annotations"));
+            let mut name = TraceEvent::metadata("thread_name", args);
+            name.tid = ANNOTATION_TID;
+            let mut args = Map::new();
+            args.insert("sort_index".to_owned(), Value::from(-2));
+            let mut sort = TraceEvent::metadata("thread_sort_index", args);
+            sort.tid = ANNOTATION_TID;
+            records.push(name);
+            records.push(sort);
+        }
+        records.push(TraceEvent::metadata(WALL_CLOCK_EVENT, wall_clock(epoch, ts_offset)));
+        if self.config.measure_overhead {
+            let costs = overhead::calibrate(self.config.channel_capacity.is_some());
+            records.push(TraceEvent::metadata(overhead::OVERHEAD_EVENT, costs));
+        }
+        let mut banner = TraceEvent::new("I", CONFIG_EVENT.to_owned(), String::new(), 0);
+        banner.scope = Some("g");
+        banner.args = self.config.to_args();
+        banner.args.insert("sinks".to_owned(), Value::from(sinks));
+        records.push(banner);
+        records
+            .into_iter()
+            .map(|event| {
+                let route = if event.ph == "M" { Route::All } else { Route::Default };
+                (event, route)
+            })
+            .collect()
+    }
+
+    fn pool_worker(&self) -> Option<usize> {
+        let (pool, joined) = self.pools.current(&self.config.pool_prefixes);
+        if let Some(joined) = joined {
+            self.name_pool_thread(joined);
+        }
+        pool
+    }
+
+    /// A pool worker counts as active while it's inside any span.
+    fn pool_busy(&self, busy: bool) {
+        if let Some(pool) = self.pool_worker() {
+            let active = self.pools.set_busy(pool, busy);
+            let name = format!("{} workers", self.pools.name(pool));
+            let mut event = TraceEvent::new("C", name, String::new(), self.now());
+            event.args.insert("active".to_owned(), Value::from(active));
+            self.send(event);
+        }
+    }
+
+    fn open_span_key(&self, span: &span::SpanState) -> Option<String> {
+        self.config.count_open_spans.map(|key| match key {
+            SpanCountKey::Name => span.name.to_owned(),
+            SpanCountKey::Target => span.target.to_owned(),
+        })
+    }
+
+    fn count_open_span(&self, key: String, opened: bool) {
+        let open = {
+            let mut counts = self.open_spans.lock().unwrap();
+            let count = counts.entry(key.clone()).or_insert(0);
+            if opened {
+                *count += 1;
+            } else {
+                *count = count.saturating_sub(1);
+            }
+            *count
+        };
+        let mut event = TraceEvent::new("C", format!("open: {}", key), String::new(), self.now());
+        event.args.insert("spans".to_owned(), Value::from(open));
+        self.send(event);
+    }
+
+    /// Turns a park/unpark event into the start or end of an idle slice,
+    /// returning whether it was one.
+    fn park_event(&self, message: Option<&str>, ts: u64) -> bool {
+        let (park_events, message) = match (&self.config.park_events, message) {
+            (Some(park_events), Some(message)) => (park_events, message),
+            _ => return false,
+        };
+        let parking = if message == park_events.park {
+            true
+        } else if message == park_events.unpark {
+            false
+        } else {
+            return false;
+        };
+        // Drop unbalanced events, so a missed park doesn't close some other
+        // slice on this thread.
+        if PARKED.with(|parked| parked.replace(parking)) != parking {
+            let mut event = TraceEvent::new(
+                if parking { "B" } else { "E" },
+                "idle".to_owned(),
+                "park".to_owned(),
+                ts,
+            );
+            event.cname = Some("thread_state_sleeping".to_owned());
+            self.send(event);
+        }
+        true
+    }
+
+    fn in_selected_span(&self) -> bool {
+        span::current()
+            .and_then(|id| self.spans.with(id, |span| span.selected))
+            .unwrap_or(false)
+    }
+
+    fn now(&self) -> u64 {
+        in_micros(self.start.elapsed())
+    }
+
+    /// Emits the scheduler counter for runtime preset `runtime`.
+    fn task_counter(&self, runtime: usize) {
+        let counts = &self.tasks[runtime];
+        let name = format!("{} tasks", self.config.runtimes[runtime].name());
+        let mut event = TraceEvent::new("C", name, String::new(), self.now());
+        event.args.insert("live".to_owned(), Value::from(counts.live.load(Ordering::Relaxed)));
+        event.args.insert("polling".to_owned(), Value::from(counts.polling.load(Ordering::Relaxed)));
+        self.send(event);
+    }
+
+    /// Writes the slice for an entry into span `id` that just ended. A
+    /// complete event rather than a begin/end pair keeps the nesting right
+    /// even when spans aren't exited in the order they were entered.
+    fn span_slice(&self, id: u64, elapsed: Duration) {
+        let slice = self.spans.with(id, |span| {
+            let mut args = Map::new();
+            if span.args {
+                args = span.fields.clone();
+                for (key, value) in &span.location {
+                    args.entry(key.clone()).or_insert_with(|| value.clone());
+                }
+                // Both so far, which for the last entry is the whole span.
+                args.insert("total_us".to_owned(), Value::from(in_micros(span.busy)));
+                args.insert("self_us".to_owned(), Value::from(in_micros(span.self_time())));
+            }
+            (span.name, span.target, args, span.cname.clone(), span.args)
+        });
+        let (name, target, mut args, cname, with_args) = match slice {
+            Some(slice) => slice,
+            None => return,
+        };
+        if with_args {
+            self.config.add_context(&mut args);
+        }
+        let dur = in_micros(elapsed);
+        let mut event = TraceEvent::new("X", name, target, self.now().saturating_sub(dur));
+        event.dur = Some(dur);
+        event.args = args;
+        event.target = target;
+        event.span = Some(id);
+        event.cname = cname;
+        self.send(event);
+    }
+
+    /// Writes an entry into span `id` that just ended as an async slice
+    /// nested in the span's lifetime (see `span_lifetime`), which shares its
+    /// id and category.
+    fn busy_slice(&self, id: u64, elapsed: Duration) {
+        let target = match self.spans.with(id, |span| span.target) {
+            Some(target) => target,
+            None => return,
+        };
+        let now = self.now();
+        let mut begin = TraceEvent::new("b", "busy", target, now.saturating_sub(in_micros(elapsed)));
+        begin.id = Some(id);
+        begin.target = target;
+        begin.span = Some(id);
+        let mut end = begin.clone();
+        end.ph = "e";
+        end.ts = now;
+        self.send(begin);
+        self.send(end);
+    }
+
+    /// Writes the async slice covering a closed span's whole lifetime.
+    fn span_lifetime(&self, id: u64, closed: &span::SpanState) {
+        let lifetime = closed.created.elapsed();
+        let idle = if closed.busy < lifetime { lifetime - closed.busy } else { Duration::from_secs(0) };
+        let start = in_micros(closed.created.duration_since(self.start));
+        let mut begin = TraceEvent::new("b", closed.name, closed.target, start);
+        begin.id = Some(id);
+        begin.target = closed.target;
+        let mut end = begin.clone();
+        if closed.args {
+            begin.args = closed.fields.clone();
+        }
+        end.ph = "e";
+        end.ts = self.now();
+        end.args.insert("busy_us".to_owned(), Value::from(in_micros(closed.busy)));
+        end.args.insert("idle_us".to_owned(), Value::from(in_micros(idle)));
+        end.args.insert("total_us".to_owned(), Value::from(in_micros(closed.busy)));
+        end.args.insert("self_us".to_owned(), Value::from(in_micros(closed.self_time())));
+        self.send(begin);
+        self.send(end);
+    }
+
+    /// Forgets spans past `max_open_spans` or `span_ttl`.
+    fn evict_spans(&self) {
+        let max = self.config.max_open_spans;
+        let ttl = self.config.span_ttl;
+        if max.is_none() && ttl.is_none() {
+            return;
+        }
+        let ttl = ttl.filter(|_| {
+            let mut last = self.last_eviction.lock().unwrap();
+            let due = last.elapsed() >= Duration::from_secs(1);
+            if due {
+                *last = Instant::now();
+            }
+            due
+        });
+        if max.is_none_or(|max| self.spans.len() <= max) && ttl.is_none() {
+            return;
+        }
+        for (id, evicted) in self.spans.evict(max, ttl) {
+            if evicted.capture == Some(id) {
+                self.send_message(Message::EndCapture(id));
+            }
+            if let Some(key) = self.open_span_key(&evicted) {
+                self.count_open_span(key, false);
+            }
+            if let Some(runtime) = evicted.runtime {
+                self.tasks[runtime].live.fetch_sub(1, Ordering::Relaxed);
+                self.task_counter(runtime);
+            }
+            let mut event = TraceEvent::new("I", "span evicted".to_owned(), "chrome_trace".to_owned(), self.now());
+            event.scope = Some("g");
+            event.args.insert("span".to_owned(), Value::from(evicted.name));
+            event.args.insert("id".to_owned(), Value::from(id));
+            event.args.insert("age_us".to_owned(), Value::from(in_micros(evicted.created.elapsed())));
+            self.send(event);
+        }
+    }
+
+    /// Writes an object event for span `id`; snapshots (`O`) carry its
+    /// fields.
+    fn object_event(&self, ph: &'static str, id: u64, span: &span::SpanState) {
+        let mut event = TraceEvent::new(ph, span.name, span.target, self.now());
+        event.id = Some(id);
+        event.target = span.target;
+        if ph == "O" {
+            event.args.insert("snapshot".to_owned(), Value::Object(span.fields.clone()));
+        }
+        self.send(event);
+    }
+
+    fn callsite_registered(&self, meta: &Metadata, enabled: bool) {
+        let mut event = TraceEvent::new("I", "callsite registered".to_owned(), "chrome_trace".to_owned(), self.now());
+        event.scope = Some("t");
+        event.args.insert("name".to_owned(), Value::from(meta.name()));
+        event.args.insert("target".to_owned(), Value::from(meta.target()));
+        add_location(&mut event.args, meta);
+        event.args.insert("enabled".to_owned(), Value::from(enabled));
+        self.send(event);
+    }
+
+    fn send(&self, event: TraceEvent) {
+        let route = if event.ph == "M" { Route::All } else { Route::Default };
+        self.send_message(Message::Event(event, route))
+    }
+
+    fn send_for_target(&self, event: TraceEvent, target: &str) {
+        let capture = span::current()
+            .and_then(|id| self.spans.with(id, |span| span.capture))
+            .and_then(|capture| capture);
+        if let Some(capture) = capture {
+            self.send_message(Message::Captured(event.clone(), capture));
         }
+        let route = self.config.route(target);
+        self.send_message(Message::Event(event, route))
     }
-}
 
-fn writer_thread(rx: Receiver<Message>, mut writer: File) {
-    drop(writeln!(writer, "["));
-    while let Ok(msg) = rx.recv() {
-        match msg {
-            Message::Done => {
-                drop(writeln!(writer, "]"));
-                break;
+    fn send_message(&self, mut msg: Message) {
+        if self.paused() {
+            if let Message::Event(ref event, _) | Message::Captured(ref event, _) = msg {
+                if event.ph != "M" {
+                    return;
+                }
+            }
+        }
+        if self.config.pool_processes {
+            if let Message::Event(ref mut event, _) | Message::Captured(ref mut event, _) = msg {
+                // Leave events on made-up tracks, like annotations, where
+                // they are.
+                if event.tid == event::tid() {
+                    if let Some(pool) = pool::worker_of() {
+                        event.pid = pool::pool_pid(pool);
+                    }
+                }
             }
-            Message::Event(val) => {
-                drop(serde_json::to_writer(&writer, &val));
-                // Add a trailing comma because we're writing a JSON array.
-                drop(writeln!(writer, ","));
+        }
+        if let Some(ref foreign) = self.foreign {
+            if let Message::Event(ref mut event, _) | Message::Captured(ref mut event, _) = msg {
+                if event.tid == event::tid() {
+                    let worker = pool::worker_of().is_some();
+                    if let Some((tid, records)) = foreign.track(self.instance, worker) {
+                        event.tid = tid;
+                        for record in records {
+                            self.send(record);
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(ref lanes) = self.lanes {
+            if let Message::Event(ref mut event, _) | Message::Captured(ref mut event, _) = msg {
+                self.move_to_lane(lanes, event);
+            }
+        }
+        if let Some(ref budget) = self.config.memory_limit {
+            if let Message::Event(ref mut event, _) | Message::Captured(ref mut event, _) = msg {
+                // Metadata is small and needed to read everything else.
+                if event.ph != "M" {
+                    match budget.shed(self.memory_used()) {
+                        Shed::Nothing => {}
+                        Shed::Args => {
+                            event.args.clear();
+                            self.stats.shed(Shed::Args);
+                        }
+                        Shed::Event => return self.stats.shed(Shed::Event),
+                    }
+                }
+            }
+        }
+        self.shutdown.start();
+        let stats = &self.stats;
+        if self.config.shared_sender {
+            stats.send(&self.tx.lock().unwrap(), msg);
+        } else {
+            channel::with_local(self.instance, &self.tx, |tx| stats.send(tx, msg));
+        }
+    }
+
+    /// The lane a new span is drawn in: its own if it starts one, otherwise
+    /// its parent's.
+    fn span_lane(&self, id: u64, state: &span::SpanState, field: Option<&str>) -> Option<lanes::Lane> {
+        let own = match field {
+            Some(field) => state.fields.get(field).map(|value| lanes::Lane::from_field(field, value)),
+            None if state.root == id => match self.config.task_lanes {
+                Some(TaskLanes::RootSpan) => Some(lanes::Lane {
+                    key: id,
+                    name: format!("{} {}", state.name, id),
+                    owner: true,
+                }),
+                _ => None,
+            },
+            None => None,
+        };
+        own.or_else(|| {
+            let parent = state.parent?;
+            let lane = self.spans.with(parent, |parent| parent.lane.clone())??;
+            Some(lanes::Lane { owner: false, ..lane })
+        })
+    }
+
+    /// Moves `event` onto the lane of the span it belongs to, naming the
+    /// lane first if it's new.
+    fn move_to_lane(&self, lanes: &lanes::Lanes, event: &mut TraceEvent) {
+        let span = match event.span {
+            Some(span) if event.ph != "M" => span,
+            _ => return,
+        };
+        let moved = self
+            .spans
+            .with(span, |span| span.lane.as_ref().map(|lane| lanes.tid(lane)))
+            .and_then(|moved| moved);
+        if let Some((tid, names)) = moved {
+            event.tid = tid;
+            for name in names.into_iter().flatten() {
+                self.send(name);
             }
         }
     }
+
+    fn paused(&self) -> bool {
+        self.control.as_ref().is_some_and(|control| control.paused())
+    }
+
+    fn memory_used(&self) -> usize {
+        self.stats.queued_bytes() + self.spans.len() * budget::span_size()
+    }
 }
 
 impl Drop for ChromeTraceSubscriber {
     fn drop(&mut self) {
-        drop(self.tx.lock().unwrap().send(Message::Done))
+        self.finish_because(ShutdownReason::unless_panicking(ShutdownReason::Drop))
+    }
+}
+
+impl ChromeTraceSubscriber {
+    /// Starts keeping span `id`, for `Subscriber::new_span` and, with the
+    /// registry's id, `ChromeTraceLayer`.
+    pub(crate) fn open_span(&self, id: u64, attrs: &Attributes) {
+        self.name_thread();
+        let metadata = attrs.metadata();
+        if let Some(ref budget) = self.config.memory_limit {
+            if self.memory_used() >= budget.limit {
+                return self.stats.shed_span();
+            }
+        }
+        let mut state = span::SpanState::new(metadata.name(), metadata.target(), id);
+        state.selected = self.config.matches_within(metadata) || self.in_selected_span();
+        if let Some(parent) = parent(attrs.parent(), attrs.is_contextual()) {
+            let inherited = self.spans.with(parent, |parent| (parent.capture, parent.root));
+            if let Some((capture, root)) = inherited {
+                state.parent = Some(parent);
+                state.capture = capture;
+                state.root = root;
+            }
+        }
+        state.args = self.config.span_slices && self.config.include_args(metadata);
+        state.object = metadata.fields().field(OBJECT_FIELD).is_some();
+        state.cname = self.config.color(metadata);
+        let lane_field = match self.config.task_lanes {
+            Some(TaskLanes::Field(field)) => metadata.fields().field(field).map(|_| field),
+            _ => None,
+        };
+        let linked = metadata.fields().field(LINK_FIELD).is_some();
+        state.recolor = metadata.fields().field(COLOR_FIELD).is_some();
+        let mut link = None;
+        let wanted = self.config.export_spans || state.args || state.object || lane_field.is_some();
+        if wanted || linked || state.recolor {
+            let mut rec = Recorder::new(&self.config);
+            attrs.record(&mut rec);
+            state.fields = rec.into_fields();
+            state.fields.remove(OBJECT_FIELD);
+            if let Some(cname) = take_color(&mut state.fields) {
+                state.cname = Some(cname);
+            }
+            let from = state.fields.remove(LINK_FIELD).and_then(|from| from.as_u64());
+            let label = match state.fields.remove(LINK_LABEL_FIELD) {
+                Some(Value::String(label)) => label,
+                _ => LINK_CATEGORY.to_owned(),
+            };
+            link = from.map(|from| (from, label));
+        }
+        if state.args && self.config.include_location(metadata) {
+            add_location(&mut state.location, metadata);
+        }
+        if self.lanes.is_some() {
+            state.lane = self.span_lane(id, &state, lane_field);
+        }
+        if state.object {
+            self.object_event("N", id, &state);
+            self.object_event("O", id, &state);
+        }
+        state.runtime = self.config.runtimes.iter().position(|rt| rt.is_task_span(metadata));
+        let runtime = state.runtime;
+        let open_key = self.open_span_key(&state);
+        self.spans.insert(id, state);
+        if let Some((from, label)) = link {
+            self.start_flow(from, id, LINK_CATEGORY, label);
+        }
+        self.evict_spans();
+        if let Some(key) = open_key {
+            self.count_open_span(key, true);
+        }
+        if let Some(runtime) = runtime {
+            self.tasks[runtime].live.fetch_add(1, Ordering::Relaxed);
+            self.task_counter(runtime);
+        }
     }
 }
 
-impl tokio_trace_core::Subscriber for ChromeTraceSubscriber {
-    fn enabled(&self, _metadata: &Metadata) -> bool { true }
+impl tracing_core::Subscriber for ChromeTraceSubscriber {
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        let enabled = self.enabled(metadata);
+        if self.config.debug_callsites {
+            self.callsite_registered(metadata, enabled);
+        }
+        if self.control.is_some() {
+            // The filter can change, so ask every time.
+            Interest::sometimes()
+        } else if enabled {
+            Interest::always()
+        } else {
+            Interest::never()
+        }
+    }
+
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match self.control {
+            Some(ref control) => {
+                let enabled = control.enabled(&self.config, metadata);
+                // Only a filter set through the handle can turn away a
+                // callsite that registered as interesting.
+                if !enabled && self.config.enabled(metadata) {
+                    self.stats.suppressed(metadata, Suppressed::Filtered);
+                }
+                enabled
+            }
+            None => self.config.enabled(metadata),
+        }
+    }
 
-    fn new_span(&self, _metadata: &Metadata, _values: &field::ValueSet) -> Span {
-        Span::from_u64(self.next_span.fetch_add(10, Ordering::SeqCst) as u64)
+    fn new_span(&self, attrs: &Attributes) -> Id {
+        let id = match self.callsite_ids {
+            Some(ref ids) => ids.next(attrs.metadata()),
+            None => self.next_span.fetch_add(10, Ordering::SeqCst) as u64,
+        };
+        self.open_span(id, attrs);
+        Id::from_u64(id)
     }
 
-    fn record(&self, _span: &Span, _values: &field::ValueSet) {}
+    fn record(&self, span: &Id, values: &Record) {
+        let id = span.into_u64();
+        let wanted = self.config.export_spans
+            || self.config.span_slices
+            || self.spans.with(id, |span| span.object || span.recolor).unwrap_or(false);
+        if wanted {
+            let mut rec = Recorder::new(&self.config);
+            values.record(&mut rec);
+            let mut fields = rec.into_fields();
+            let cname = take_color(&mut fields);
+            self.spans.with(id, |span| {
+                if cname.is_some() {
+                    span.cname = cname;
+                }
+                span.fields.append(&mut fields);
+                if span.object {
+                    self.object_event("O", id, span);
+                }
+            });
+        }
+    }
 
-    fn record_follows_from(&self, _span: &Span, _follows: &Span) {}
+    fn record_follows_from(&self, span: &Id, follows: &Id) {
+        self.start_flow(follows.into_u64(), span.into_u64(), FLOW_NAME, FLOW_NAME.to_owned())
+    }
 
     fn event(&self, event: &Event) {
+        if self.paused() {
+            return self.stats.suppressed(event.metadata(), Suppressed::Paused);
+        }
+        if self.config.only_within.is_some() && !self.in_selected_span() {
+            return self.stats.suppressed(event.metadata(), Suppressed::Filtered);
+        }
+        self.name_thread();
+        self.pool_worker();
         let ts = self.start.elapsed();
         let meta = event.metadata();
-        let mut rec = Recorder::new();
-        event.record(&mut rec);
-        let Recorder { message, fields } = rec;
-        let val = json!({
-            "name": message.unwrap_or("<unknown>".to_owned()),
-            "cat": meta.target(),
-            "ph": "I",
-            "ts": in_micros(ts),
-            "s": "p",
-            "pid": process::id(),
-            "tid": thread_id::get(),
-            "args": fields,
-        });
-        drop(self.tx.lock().unwrap().send(Message::Event(val)))
+        let (message, mut fields) = if message_only(meta) {
+            // Most events are just a message: a map-free path that takes it
+            // as it's formatted, with no field map to build and pick the
+            // reserved fields out of. The message is the one String made
+            // here; the category (with one source and no level suffix) and
+            // target are borrowed from the callsite.
+            let mut rec = MessageRecorder(None);
+            event.record(&mut rec);
+            (rec.0, Map::new())
+        } else {
+            let mut rec = Recorder::new(&self.config);
+            event.record(&mut rec);
+            (rec.message, rec.fields)
+        };
+        if self.park_event(message.as_deref(), in_micros(ts)) {
+            return;
+        }
+        let phase = fields.remove(PHASE_FIELD);
+        let counter = fields.remove(COUNTER_FIELD).is_some();
+        if counter || phase.as_ref().and_then(Value::as_str) == Some("C") {
+            let name = message.map_or(Cow::Borrowed(meta.name()), Cow::Owned);
+            let mut counter = TraceEvent::new("C", name, self.config.category(meta), in_micros(ts));
+            counter.target = meta.target();
+            counter.level = Some(level_name(meta.level()));
+            counter.cname = take_color(&mut fields).or_else(|| self.config.color(meta));
+            counter.args = fields.into_iter().filter(|(_, value)| value.is_number()).collect();
+            return self.send_for_target(counter, meta.target());
+        }
+        let mut trace_event = TraceEvent::new(
+            "I",
+            message.map_or(Cow::Borrowed("<unknown>"), Cow::Owned),
+            self.config.category(meta),
+            in_micros(ts),
+        );
+        match fields.remove(DURATION_FIELD).and_then(|dur| dur.as_u64()) {
+            // The event reports on work that just finished, so draw it as a
+            // slice ending now.
+            Some(dur) => {
+                trace_event.ph = "X";
+                trace_event.ts = trace_event.ts.saturating_sub(dur);
+                trace_event.dur = Some(dur);
+            }
+            None => trace_event.scope = Some(self.config.instant_scope.code()),
+        }
+        if let Some(ph) = phase {
+            let scope = self.config.instant_scope.code();
+            if let Err(e) = set_phase(&mut trace_event, ph, &mut fields, scope) {
+                self.stats.log(|| format!("{} {}: {}", PHASE_FIELD, trace_event.name, e));
+            }
+        }
+        trace_event.target = meta.target();
+        trace_event.level = Some(level_name(meta.level()));
+        trace_event.span = parent(event.parent(), event.is_contextual());
+        trace_event.cname = take_color(&mut fields).or_else(|| self.config.color(meta));
+        if self.config.include_args(meta) {
+            trace_event.args = fields;
+            if self.config.include_location(meta) {
+                add_location(&mut trace_event.args, meta);
+            }
+            self.config.add_context(&mut trace_event.args);
+            #[cfg(feature = "backtrace")]
+            {
+                if self.config.wants_backtrace(meta) {
+                    trace_event.args.insert("backtrace".to_owned(), stack::capture());
+                }
+            }
+        }
+        if self.config.sequence_numbers {
+            let seq = SEQUENCE.with(|seq| {
+                let next = seq.get() + 1;
+                seq.set(next);
+                next
+            });
+            trace_event.args.insert("seq".to_owned(), Value::from(seq));
+        }
+        #[cfg(target_os = "macos")]
+        {
+            if let Some(ref signposts) = self.signposts {
+                signposts.event(&trace_event.name);
+            }
+        }
+        if let Some(ref sampler) = self.sampler {
+            let (keep, changed) = sampler.sample(in_micros(ts), &trace_event);
+            if let Some(changed) = changed {
+                self.send(changed);
+            }
+            if !keep {
+                self.stats.suppressed(meta, Suppressed::Sampled);
+                return self.stats.sampled_out();
+            }
+        }
+        self.send_for_target(trace_event, meta.target())
+    }
+
+    fn enter(&self, span: &Id) {
+        let id = span.into_u64();
+        self.name_thread();
+        span::push(id);
+        let flows = self.spans.with(id, |span| mem::take(&mut span.flows));
+        for (flow, cat, name) in flows.unwrap_or_default() {
+            let mut end = TraceEvent::new("f", name, cat.to_owned(), self.now());
+            end.id = Some(flow);
+            end.span = Some(id);
+            self.send(end);
+        }
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        {
+            if let Some(ref marker) = self.atrace {
+                let name = self.spans.with(id, |span| span.name).unwrap_or("<unknown>");
+                marker.begin(name);
+            }
+        }
+        #[cfg(target_os = "macos")]
+        {
+            if let Some(ref signposts) = self.signposts {
+                let name = self.spans.with(id, |span| span.name).unwrap_or("<unknown>");
+                signposts.begin(id, name);
+            }
+        }
+        if span::depth() == 1 {
+            self.pool_busy(true);
+            if let Some(ref polls) = self.polls {
+                let name = self.spans.with(id, |span| span.name).unwrap_or("<unknown>");
+                polls.enter(event::tid(), name);
+            }
+        }
+        if let Some(runtime) = state_runtime(&self.spans, id) {
+            self.tasks[runtime].polling.fetch_add(1, Ordering::Relaxed);
+            self.task_counter(runtime);
+        }
+    }
+
+    fn exit(&self, span: &Id) {
+        let id = span.into_u64();
+        if let Some((elapsed, parent)) = span::pop(id) {
+            self.spans.add_busy(id, parent, elapsed);
+            if self.config.span_slices && self.config.track_style.threaded() {
+                self.span_slice(id, elapsed);
+            }
+            if self.config.span_slices && self.config.track_style.async_tracks() {
+                self.busy_slice(id, elapsed);
+            }
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            {
+                if let Some(ref marker) = self.atrace {
+                    marker.end();
+                }
+            }
+            #[cfg(target_os = "macos")]
+            {
+                if let Some(ref signposts) = self.signposts {
+                    let name = self.spans.with(id, |span| span.name).unwrap_or("<unknown>");
+                    signposts.end(id, name);
+                }
+            }
+        }
+        if span::depth() == 0 {
+            self.pool_busy(false);
+            if let Some(ref polls) = self.polls {
+                if let Some(warning) = polls.exit(event::tid(), self.start) {
+                    self.send(warning);
+                }
+            }
+        }
+        if let Some(runtime) = state_runtime(&self.spans, id) {
+            self.tasks[runtime].polling.fetch_sub(1, Ordering::Relaxed);
+            self.task_counter(runtime);
+        }
+    }
+
+    fn clone_span(&self, id: &Id) -> Id {
+        self.spans.clone_span(id.into_u64());
+        id.clone()
+    }
+
+    fn try_close(&self, id: Id) -> bool {
+        let id = id.into_u64();
+        let closed = match self.spans.drop_span(id) {
+            Some(closed) => closed,
+            None => return false,
+        };
+        if closed.capture == Some(id) {
+            self.send_message(Message::EndCapture(id));
+        }
+        self.span_times.add(&closed);
+        #[cfg(feature = "hdrhistogram")]
+        {
+            if self.config.latency_percentiles {
+                self.latencies.record(closed.name, closed.created.elapsed());
+            }
+        }
+        if let Some(key) = self.open_span_key(&closed) {
+            self.count_open_span(key, false);
+        }
+        if let Some(runtime) = closed.runtime {
+            self.tasks[runtime].live.fetch_sub(1, Ordering::Relaxed);
+            self.task_counter(runtime);
+        }
+        if self.config.span_slices && self.config.track_style.async_tracks() {
+            self.span_lifetime(id, &closed);
+        }
+        if closed.object {
+            self.object_event("D", id, &closed);
+        }
+        if let (Some(lanes), Some(lane)) = (self.lanes.as_ref(), closed.lane.as_ref()) {
+            if lane.owner {
+                lanes.release(lane.key);
+            }
+        }
+        if self.config.export_spans {
+            let start = in_micros(closed.created.duration_since(self.start));
+            self.send_message(Message::SpanClosed(ClosedSpan {
+                id,
+                parent: closed.parent,
+                root: closed.root,
+                name: closed.name,
+                target: closed.target,
+                start,
+                timestamp: self.epoch + start,
+                duration: in_micros(closed.created.elapsed()),
+                fields: closed.fields,
+            }));
+        }
+        true
+    }
+}
+
+/// What `ChromeTraceBuilder::build` writes for a setting it made the best
+/// of.
+fn config_warning(error: &Error) -> TraceEvent {
+    let mut warning = TraceEvent::new("I", "invalid configuration", "chrome_trace", 0);
+    warning.scope = Some("g");
+    warning.cname = Some("bad".to_owned());
+    warning.args.insert("error".to_owned(), Value::from(error.to_string()));
+    warning
+}
+
+/// Switches to a new JSON file at `path`, ending the current output with a
+/// summary, for `ChromeTraceSubscriber::rotate`.
+fn rotation(path: &Path, ts: u64) -> io::Result<Message> {
+    let sink = JsonSink::new(File::create(path)?);
+    let mut summary = TraceEvent::new("I", SUMMARY_EVENT.to_owned(), "chrome_trace".to_owned(), ts);
+    summary.scope = Some("g");
+    summary.args.insert("reason".to_owned(), Value::from("rotation"));
+    summary.args.insert("next".to_owned(), Value::from(path.to_string_lossy().into_owned()));
+    Ok(Message::SetOutput(Box::new(sink), Some(summary)))
+}
+
+/// Gives `event`, an instant or, with `DURATION_FIELD`, a complete event,
+/// the phase `ph` asked for with `PHASE_FIELD`. If that can't be done it's
+/// written as an instant, and the problem is returned.
+fn set_phase(
+    event: &mut TraceEvent,
+    ph: Value,
+    fields: &mut Map<String, Value>,
+    scope: &'static str,
+) -> Result<(), String> {
+    let id = fields.remove(ID_FIELD).and_then(|id| id.as_u64());
+    let checked = match ph.as_str().and_then(event::known_phase) {
+        Some("X") if event.dur.is_none() => Err(format!("`X` needs {}", DURATION_FIELD)),
+        Some(ph @ "b") | Some(ph @ "n") | Some(ph @ "e") | Some(ph @ "s") | Some(ph @ "t")
+        | Some(ph @ "f") | Some(ph @ "N") | Some(ph @ "O") | Some(ph @ "D")
+            if id.is_none() =>
+        {
+            Err(format!("`{}` needs a numeric {}", ph, ID_FIELD))
+        }
+        Some(ph) => Ok(ph),
+        None => Err(format!("unknown phase {}", ph)),
+    };
+    let ph = checked.as_ref().map_or("I", |&ph| ph);
+    if ph == "X" {
+        return Ok(());
+    }
+    // Only complete events cover the time leading up to them.
+    if let Some(dur) = event.dur.take() {
+        event.ts += dur;
+    }
+    event.ph = ph;
+    event.id = id;
+    event.scope = if ph == "i" || ph == "I" { Some(scope) } else { None };
+    checked.map(|_| ())
+}
+
+/// Removes `COLOR_FIELD` from `fields`, returning the color it names.
+fn take_color(fields: &mut Map<String, Value>) -> Option<String> {
+    match fields.remove(COLOR_FIELD) {
+        Some(Value::String(cname)) => Some(cname),
+        _ => None,
     }
+}
+
+/// The environment variable `MaybeChromeTraceSubscriber::from_env` reads the
+/// output path from.
+pub const TRACE_PATH_VAR: &str = "CHROME_TRACE";
+
+/// Traces to the file at `path` as the global default subscriber. The trace
+/// is finished when the returned guard is dropped, so keep it until the end
+/// of `main`. Fails if the file can't be created or a global default is
+/// already set.
+pub fn init<P: AsRef<Path>>(path: P) -> Result<FlushGuard, Error> {
+    let subscriber = ChromeTraceSubscriber::from_path(path)?;
+    let guard = subscriber.flush_guard();
+    set_global_default(subscriber)?;
+    Ok(guard)
+}
 
-    fn enter(&self, _span: &Span) {}
+/// Like `init`, tracing to the file named by `CHROME_TRACE` if it's set, and
+/// doing nothing otherwise.
+pub fn init_from_env() -> Result<Option<FlushGuard>, Error> {
+    let subscriber = MaybeChromeTraceSubscriber::try_from_env()?;
+    let guard = subscriber.flush_guard();
+    if guard.is_some() {
+        set_global_default(subscriber)?;
+    }
+    Ok(guard)
+}
 
-    fn exit(&self, _span: &Span) {}
+fn set_global_default<S>(subscriber: S) -> Result<(), Error>
+where
+    S: tracing_core::Subscriber + Send + Sync + 'static,
+{
+    let dispatch = tracing_core::Dispatch::new(subscriber);
+    tracing_core::dispatcher::set_global_default(dispatch)
+        .map_err(|_| Error::Config("a global default subscriber is already set".to_owned()))
 }
 
-impl tokio_trace_core::Subscriber for MaybeChromeTraceSubscriber {
-    fn enabled(&self, _metadata: &Metadata) -> bool { self.0.is_some() }
+impl MaybeChromeTraceSubscriber {
+    /// Traces to the file named by `CHROME_TRACE` if it's set (and the file
+    /// can be created), and does nothing otherwise.
+    pub fn from_env() -> Self {
+        let subscriber = env::var_os(TRACE_PATH_VAR)
+            .filter(|path| !path.is_empty())
+            .and_then(|path| ChromeTraceSubscriber::from_path(path).ok());
+        MaybeChromeTraceSubscriber(subscriber)
+    }
+
+    /// Like `from_env`, failing if `CHROME_TRACE` is set but the file can't
+    /// be created.
+    pub fn try_from_env() -> Result<Self, Error> {
+        let subscriber = match env::var_os(TRACE_PATH_VAR).filter(|path| !path.is_empty()) {
+            Some(path) => Some(ChromeTraceSubscriber::from_path(path)?),
+            None => None,
+        };
+        Ok(MaybeChromeTraceSubscriber(subscriber))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub fn flush(&self) {
+        if let Some(ref s) = self.0 {
+            s.flush()
+        }
+    }
+
+    pub fn finish(&self) {
+        if let Some(ref s) = self.0 {
+            s.finish()
+        }
+    }
+
+    pub fn finish_because(&self, reason: ShutdownReason) {
+        if let Some(ref s) = self.0 {
+            s.finish_because(reason)
+        }
+    }
+
+    pub fn link(&self, from: &Id, to: &Id, label: &str) {
+        if let Some(ref s) = self.0 {
+            s.link(from, to, label)
+        }
+    }
+
+    pub fn attach<S: TraceSink + 'static>(&self, sink: S) {
+        if let Some(ref s) = self.0 {
+            s.attach(sink)
+        }
+    }
+
+    pub fn rotate<P: AsRef<Path>>(&self, new_path: P) -> Result<(), Error> {
+        match self.0 {
+            Some(ref s) => s.rotate(new_path),
+            None => Ok(()),
+        }
+    }
+
+    pub fn flush_guard(&self) -> Option<FlushGuard> {
+        self.0.as_ref().map(ChromeTraceSubscriber::flush_guard)
+    }
+
+    pub fn set_process_labels(&self, labels: &[&str]) {
+        if let Some(ref s) = self.0 {
+            s.set_process_labels(labels)
+        }
+    }
+
+    pub fn set_internal_logger<F: Fn(&str) + Send + Sync + 'static>(&self, logger: F) {
+        if let Some(ref s) = self.0 {
+            s.set_internal_logger(logger)
+        }
+    }
+
+    pub fn add_system_trace_events(&self, text: String) {
+        if let Some(ref s) = self.0 {
+            s.add_system_trace_events(text)
+        }
+    }
+
+    pub fn capture_span<S: TraceSink + 'static>(&self, span: &Id, sink: S) {
+        if let Some(ref s) = self.0 {
+            s.capture_span(span, sink)
+        }
+    }
+
+    #[cfg(all(unix, feature = "serde_json"))]
+    pub fn collector(&self) -> Option<ParentCollector> {
+        self.0.as_ref().map(ChromeTraceSubscriber::collector)
+    }
+
+    pub fn register_pool_thread(&self, pool: &str, index: usize) {
+        if let Some(ref s) = self.0 {
+            s.register_pool_thread(pool, index)
+        }
+    }
+
+    pub fn record_pool_pending(&self, pool: &str, pending: usize) {
+        if let Some(ref s) = self.0 {
+            s.record_pool_pending(pool, pending)
+        }
+    }
+
+    pub fn emit_clock_sync(&self, name: &str) {
+        if let Some(ref s) = self.0 {
+            s.emit_clock_sync(name)
+        }
+    }
+
+    pub fn annotate(&self, label: &str) {
+        if let Some(ref s) = self.0 {
+            s.annotate(label)
+        }
+    }
+
+    pub fn current_span(&self) -> Option<Id> {
+        self.0.as_ref().and_then(ChromeTraceSubscriber::current_span)
+    }
+
+    pub fn record_counter(&self, name: &str, value: f64) {
+        if let Some(ref s) = self.0 {
+            s.record_counter(name, value)
+        }
+    }
+
+    pub fn record_counters(&self, name: &str, series: &[(&str, f64)]) {
+        if let Some(ref s) = self.0 {
+            s.record_counters(name, series)
+        }
+    }
+}
 
-    fn new_span(&self, metadata: &Metadata, values: &field::ValueSet) -> Span {
+impl tracing_core::Subscriber for MaybeChromeTraceSubscriber {
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
         match self.0 {
-            Some(ref s) => s.new_span(metadata, values),
-            None => Span::from_u64(0),
+            Some(ref s) => s.register_callsite(metadata),
+            None => Interest::never(),
         }
     }
 
-    fn record(&self, span: &Span, values: &field::ValueSet) {
+    fn enabled(&self, metadata: &Metadata) -> bool {
         match self.0 {
-            Some(ref s) => s.record(span, values),
-            None => {}
+            Some(ref s) => s.enabled(metadata),
+            None => false,
         }
     }
 
-    fn record_follows_from(&self, span: &Span, follows: &Span) {
+    fn new_span(&self, attrs: &Attributes) -> Id {
         match self.0 {
-            Some(ref s) => s.record_follows_from(span, follows),
-            None => {}
+            Some(ref s) => s.new_span(attrs),
+            // Not reached: with no subscriber, nothing is enabled.
+            None => Id::from_u64(1),
+        }
+    }
+
+    fn record(&self, span: &Id, values: &Record) {
+        if let Some(ref s) = self.0 {
+            s.record(span, values)
+        }
+    }
+
+    fn record_follows_from(&self, span: &Id, follows: &Id) {
+        if let Some(ref s) = self.0 {
+            s.record_follows_from(span, follows)
         }
     }
 
     fn event(&self, event: &Event) {
-        match self.0 {
-            Some(ref s) => s.event(event),
-            None => {}
+        if let Some(ref s) = self.0 {
+            s.event(event)
+        }
+    }
+
+    fn enter(&self, span: &Id) {
+        if let Some(ref s) = self.0 {
+            s.enter(span)
+        }
+    }
+
+    fn exit(&self, span: &Id) {
+        if let Some(ref s) = self.0 {
+            s.exit(span)
         }
     }
 
-    fn enter(&self, span: &Span) {
+    fn clone_span(&self, id: &Id) -> Id {
         match self.0 {
-            Some(ref s) => s.enter(span),
-            None => {}
+            Some(ref s) => s.clone_span(id),
+            None => id.clone(),
         }
     }
 
-    fn exit(&self, span: &Span) {
+    fn try_close(&self, id: Id) -> bool {
         match self.0 {
-            Some(ref s) => s.exit(span),
-            None => {}
+            Some(ref s) => s.try_close(id),
+            None => false,
         }
     }
 }
 
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn process_age() -> Option<Duration> {
+    procfs::process_age()
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn process_age() -> Option<Duration> {
+    None
+}
+
+/// The span something new is inside: the one it names as its parent, or
+/// for a contextual span or event, the current one.
+fn parent(explicit: Option<&Id>, contextual: bool) -> Option<u64> {
+    match explicit {
+        Some(id) => Some(id.into_u64()),
+        None if contextual => span::current(),
+        None => None,
+    }
+}
+
+fn state_runtime(spans: &span::Registry, id: u64) -> Option<usize> {
+    spans.with(id, |span| span.runtime).and_then(|runtime| runtime)
+}
+
 fn in_micros(d: Duration) -> u64 {
-    1000000 * d.as_secs() + (d.subsec_nanos() / 1000) as u64
+    1000000 * d.as_secs() + d.subsec_micros() as u64
 }
 
 struct Recorder {
     pub message: Option<String>,
-    pub fields: HashMap<&'static str, String>,
+    pub fields: Map<String, Value>,
+    large_ints: LargeIntEncoding,
+    durations: bool,
 }
 
 impl Recorder {
-    pub fn new() -> Self {
+    fn new(config: &Config) -> Self {
         Recorder {
             message: None,
-            fields: HashMap::new(),
+            fields: Map::new(),
+            large_ints: config.large_ints,
+            durations: config.durations_as_micros,
+        }
+    }
+
+    /// All recorded values, with the message (if any) as `message`.
+    fn into_fields(self) -> Map<String, Value> {
+        let mut fields = self.fields;
+        if let Some(message) = self.message {
+            fields.insert("message".to_owned(), Value::from(message));
         }
+        fields
+    }
+}
+
+/// Parses `Duration`'s Debug output (`1.5s`, `250ms`, `12µs`, `80ns`) into
+/// microseconds.
+fn parse_duration_micros(debug: &str) -> Option<Value> {
+    let units: &[(&str, f64)] = &[("ns", 0.001), ("µs", 1.0), ("ms", 1000.0), ("s", 1_000_000.0)];
+    let &(unit, scale) = units.iter().find(|&&(unit, _)| debug.ends_with(unit))?;
+    let number = &debug[..debug.len() - unit.len()];
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+    let micros = number.parse::<f64>().ok()? * scale;
+    if micros.fract() == 0.0 && micros <= MAX_SAFE_INTEGER as f64 {
+        Some(Value::from(micros as u64))
+    } else {
+        Some(Value::from(micros))
     }
 }
 
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
 
-impl field::Record for Recorder {
-    fn record_str(&mut self, field: &field::Field, value: &str) {
-        if field.name() == "message" {
+fn encode_u64(value: u64, encoding: LargeIntEncoding) -> Value {
+    if value <= MAX_SAFE_INTEGER {
+        return Value::from(value);
+    }
+    match encoding {
+        LargeIntEncoding::Number => Value::from(value),
+        LargeIntEncoding::String => Value::from(value.to_string()),
+        LargeIntEncoding::HiLo => hi_lo(value >> 32, value & 0xffff_ffff),
+    }
+}
+
+fn encode_i64(value: i64, encoding: LargeIntEncoding) -> Value {
+    if value.checked_abs().is_some_and(|v| v as u64 <= MAX_SAFE_INTEGER) {
+        return Value::from(value);
+    }
+    match encoding {
+        LargeIntEncoding::Number => Value::from(value),
+        LargeIntEncoding::String => Value::from(value.to_string()),
+        LargeIntEncoding::HiLo => hi_lo(value >> 32, value & 0xffff_ffff),
+    }
+}
+
+fn hi_lo<T: Into<Value>>(hi: T, lo: T) -> Value {
+    let mut obj = Map::new();
+    obj.insert("hi".to_owned(), hi.into());
+    obj.insert("lo".to_owned(), lo.into());
+    Value::Object(obj)
+}
+
+
+/// Whether the callsite's only field is the message.
+fn message_only(meta: &Metadata) -> bool {
+    meta.fields().iter().all(|field| field.name() == "message")
+}
+
+/// Records just the message, for `message_only` callsites. Skips the field
+/// map, not the message itself, which `tracing` hands over to be formatted
+/// (or copied) and has to be owned to reach the writer thread.
+struct MessageRecorder(Option<String>);
+
+impl field::Visit for MessageRecorder {
+    fn record_str(&mut self, _field: &field::Field, value: &str) {
+        self.0 = Some(value.to_owned());
+    }
+
+    fn record_debug(&mut self, _field: &field::Field, value: &dyn fmt::Debug) {
+        self.0 = Some(format!("{:?}", value));
+    }
+}
+
+impl Recorder {
+    fn put_i64(&mut self, name: &str, value: i64) {
+        let value = encode_i64(value, self.large_ints);
+        self.fields.insert(name.to_owned(), value);
+    }
+
+    fn put_u64(&mut self, name: &str, value: u64) {
+        let value = encode_u64(value, self.large_ints);
+        self.fields.insert(name.to_owned(), value);
+    }
+
+    fn put_str(&mut self, name: &str, value: &str) {
+        if name == "message" {
             self.message = Some(value.to_owned());
         } else {
-            self.fields.insert(field.name(), value.to_owned());
+            self.fields.insert(name.to_owned(), Value::from(value));
         }
     }
 
-    fn record_debug(&mut self, field: &field::Field, value: &fmt::Debug) {
-        if field.name() == "message" {
-            self.message = Some(format!("{:?}", value));
-        } else {
-            self.fields.insert(field.name(), format!("{:?}", value));
+    fn put_debug(&mut self, name: &str, value: String) {
+        if name == "message" {
+            self.message = Some(value);
+            return;
         }
+        if self.durations {
+            if let Some(micros) = parse_duration_micros(&value) {
+                let name = if name.ends_with("_us") {
+                    name.to_owned()
+                } else {
+                    format!("{}_us", name)
+                };
+                self.fields.insert(name, micros);
+                return;
+            }
+        }
+        self.fields.insert(name.to_owned(), Value::from(value));
+    }
+}
+
+impl field::Visit for Recorder {
+    fn record_i64(&mut self, field: &field::Field, value: i64) {
+        self.put_i64(field.name(), value)
+    }
+
+    fn record_u64(&mut self, field: &field::Field, value: u64) {
+        self.put_u64(field.name(), value)
+    }
+
+    fn record_f64(&mut self, field: &field::Field, value: f64) {
+        self.fields.insert(field.name().to_owned(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &field::Field, value: bool) {
+        self.fields.insert(field.name().to_owned(), Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &field::Field, value: &str) {
+        self.put_str(field.name(), value)
+    }
+
+    fn record_debug(&mut self, field: &field::Field, value: &dyn fmt::Debug) {
+        self.put_debug(field.name(), format!("{:?}", value))
     }
 }
@@ -0,0 +1,57 @@
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+
+use crate::json::{Map, Value};
+use crate::TraceEvent;
+
+/// Accumulates what a sink writes, for the sidecar manifest written when it
+/// finishes.
+pub(crate) struct Manifest {
+    path: PathBuf,
+    hasher: Sha256,
+    size: u64,
+    events: u64,
+    start_us: Option<u64>,
+    end_us: u64,
+}
+
+impl Manifest {
+    pub fn new(path: PathBuf) -> Self {
+        Manifest {
+            path,
+            hasher: Sha256::new(),
+            size: 0,
+            events: 0,
+            start_us: None,
+            end_us: 0,
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.hasher.update(bytes);
+        self.size += bytes.len() as u64;
+    }
+
+    pub fn event(&mut self, event: &TraceEvent) {
+        self.events += 1;
+        if event.ph == "M" {
+            return;
+        }
+        self.start_us = Some(self.start_us.map_or(event.ts, |start| start.min(event.ts)));
+        self.end_us = self.end_us.max(event.ts + event.dur.unwrap_or(0));
+    }
+
+    pub fn write(self) -> io::Result<()> {
+        let mut manifest = Map::new();
+        manifest.insert("size".to_owned(), Value::from(self.size));
+        manifest.insert("sha256".to_owned(), Value::from(format!("{:x}", self.hasher.finalize())));
+        manifest.insert("events".to_owned(), Value::from(self.events));
+        manifest.insert("start_us".to_owned(), Value::from(self.start_us.unwrap_or(0)));
+        manifest.insert("end_us".to_owned(), Value::from(self.end_us));
+        let file = File::create(&self.path)?;
+        serde_json::to_writer_pretty(file, &Value::Object(manifest))?;
+        Ok(())
+    }
+}
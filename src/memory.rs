@@ -0,0 +1,153 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tracing_core::span::Id;
+use tracing_core::Level;
+
+use crate::json::Value;
+use crate::{level_name, target_matches, TraceEvent, TraceSink};
+
+/// The most recent events written to a `MemorySink`, shared with the
+/// application so it can look through them, e.g. to attach recent activity
+/// to an error report.
+#[derive(Clone)]
+pub struct EventBuffer {
+    events: Arc<Mutex<VecDeque<TraceEvent>>>,
+    capacity: usize,
+}
+
+impl EventBuffer {
+    /// Keeps up to `capacity` events, dropping the oldest first.
+    pub fn new(capacity: usize) -> Self {
+        EventBuffer {
+            events: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// Keeps every event, e.g. for tests that check what some code
+    /// recorded.
+    pub fn unbounded() -> Self {
+        EventBuffer {
+            events: Arc::new(Mutex::new(VecDeque::new())),
+            capacity: usize::MAX,
+        }
+    }
+
+    /// A sink that fills this buffer.
+    pub fn sink(&self) -> MemorySink {
+        MemorySink {
+            buffer: self.clone(),
+        }
+    }
+
+    /// The retained events matching `query`, oldest first.
+    pub fn query(&self, query: &Query) -> Vec<TraceEvent> {
+        let events = self.events.lock().unwrap();
+        events.iter().filter(|event| query.matches(event)).cloned().collect()
+    }
+
+    /// Every retained event, metadata included, as it would appear in a
+    /// JSON trace, oldest first. Events are written on the writer thread, so
+    /// call `ChromeTraceSubscriber::flush` (or `finish`) before looking.
+    pub fn events(&self) -> Vec<Value> {
+        let events = self.events.lock().unwrap();
+        events.iter().map(TraceEvent::to_json).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Writes events to an `EventBuffer`.
+pub struct MemorySink {
+    buffer: EventBuffer,
+}
+
+impl TraceSink for MemorySink {
+    fn write_event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        if self.buffer.capacity == 0 {
+            return Ok(());
+        }
+        let mut events = self.buffer.events.lock().unwrap();
+        if events.len() == self.buffer.capacity {
+            events.pop_front();
+        }
+        events.push_back(event.clone());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Selects events from an `EventBuffer`. Every condition that's set has to
+/// match; an empty query matches everything.
+#[derive(Clone, Debug, Default)]
+pub struct Query {
+    from: Option<u64>,
+    to: Option<u64>,
+    target: Option<String>,
+    levels: Option<Vec<&'static str>>,
+    span: Option<u64>,
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Query::default()
+    }
+
+    /// Events with timestamps in `from..=to`, in microseconds since the
+    /// subscriber started.
+    pub fn between(mut self, from: u64, to: u64) -> Self {
+        self.from = Some(from);
+        self.to = Some(to);
+        self
+    }
+
+    /// Events whose target is under `prefix`.
+    pub fn target(mut self, prefix: &str) -> Self {
+        self.target = Some(prefix.to_owned());
+        self
+    }
+
+    /// Events at `level` or more severe.
+    pub fn min_level(mut self, level: Level) -> Self {
+        let all = [Level::ERROR, Level::WARN, Level::INFO, Level::DEBUG, Level::TRACE];
+        let keep = all.iter().position(|l| *l == level).map_or(all.len(), |i| i + 1);
+        self.levels = Some(all[..keep].iter().map(level_name).collect());
+        self
+    }
+
+    /// Events recorded directly inside `span`.
+    pub fn span(mut self, span: &Id) -> Self {
+        self.span = Some(span.into_u64());
+        self
+    }
+
+    fn matches(&self, event: &TraceEvent) -> bool {
+        if event.ph == "M" {
+            return false;
+        }
+        if self.from.is_some_and(|from| event.ts < from) || self.to.is_some_and(|to| event.ts > to) {
+            return false;
+        }
+        if let Some(ref prefix) = self.target {
+            if !target_matches(event.target, prefix) {
+                return false;
+            }
+        }
+        if let Some(ref levels) = self.levels {
+            if !event.level.is_some_and(|level| levels.contains(&level)) {
+                return false;
+            }
+        }
+        self.span.is_none_or(|span| event.span == Some(span))
+    }
+}
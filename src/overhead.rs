@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+use crate::channel::{self, ChannelPolicy};
+use crate::clock::Instant;
+use crate::json::{self, Map, Value};
+use crate::writer::Route;
+use crate::{Message, TraceEvent};
+
+/// Metadata record with the measured cost of recording, see
+/// `ChromeTraceBuilder::measure_overhead`.
+pub(crate) const OVERHEAD_EVENT: &str = "chrome_trace_overhead";
+
+const ROUNDS: u32 = 1000;
+
+/// Times serializing and queueing a typical event on this machine, before
+/// any real events are recorded.
+pub(crate) fn calibrate(bounded: bool) -> Map<String, Value> {
+    let mut event = TraceEvent::new("X", "calibration".to_owned(), "chrome_trace".to_owned(), 0);
+    event.dur = Some(0);
+    event.args.insert("message".to_owned(), Value::from("a typical message"));
+    event.args.insert("count".to_owned(), Value::from(42));
+
+    let start = Instant::now();
+    let mut bytes = 0;
+    for _ in 0..ROUNDS {
+        bytes += json::to_vec(&event.to_json()).len();
+    }
+    let serialize = start.elapsed();
+
+    // The same kind of channel as the real one, big enough to never block.
+    let capacity = if bounded { Some(ROUNDS as usize) } else { None };
+    let (tx, _rx) = channel::channel(capacity, ChannelPolicy::Block);
+    let messages: Vec<Message> = (0..ROUNDS)
+        .map(|_| Message::Event(event.clone(), Route::Default))
+        .collect();
+    let start = Instant::now();
+    for msg in messages {
+        let _ = tx.send(msg);
+    }
+    let enqueue = start.elapsed();
+
+    let mut args = Map::new();
+    args.insert("serialize_ns".to_owned(), Value::from(per_round(serialize)));
+    args.insert("enqueue_ns".to_owned(), Value::from(per_round(enqueue)));
+    args.insert("event_bytes".to_owned(), Value::from(bytes / ROUNDS as usize));
+    args
+}
+
+fn per_round(elapsed: Duration) -> u64 {
+    (elapsed.as_secs() * 1_000_000_000 + u64::from(elapsed.subsec_nanos())) / u64::from(ROUNDS)
+}
+
+/// Time the writer thread spends handing events to the sinks.
+#[derive(Default)]
+pub(crate) struct WriteTime {
+    total: Duration,
+    events: u64,
+}
+
+impl WriteTime {
+    pub fn add(&mut self, elapsed: Duration) {
+        self.total += elapsed;
+        self.events += 1;
+    }
+
+    pub fn per_event_ns(&self) -> u64 {
+        if self.events == 0 {
+            return 0;
+        }
+        let total = self.total.as_secs() * 1_000_000_000 + u64::from(self.total.subsec_nanos());
+        total / self.events
+    }
+}
@@ -0,0 +1,500 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::mem;
+
+use crate::json::Value;
+use crate::{TraceEvent, TraceSink};
+
+// Field numbers from Perfetto's trace.proto, trace_packet.proto,
+// track_event.proto and friends.
+const TRACE_PACKET: u32 = 1;
+
+const PACKET_TIMESTAMP: u32 = 8;
+const PACKET_SEQUENCE_ID: u32 = 10;
+const PACKET_TRACK_EVENT: u32 = 11;
+const PACKET_SEQUENCE_FLAGS: u32 = 13;
+const PACKET_TIMESTAMP_CLOCK_ID: u32 = 58;
+const PACKET_TRACK_DESCRIPTOR: u32 = 60;
+
+const TRACK_UUID: u32 = 1;
+const TRACK_NAME: u32 = 2;
+const TRACK_PROCESS: u32 = 3;
+const TRACK_THREAD: u32 = 4;
+const TRACK_PARENT_UUID: u32 = 5;
+const TRACK_COUNTER: u32 = 8;
+
+const PROCESS_PID: u32 = 1;
+const PROCESS_NAME: u32 = 6;
+const THREAD_PID: u32 = 1;
+const THREAD_TID: u32 = 2;
+const THREAD_NAME: u32 = 5;
+
+const EVENT_ANNOTATIONS: u32 = 4;
+const EVENT_TYPE: u32 = 9;
+const EVENT_TRACK_UUID: u32 = 11;
+const EVENT_CATEGORIES: u32 = 22;
+const EVENT_NAME: u32 = 23;
+const EVENT_DOUBLE_COUNTER_VALUE: u32 = 44;
+
+const TYPE_SLICE_BEGIN: u64 = 1;
+const TYPE_SLICE_END: u64 = 2;
+const TYPE_INSTANT: u64 = 3;
+const TYPE_COUNTER: u64 = 4;
+
+const ANNOTATION_BOOL: u32 = 2;
+const ANNOTATION_INT: u32 = 4;
+const ANNOTATION_DOUBLE: u32 = 5;
+const ANNOTATION_STRING: u32 = 6;
+const ANNOTATION_NAME: u32 = 10;
+
+const SEQ_INCREMENTAL_STATE_CLEARED: u64 = 1;
+/// All packets are written by this one sink, so share one sequence.
+const SEQUENCE_ID: u64 = 1;
+
+/// A protobuf message under construction.
+#[derive(Default)]
+pub(crate) struct Message(pub(crate) Vec<u8>);
+
+impl Message {
+    pub(crate) fn varint(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.0.push(byte);
+                return;
+            }
+            self.0.push(byte | 0x80);
+        }
+    }
+
+    fn key(&mut self, field: u32, wire_type: u64) {
+        self.varint(u64::from(field) << 3 | wire_type)
+    }
+
+    pub(crate) fn uint(&mut self, field: u32, value: u64) {
+        self.key(field, 0);
+        self.varint(value);
+    }
+
+    fn int(&mut self, field: u32, value: i64) {
+        self.uint(field, value as u64)
+    }
+
+    fn double(&mut self, field: u32, value: f64) {
+        self.key(field, 1);
+        self.0.extend_from_slice(&value.to_bits().to_le_bytes());
+    }
+
+    pub(crate) fn bytes(&mut self, field: u32, bytes: &[u8]) {
+        self.key(field, 2);
+        self.varint(bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn string(&mut self, field: u32, s: &str) {
+        self.bytes(field, s.as_bytes())
+    }
+
+    pub(crate) fn message(&mut self, field: u32, message: Message) {
+        self.bytes(field, &message.0)
+    }
+}
+
+/// Which track an event goes on.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum TrackKey {
+    Process(u32),
+    Thread(u32, u64),
+    /// An async track, by category and id.
+    Async(u32, Cow<'static, str>, u64),
+    /// A counter track, by counter and series name.
+    Counter(u32, Cow<'static, str>, String),
+}
+
+/// Turns events into `TracePacket`s, describing each track the first time
+/// an event lands on it.
+pub(crate) struct Encoder {
+    tracks: HashMap<TrackKey, u64>,
+    /// Tracks described before incremental state was last cleared. They're
+    /// described again, under the same uuid, when next used.
+    stale: HashMap<TrackKey, u64>,
+    names: HashMap<TrackKey, String>,
+    last_uuid: u64,
+    first: bool,
+    /// `None` when traced assigns the sequence, as it does for producers.
+    sequence: Option<u64>,
+    /// The clock timestamps are on, if not the trace's default.
+    clock: Option<u64>,
+    packets: Vec<Message>,
+}
+
+impl Encoder {
+    /// For a trace file: one sequence, and timestamps as they are.
+    pub(crate) fn new() -> Self {
+        Encoder::stamped(Some(SEQUENCE_ID), None)
+    }
+
+    pub(crate) fn stamped(sequence: Option<u64>, clock: Option<u64>) -> Self {
+        Encoder {
+            tracks: HashMap::new(),
+            stale: HashMap::new(),
+            names: HashMap::new(),
+            last_uuid: 0,
+            first: true,
+            sequence,
+            clock,
+            packets: Vec::new(),
+        }
+    }
+
+    /// The packets encoded since the last call.
+    pub(crate) fn packets(&mut self) -> Vec<Message> {
+        mem::take(&mut self.packets)
+    }
+
+    /// Starts the sequence over, describing tracks again as they're used.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub(crate) fn clear_incremental_state(&mut self) {
+        self.stale.extend(self.tracks.drain());
+        self.first = true;
+    }
+
+    fn packet(&mut self, mut packet: Message) {
+        if let Some(sequence) = self.sequence {
+            packet.uint(PACKET_SEQUENCE_ID, sequence);
+        }
+        if self.first {
+            packet.uint(PACKET_SEQUENCE_FLAGS, SEQ_INCREMENTAL_STATE_CLEARED);
+            self.first = false;
+        }
+        self.packets.push(packet);
+    }
+
+    /// The uuid of the track for `key`, describing it first if it's new.
+    fn track(&mut self, key: TrackKey, name: &str) -> u64 {
+        if let Some(&uuid) = self.tracks.get(&key) {
+            return uuid;
+        }
+        let uuid = self.stale.remove(&key).unwrap_or_else(|| {
+            self.last_uuid += 1;
+            self.last_uuid
+        });
+        self.tracks.insert(key.clone(), uuid);
+        if !name.is_empty() {
+            self.names.entry(key.clone()).or_insert_with(|| name.to_owned());
+        }
+        let parent = match key {
+            TrackKey::Process(_) => None,
+            TrackKey::Thread(pid, _) | TrackKey::Async(pid, ..) | TrackKey::Counter(pid, ..) => {
+                let process = self.name(&TrackKey::Process(pid));
+                Some(self.track(TrackKey::Process(pid), &process))
+            }
+        };
+        let name = self.name(&key);
+        self.describe(uuid, parent, &key, &name);
+        uuid
+    }
+
+    fn name(&self, key: &TrackKey) -> String {
+        self.names.get(key).cloned().unwrap_or_default()
+    }
+
+    fn describe(&mut self, uuid: u64, parent: Option<u64>, key: &TrackKey, name: &str) {
+        let mut track = Message::default();
+        track.uint(TRACK_UUID, uuid);
+        if let Some(parent) = parent {
+            track.uint(TRACK_PARENT_UUID, parent);
+        }
+        match *key {
+            TrackKey::Process(pid) => {
+                let mut process = Message::default();
+                process.uint(PROCESS_PID, u64::from(pid));
+                if !name.is_empty() {
+                    process.string(PROCESS_NAME, name);
+                }
+                track.message(TRACK_PROCESS, process);
+            }
+            TrackKey::Thread(pid, tid) => {
+                let mut thread = Message::default();
+                thread.uint(THREAD_PID, u64::from(pid));
+                thread.uint(THREAD_TID, tid);
+                if !name.is_empty() {
+                    thread.string(THREAD_NAME, name);
+                }
+                track.message(TRACK_THREAD, thread);
+            }
+            TrackKey::Async(..) => track.string(TRACK_NAME, name),
+            TrackKey::Counter(..) => {
+                track.string(TRACK_NAME, name);
+                track.message(TRACK_COUNTER, Message::default());
+            }
+        }
+        let mut packet = Message::default();
+        packet.message(PACKET_TRACK_DESCRIPTOR, track);
+        self.packet(packet)
+    }
+
+    /// Renames a thread or process track, describing it again if it's
+    /// already been described.
+    fn rename(&mut self, key: TrackKey, name: &str) {
+        self.names.insert(key.clone(), name.to_owned());
+        match self.tracks.get(&key).cloned() {
+            Some(uuid) => {
+                let parent = match key {
+                    TrackKey::Thread(pid, _) => self.tracks.get(&TrackKey::Process(pid)).cloned(),
+                    _ => None,
+                };
+                self.describe(uuid, parent, &key, name)
+            }
+            None => drop(self.track(key, name)),
+        }
+    }
+
+    fn track_event(
+        &mut self,
+        ts: u64,
+        track: u64,
+        kind: u64,
+        event: Option<&TraceEvent>,
+        value: Option<f64>,
+    ) {
+        let mut track_event = Message::default();
+        track_event.uint(EVENT_TYPE, kind);
+        track_event.uint(EVENT_TRACK_UUID, track);
+        if let Some(event) = event {
+            track_event.string(EVENT_NAME, &event.name);
+            for category in event.cat.split(',').filter(|c| !c.is_empty()) {
+                track_event.string(EVENT_CATEGORIES, category);
+            }
+            for (name, value) in &event.args {
+                track_event.message(EVENT_ANNOTATIONS, annotation(name, value));
+            }
+        }
+        if let Some(value) = value {
+            track_event.double(EVENT_DOUBLE_COUNTER_VALUE, value);
+        }
+        let mut packet = Message::default();
+        // Perfetto counts in nanoseconds.
+        packet.uint(PACKET_TIMESTAMP, ts * 1000);
+        if let Some(clock) = self.clock {
+            packet.uint(PACKET_TIMESTAMP_CLOCK_ID, clock);
+        }
+        packet.message(PACKET_TRACK_EVENT, track_event);
+        self.packet(packet)
+    }
+
+    pub(crate) fn encode(&mut self, event: &TraceEvent) {
+        let thread = TrackKey::Thread(event.pid, event.tid);
+        match event.ph {
+            "M" => {
+                let name = event.args.get("name").and_then(Value::as_str).unwrap_or("");
+                match &*event.name {
+                    "process_name" => self.rename(TrackKey::Process(event.pid), name),
+                    "thread_name" => self.rename(thread, name),
+                    _ => {}
+                }
+            }
+            "X" => {
+                let track = self.track(thread, "");
+                self.track_event(event.ts, track, TYPE_SLICE_BEGIN, Some(event), None);
+                let end = event.ts + event.dur.unwrap_or(0);
+                self.track_event(end, track, TYPE_SLICE_END, None, None)
+            }
+            "B" => {
+                let track = self.track(thread, "");
+                self.track_event(event.ts, track, TYPE_SLICE_BEGIN, Some(event), None)
+            }
+            "E" => {
+                let track = self.track(thread, "");
+                self.track_event(event.ts, track, TYPE_SLICE_END, None, None)
+            }
+            "I" | "i" => {
+                let key = match event.scope {
+                    Some("t") => thread,
+                    _ => TrackKey::Process(event.pid),
+                };
+                let track = self.track(key, "");
+                self.track_event(event.ts, track, TYPE_INSTANT, Some(event), None)
+            }
+            "b" | "e" => {
+                let key = TrackKey::Async(event.pid, event.cat.clone(), event.id.unwrap_or(0));
+                let track = self.track(key, &event.name);
+                if event.ph == "b" {
+                    self.track_event(event.ts, track, TYPE_SLICE_BEGIN, Some(event), None)
+                } else {
+                    self.track_event(event.ts, track, TYPE_SLICE_END, None, None)
+                }
+            }
+            "C" => {
+                for (series, value) in &event.args {
+                    let value = match value.as_f64() {
+                        Some(value) => value,
+                        None => continue,
+                    };
+                    let name = if event.args.len() == 1 {
+                        event.name.to_string()
+                    } else {
+                        format!("{} {}", event.name, series)
+                    };
+                    let key = TrackKey::Counter(event.pid, event.name.clone(), series.clone());
+                    let track = self.track(key, &name);
+                    self.track_event(event.ts, track, TYPE_COUNTER, None, Some(value));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Writes events as a Perfetto protobuf trace (a stream of `TracePacket`s
+/// holding track events), which ui.perfetto.dev loads and which is much
+/// smaller than JSON for long traces.
+///
+/// Slices, instants, async slices and counters are written; flows and
+/// object snapshots aren't.
+pub struct PerfettoSink<W> {
+    writer: W,
+    encoder: Encoder,
+}
+
+impl<W: Write> PerfettoSink<W> {
+    pub fn new(writer: W) -> Self {
+        PerfettoSink {
+            writer,
+            encoder: Encoder::new(),
+        }
+    }
+}
+
+fn annotation(name: &str, value: &Value) -> Message {
+    let mut annotation = Message::default();
+    annotation.string(ANNOTATION_NAME, name);
+    match *value {
+        Value::Bool(b) => annotation.uint(ANNOTATION_BOOL, b as u64),
+        Value::Number(ref n) => match n.as_i64() {
+            Some(i) => annotation.int(ANNOTATION_INT, i),
+            None => annotation.double(ANNOTATION_DOUBLE, n.as_f64().unwrap_or(0.0)),
+        },
+        Value::String(ref s) => annotation.string(ANNOTATION_STRING, s),
+        ref other => annotation.string(ANNOTATION_STRING, &other.to_string()),
+    }
+    annotation
+}
+
+impl<W: Write + Send> TraceSink for PerfettoSink<W> {
+    fn write_event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        self.encoder.encode(event);
+        for packet in self.encoder.packets() {
+            let mut trace = Message::default();
+            trace.message(TRACE_PACKET, packet);
+            self.writer.write_all(&trace.0)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded<F: FnOnce(&mut Message)>(f: F) -> Vec<u8> {
+        let mut message = Message::default();
+        f(&mut message);
+        message.0
+    }
+
+    fn complete(name: &'static str, tid: u64, ts: u64, dur: u64) -> TraceEvent {
+        let mut event = TraceEvent::new("X", name, "", ts);
+        event.pid = 7;
+        event.tid = tid;
+        event.dur = Some(dur);
+        event
+    }
+
+    /// The `TracePacket`s in a trace, as written by `PerfettoSink::packet`.
+    fn packets(mut trace: &[u8]) -> Vec<&[u8]> {
+        let mut packets = Vec::new();
+        while !trace.is_empty() {
+            assert_eq!(trace[0], 0x0a);
+            let (mut len, mut read) = (0, 1);
+            loop {
+                let byte = trace[read];
+                len |= usize::from(byte & 0x7f) << (7 * (read - 1));
+                read += 1;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+            packets.push(&trace[read..read + len]);
+            trace = &trace[read + len..];
+        }
+        packets
+    }
+
+    #[test]
+    fn varints() {
+        assert_eq!(encoded(|m| m.varint(0)), [0x00]);
+        assert_eq!(encoded(|m| m.varint(1)), [0x01]);
+        assert_eq!(encoded(|m| m.varint(127)), [0x7f]);
+        assert_eq!(encoded(|m| m.varint(128)), [0x80, 0x01]);
+        assert_eq!(encoded(|m| m.varint(300)), [0xac, 0x02]);
+        let max = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert_eq!(encoded(|m| m.varint(u64::MAX)), max);
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(encoded(|m| m.key(TRACE_PACKET, 2)), [0x0a]);
+        assert_eq!(encoded(|m| m.key(PACKET_TIMESTAMP, 0)), [0x40]);
+        assert_eq!(encoded(|m| m.key(EVENT_NAME, 2)), [0xba, 0x01]);
+        assert_eq!(encoded(|m| m.key(PACKET_TRACK_DESCRIPTOR, 2)), [0xe2, 0x03]);
+    }
+
+    #[test]
+    fn complete_event() {
+        let mut sink = PerfettoSink::new(Vec::new());
+        sink.write_event(&complete("work", 9, 5, 2)).unwrap();
+        let expected: &[&[u8]] = &[
+            // The process track, uuid 2, starting the sequence.
+            &[0xe2, 0x03, 0x06, 0x08, 0x02, 0x1a, 0x02, 0x08, 0x07, 0x50, 0x01, 0x68, 0x01],
+            // The thread track, uuid 1, in it.
+            &[
+                0xe2, 0x03, 0x0a, 0x08, 0x01, 0x28, 0x02, 0x22, 0x04, 0x08, 0x07, 0x10, 0x09,
+                0x50, 0x01,
+            ],
+            // The slice beginning at 5000ns, named `work`.
+            &[
+                0x40, 0x88, 0x27, 0x5a, 0x0b, 0x48, 0x01, 0x58, 0x01, 0xba, 0x01, 0x04, b'w',
+                b'o', b'r', b'k', 0x50, 0x01,
+            ],
+            // And ending at 7000ns.
+            &[0x40, 0xd8, 0x36, 0x5a, 0x04, 0x48, 0x02, 0x58, 0x01, 0x50, 0x01],
+        ];
+        assert_eq!(packets(&sink.writer), expected);
+    }
+
+    #[test]
+    fn tracks_are_described_once() {
+        let mut sink = PerfettoSink::new(Vec::new());
+        sink.write_event(&complete("a", 9, 0, 1)).unwrap();
+        sink.write_event(&complete("b", 9, 1, 1)).unwrap();
+        sink.write_event(&complete("c", 10, 2, 1)).unwrap();
+        sink.write_event(&complete("d", 10, 3, 1)).unwrap();
+        let descriptors = packets(&sink.writer)
+            .into_iter()
+            .filter(|packet| packet.starts_with(&[0xe2, 0x03]))
+            .count();
+        // The process and its two threads.
+        assert_eq!(descriptors, 3);
+    }
+}
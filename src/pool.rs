@@ -0,0 +1,135 @@
+use std::cell::Cell;
+use std::sync::Mutex;
+use std::thread;
+
+use crate::event;
+
+#[derive(Clone, Copy)]
+enum Membership {
+    Unknown,
+    Outside,
+    Worker(usize),
+}
+
+thread_local! {
+    static MEMBERSHIP: Cell<Membership> = const { Cell::new(Membership::Unknown) };
+}
+
+/// A thread that just joined a pool and still needs naming in the trace.
+pub(crate) struct Joined {
+    pub pool: usize,
+    pub thread_name: String,
+    pub sort_index: usize,
+    /// Whether this is the pool's first worker.
+    pub first: bool,
+}
+
+struct Pool {
+    name: String,
+    active: usize,
+}
+
+#[derive(Default)]
+pub(crate) struct Pools {
+    pools: Mutex<Vec<Pool>>,
+}
+
+impl Pools {
+    /// Finds the pool called `name`, adding it if it's new.
+    fn pool_index(pools: &mut Vec<Pool>, name: &str) -> (usize, bool) {
+        match pools.iter().position(|pool| pool.name == name) {
+            Some(index) => (index, false),
+            None => {
+                pools.push(Pool {
+                    name: name.to_owned(),
+                    active: 0,
+                });
+                (pools.len() - 1, true)
+            }
+        }
+    }
+
+    /// Marks the current thread as worker `index` of `pool`.
+    pub fn join(&self, pool: &str, index: usize) -> Joined {
+        let (pool, first) = Pools::pool_index(&mut self.pools.lock().unwrap(), pool);
+        MEMBERSHIP.with(|m| m.set(Membership::Worker(pool)));
+        let name = thread::current()
+            .name()
+            .map(str::to_owned)
+            .unwrap_or_else(|| format!("{} worker {}", self.name(pool), index));
+        Joined {
+            pool,
+            thread_name: name,
+            // Keep each pool's workers next to each other, in index order.
+            sort_index: (pool + 1) * 1000 + index,
+            first,
+        }
+    }
+
+    /// The pool the current thread works for. The first time a thread is
+    /// seen, its name is checked against `prefixes` (e.g. `rayon-worker-3`
+    /// under the `rayon` prefix).
+    pub fn current(&self, prefixes: &[String]) -> (Option<usize>, Option<Joined>) {
+        match MEMBERSHIP.with(Cell::get) {
+            Membership::Worker(pool) => (Some(pool), None),
+            Membership::Outside => (None, None),
+            Membership::Unknown => {
+                let thread = thread::current();
+                let detected = thread.name().and_then(|name| {
+                    prefixes
+                        .iter()
+                        .find(|prefix| name.starts_with(prefix.as_str()))
+                        .map(|prefix| (prefix, trailing_number(name)))
+                });
+                match detected {
+                    Some((prefix, index)) => {
+                        let joined = self.join(prefix, index);
+                        (Some(joined.pool), Some(joined))
+                    }
+                    None => {
+                        MEMBERSHIP.with(|m| m.set(Membership::Outside));
+                        (None, None)
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn name(&self, pool: usize) -> String {
+        self.pools.lock().unwrap()[pool].name.clone()
+    }
+
+    /// Adjusts the number of busy workers, returning the new count.
+    pub fn set_busy(&self, pool: usize, busy: bool) -> usize {
+        let mut pools = self.pools.lock().unwrap();
+        let pool = &mut pools[pool];
+        if busy {
+            pool.active += 1;
+        } else {
+            pool.active = pool.active.saturating_sub(1);
+        }
+        pool.active
+    }
+}
+
+/// The pool the current thread is known to work for, without checking its
+/// name.
+pub(crate) fn worker_of() -> Option<usize> {
+    match MEMBERSHIP.with(Cell::get) {
+        Membership::Worker(pool) => Some(pool),
+        _ => None,
+    }
+}
+
+/// The made-up process id `pool`'s workers are shown under, see
+/// `ChromeTraceBuilder::thread_pool_groups`: the real one with the pool
+/// number above it, so it stays apart from other processes' in a merged
+/// trace.
+pub(crate) fn pool_pid(pool: usize) -> u32 {
+    ((pool as u32 + 1) << 24) | (event::pid() & 0xff_ffff)
+}
+
+fn trailing_number(name: &str) -> usize {
+    let digits = name.len() - name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    name[name.len() - digits..].parse().unwrap_or(0)
+}
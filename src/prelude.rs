@@ -0,0 +1,21 @@
+//! Everything a typical setup needs, in one import:
+//!
+//! ```ignore
+//! use tokio_trace_chrome_trace::prelude::*;
+//!
+//! let _guard = chrome_trace::init("trace.json")?;
+//! ```
+
+pub use crate as chrome_trace;
+#[cfg(feature = "tracing")]
+pub use crate::ChromeTraceLayer;
+pub use crate::{
+    ChromeTraceBuilder, ChromeTraceHandle, ChromeTraceSubscriber, Error, FlushGuard, JsonSink,
+    MaybeChromeTraceSubscriber, ShutdownReason, TraceEvent, TraceSink,
+};
+pub use crate::{
+    COLOR_FIELD, COUNTER_FIELD, DURATION_FIELD, ID_FIELD, LINK_FIELD, LINK_LABEL_FIELD,
+    OBJECT_FIELD, PHASE_FIELD,
+};
+pub use tracing_core::span::Id;
+pub use tracing_core::{Dispatch, Level, Metadata, Subscriber};
@@ -0,0 +1,128 @@
+use std::fs;
+use std::time::Duration;
+
+use crate::json::Value;
+use crate::TraceEvent;
+
+/// The kernel reports CPU times in clock ticks of `USER_HZ`, which is 100
+/// on every mainstream architecture.
+const TICK_US: u64 = 10_000;
+
+/// Samples `/proc/self/task`: one cumulative CPU time counter per thread.
+pub(crate) fn thread_cpu(ts: u64) -> Vec<TraceEvent> {
+    let tasks = match fs::read_dir("/proc/self/task") {
+        Ok(tasks) => tasks,
+        Err(_) => return Vec::new(),
+    };
+    let mut events = Vec::new();
+    for task in tasks.filter_map(Result::ok) {
+        let stat = match fs::read_to_string(task.path().join("stat")) {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+        if let Some((name, cpu_us)) = parse_stat(&stat) {
+            let tid = task.file_name().to_string_lossy().into_owned();
+            let mut event = TraceEvent::new("C", format!("cpu {} ({})", name, tid), String::new(), ts);
+            event.args.insert("ms".to_owned(), Value::from(cpu_us / 1000));
+            events.push(event);
+        }
+    }
+    events
+}
+
+/// How long ago the process started: the system's uptime less the uptime
+/// at which it started (`starttime`, field 22 of `/proc/self/stat`).
+pub(crate) fn process_age() -> Option<Duration> {
+    let stat = fs::read_to_string("/proc/self/stat").ok()?;
+    let close = stat.rfind(')')?;
+    let started: u64 = stat[close + 1..].split_whitespace().nth(19)?.parse().ok()?;
+    let uptime = fs::read_to_string("/proc/uptime").ok()?;
+    let uptime: f64 = uptime.split_whitespace().next()?.parse().ok()?;
+    let uptime_us = (uptime * 1_000_000.0) as u64;
+    Some(Duration::from_micros(uptime_us.saturating_sub(started * TICK_US)))
+}
+
+/// Pulls the thread name and user plus system CPU time out of a `stat`
+/// line. The name is in parentheses and may itself contain spaces or `)`.
+fn parse_stat(stat: &str) -> Option<(&str, u64)> {
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let name = &stat[open + 1..close];
+    // Fields after the name start at `state`, field 3; utime and stime are
+    // fields 14 and 15.
+    let mut fields = stat[close + 1..].split_whitespace().skip(11);
+    let utime: u64 = fields.next()?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+    Some((name, (utime + stime) * TICK_US))
+}
+
+/// Turns the running context switch totals of the process's threads into
+/// per-interval counters.
+#[derive(Default)]
+pub(crate) struct ContextSwitches {
+    voluntary: u64,
+    involuntary: u64,
+}
+
+impl ContextSwitches {
+    pub fn sample(&mut self, ts: u64) -> Vec<TraceEvent> {
+        let tasks = match fs::read_dir("/proc/self/task") {
+            Ok(tasks) => tasks,
+            Err(_) => return Vec::new(),
+        };
+        let (mut voluntary, mut involuntary) = (0, 0);
+        for task in tasks.filter_map(Result::ok) {
+            let status = match fs::read_to_string(task.path().join("status")) {
+                Ok(status) => status,
+                Err(_) => continue,
+            };
+            voluntary += status_field(&status, "voluntary_ctxt_switches").unwrap_or(0);
+            involuntary += status_field(&status, "nonvoluntary_ctxt_switches").unwrap_or(0);
+        }
+        // Threads that exited take their counts with them, so the totals can
+        // go down.
+        let mut event = TraceEvent::new("C", "context switches".to_owned(), String::new(), ts);
+        event.args.insert(
+            "voluntary".to_owned(),
+            Value::from(voluntary.saturating_sub(self.voluntary)),
+        );
+        event.args.insert(
+            "involuntary".to_owned(),
+            Value::from(involuntary.saturating_sub(self.involuntary)),
+        );
+        self.voluntary = voluntary;
+        self.involuntary = involuntary;
+        vec![event]
+    }
+}
+
+fn status_field(status: &str, name: &str) -> Option<u64> {
+    status
+        .lines()
+        .find(|line| line.starts_with(name) && line[name.len()..].starts_with(':'))
+        .and_then(|line| line[name.len() + 1..].trim().parse().ok())
+}
+
+/// Samples `/proc/self/fd`: counts of open file descriptors and, of those,
+/// sockets.
+pub(crate) fn open_fds(ts: u64) -> Vec<TraceEvent> {
+    let fds = match fs::read_dir("/proc/self/fd") {
+        Ok(fds) => fds,
+        Err(_) => return Vec::new(),
+    };
+    let (mut open, mut sockets) = (0u64, 0u64);
+    for fd in fds.filter_map(Result::ok) {
+        open += 1;
+        let is_socket = fs::read_link(fd.path())
+            .map(|target| target.to_string_lossy().starts_with("socket:"))
+            .unwrap_or(false);
+        if is_socket {
+            sockets += 1;
+        }
+    }
+    // Reading the directory itself holds one descriptor open.
+    let mut event = TraceEvent::new("C", "open fds".to_owned(), String::new(), ts);
+    event.args.insert("fds".to_owned(), Value::from(open.saturating_sub(1)));
+    event.args.insert("sockets".to_owned(), Value::from(sockets));
+    vec![event]
+}
@@ -0,0 +1,102 @@
+use crate::json::{Map, Value};
+use crate::{target_matches, TraceEvent};
+
+/// How much of the trace the events matching a `ChromeTraceBuilder::quota`
+/// pattern may take up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Quota {
+    /// At most this many events.
+    Events(u64),
+    /// At most this many bytes of JSON.
+    Bytes(u64),
+    /// At most this percentage of the events written so far, counted over
+    /// at least the first hundred.
+    Percent(u8),
+}
+
+struct Rule {
+    /// A target or category; a trailing `::*` is allowed and ignored.
+    pattern: String,
+    quota: Quota,
+    events: u64,
+    bytes: u64,
+    dropped: u64,
+}
+
+impl Rule {
+    fn matches(&self, event: &TraceEvent) -> bool {
+        let prefix = self.pattern.trim_end_matches("::*");
+        target_matches(event.target, prefix) || target_matches(&event.cat, prefix)
+    }
+}
+
+/// Applies the quotas on the writer thread, after transforms.
+pub(crate) struct Quotas {
+    rules: Vec<Rule>,
+    /// Events written so far, metadata aside.
+    total: u64,
+}
+
+impl Quotas {
+    pub fn new(quotas: &[(String, Quota)]) -> Self {
+        let rules = quotas
+            .iter()
+            .map(|(pattern, quota)| Rule {
+                pattern: pattern.clone(),
+                quota: *quota,
+                events: 0,
+                bytes: 0,
+                dropped: 0,
+            })
+            .collect();
+        Quotas { rules, total: 0 }
+    }
+
+    /// Whether `event` fits in the quota of the first pattern it matches,
+    /// counting it against the quota if so and as dropped if not.
+    pub fn admit(&mut self, event: &TraceEvent) -> bool {
+        if event.ph == "M" {
+            return true;
+        }
+        let total = self.total + 1;
+        let rule = match self.rules.iter_mut().find(|rule| rule.matches(event)) {
+            Some(rule) => rule,
+            None => {
+                self.total = total;
+                return true;
+            }
+        };
+        let admit = match rule.quota {
+            Quota::Events(max) => rule.events < max,
+            Quota::Bytes(max) => {
+                let mut json = Vec::new();
+                event.write_json(&mut json, false);
+                let bytes = rule.bytes + json.len() as u64;
+                if bytes <= max {
+                    rule.bytes = bytes;
+                }
+                bytes <= max
+            }
+            Quota::Percent(percent) => (rule.events + 1) * 100 <= u64::from(percent) * total.max(100),
+        };
+        if admit {
+            rule.events += 1;
+            self.total = total;
+        } else {
+            rule.dropped += 1;
+        }
+        admit
+    }
+
+    /// Events dropped by each pattern, for the `chrome_trace_summary`
+    /// event.
+    pub fn to_arg(&self) -> Value {
+        let mut dropped = Map::new();
+        for rule in &self.rules {
+            if rule.dropped > 0 {
+                dropped.insert(rule.pattern.clone(), Value::from(rule.dropped));
+            }
+        }
+        Value::Object(dropped)
+    }
+}
@@ -0,0 +1,90 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::budget::event_size;
+use crate::json::{Map, Value};
+use crate::TraceEvent;
+
+/// The rate is reconsidered this often, in microseconds.
+const WINDOW_US: u64 = 1_000_000;
+
+/// What `ChromeTraceBuilder::adaptive_sampling` keeps the trace under.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateLimit {
+    EventsPerSecond(u64),
+    /// Roughly estimated, as for `ChromeTraceBuilder::memory_limit`.
+    BytesPerSecond(u64),
+}
+
+/// Keeps one in every `every` events, picking `every` from how many events
+/// (or bytes) were offered over the last window.
+pub(crate) struct Sampler {
+    limit: RateLimit,
+    every: AtomicUsize,
+    seen: AtomicUsize,
+    /// Events or bytes offered in the current window, kept or not.
+    offered: AtomicU64,
+    /// When the current window ends, in microseconds since the subscriber
+    /// started.
+    window_end: AtomicU64,
+}
+
+impl Sampler {
+    pub fn new(limit: RateLimit) -> Self {
+        Sampler {
+            limit,
+            every: AtomicUsize::new(1),
+            seen: AtomicUsize::new(0),
+            offered: AtomicU64::new(0),
+            window_end: AtomicU64::new(WINDOW_US),
+        }
+    }
+
+    /// Whether to keep `event`, seen at `now`, plus the metadata to write if
+    /// the rate just changed.
+    pub fn sample(&self, now: u64, event: &TraceEvent) -> (bool, Option<TraceEvent>) {
+        let changed = self.adjust(now);
+        self.offered.fetch_add(self.cost(event), Ordering::Relaxed);
+        let every = self.every.load(Ordering::Relaxed);
+        let keep = self.seen.fetch_add(1, Ordering::Relaxed).is_multiple_of(every);
+        (keep, changed)
+    }
+
+    /// What `event` counts for against the limit.
+    fn cost(&self, event: &TraceEvent) -> u64 {
+        match self.limit {
+            RateLimit::EventsPerSecond(_) => 1,
+            RateLimit::BytesPerSecond(_) => event_size(event) as u64,
+        }
+    }
+
+    /// Starts a new window if the current one is over. Only one thread gets
+    /// to do it.
+    fn adjust(&self, now: u64) -> Option<TraceEvent> {
+        let end = self.window_end.load(Ordering::Relaxed);
+        if now < end {
+            return None;
+        }
+        if self
+            .window_end
+            .compare_exchange(end, now + WINDOW_US, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return None;
+        }
+        let offered = self.offered.swap(0, Ordering::Relaxed);
+        let elapsed = now - (end - WINDOW_US);
+        let per_second = offered.saturating_mul(1_000_000) / elapsed.max(1);
+        let limit = match self.limit {
+            RateLimit::EventsPerSecond(limit) | RateLimit::BytesPerSecond(limit) => limit.max(1),
+        };
+        let every = per_second.div_ceil(limit).max(1) as usize;
+        if self.every.swap(every, Ordering::Relaxed) == every {
+            return None;
+        }
+        let mut args = Map::new();
+        args.insert("keep_one_in".to_owned(), Value::from(every));
+        args.insert("offered_per_second".to_owned(), Value::from(per_second));
+        args.insert("since_us".to_owned(), Value::from(now));
+        Some(TraceEvent::metadata("sampling_rate", args))
+    }
+}
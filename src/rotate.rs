@@ -0,0 +1,156 @@
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::json::Value;
+use crate::{ClosedSpan, JsonSink, TraceEvent, TraceSink, SUMMARY_EVENT};
+
+/// When `RotatingSink` moves on to the next file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RotateAfter {
+    Events(usize),
+    Bytes(u64),
+}
+
+/// A file that knows how much has been written to it.
+struct Counted {
+    file: File,
+    written: u64,
+}
+
+impl Write for Counted {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for Counted {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let at = self.file.seek(pos)?;
+        self.written = at;
+        Ok(at)
+    }
+}
+
+/// Splits the trace over numbered files — `trace.json` becomes
+/// `trace-0001.json`, `trace-0002.json` and so on — each a complete trace
+/// that loads on its own, so a long-running server never has one
+/// ever-growing file. Process and thread names are repeated at the top of
+/// every file, and every file but the last ends with a summary event whose
+/// `reason` is `"rotation"`, naming the file that follows.
+pub struct RotatingSink {
+    path: PathBuf,
+    after: RotateAfter,
+    part: usize,
+    current: JsonSink<Counted>,
+    events: usize,
+    /// Timestamp of the latest event, for the rotation summary.
+    last_ts: u64,
+    metadata: Vec<TraceEvent>,
+}
+
+impl RotatingSink {
+    /// Creates the first file; the rest are created as they're needed.
+    pub fn new<P: AsRef<Path>>(path: P, after: RotateAfter) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let current = open(&path, 1)?;
+        Ok(RotatingSink {
+            path,
+            after,
+            part: 1,
+            current,
+            events: 0,
+            last_ts: 0,
+            metadata: Vec::new(),
+        })
+    }
+
+    /// The file being written to now.
+    pub fn current_path(&self) -> PathBuf {
+        part_path(&self.path, self.part)
+    }
+
+    fn full(&self) -> bool {
+        match self.after {
+            RotateAfter::Events(max) => self.events >= max,
+            RotateAfter::Bytes(max) => self.current.get_ref().written >= max,
+        }
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let mut summary = TraceEvent::new("I", SUMMARY_EVENT.to_owned(), "chrome_trace".to_owned(), self.last_ts);
+        summary.scope = Some("g");
+        summary.args.insert("reason".to_owned(), Value::from("rotation"));
+        let next = part_path(&self.path, self.part + 1);
+        summary.args.insert("next".to_owned(), Value::from(next.to_string_lossy().into_owned()));
+        self.current.write_event(&summary)?;
+        self.current.finish()?;
+        self.part += 1;
+        self.current = open(&self.path, self.part)?;
+        self.current.start()?;
+        self.events = 0;
+        for event in &self.metadata {
+            self.current.write_event(event)?;
+        }
+        Ok(())
+    }
+}
+
+fn part_path(path: &Path, part: usize) -> PathBuf {
+    let stem = path.file_stem().map_or_else(|| "trace".into(), |stem| stem.to_string_lossy());
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{:04}.{}", stem, part, ext.to_string_lossy()),
+        None => format!("{}-{:04}", stem, part),
+    };
+    path.with_file_name(name)
+}
+
+fn open(path: &Path, part: usize) -> io::Result<JsonSink<Counted>> {
+    let file = File::create(part_path(path, part))?;
+    Ok(JsonSink::seekable(Counted { file, written: 0 }))
+}
+
+impl TraceSink for RotatingSink {
+    fn start(&mut self) -> io::Result<()> {
+        self.current.start()
+    }
+
+    fn write_event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        if event.ph == "M" {
+            self.metadata.push(event.clone());
+        } else {
+            if self.full() {
+                self.rotate()?;
+            }
+            self.events += 1;
+            self.last_ts = self.last_ts.max(event.ts);
+        }
+        self.current.write_event(event)
+    }
+
+    fn system_trace(&mut self, text: &str) -> io::Result<()> {
+        self.current.system_trace(text)
+    }
+
+    fn span_closed(&mut self, span: &ClosedSpan) -> io::Result<()> {
+        self.current.span_closed(span)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.current.flush()
+    }
+
+    fn checkpoint(&mut self) -> io::Result<()> {
+        self.current.checkpoint()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.current.finish()
+    }
+}
@@ -0,0 +1,57 @@
+use std::sync::atomic::AtomicUsize;
+use tracing_core::Metadata;
+
+use crate::target_matches;
+
+/// Describes an async runtime's own instrumentation, so its task spans can
+/// be recognized and turned into scheduler counters.
+#[derive(Clone, Copy, Debug)]
+pub struct RuntimePreset {
+    name: &'static str,
+    targets: &'static [&'static str],
+    task_spans: &'static [&'static str],
+}
+
+impl RuntimePreset {
+    pub const TOKIO: RuntimePreset =
+        RuntimePreset::new("tokio", &["tokio"], &["task", "runtime.spawn"]);
+    pub const ASYNC_STD: RuntimePreset =
+        RuntimePreset::new("async-std", &["async_std"], &["task", "spawn"]);
+    pub const SMOL: RuntimePreset = RuntimePreset::new(
+        "smol",
+        &["smol", "async_executor", "async_task"],
+        &["task", "spawn"],
+    );
+
+    /// A preset for another executor: spans named one of `task_spans` with a
+    /// target under one of `targets` are treated as tasks.
+    pub const fn new(
+        name: &'static str,
+        targets: &'static [&'static str],
+        task_spans: &'static [&'static str],
+    ) -> Self {
+        RuntimePreset {
+            name,
+            targets,
+            task_spans,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub(crate) fn is_task_span(&self, meta: &Metadata) -> bool {
+        self.task_spans.contains(&meta.name())
+            && self
+                .targets
+                .iter()
+                .any(|prefix| target_matches(meta.target(), prefix))
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct TaskCounts {
+    pub live: AtomicUsize,
+    pub polling: AtomicUsize,
+}
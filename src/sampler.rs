@@ -0,0 +1,34 @@
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Duration;
+
+use crate::channel::Tx;
+use crate::clock::Instant;
+use crate::stats::Stats;
+use crate::writer::Route;
+use crate::{in_micros, Message, TraceEvent};
+
+/// Produces the counter events for one sample, given its timestamp.
+pub(crate) type Sample = Box<dyn FnMut(u64) -> Vec<TraceEvent> + Send>;
+
+/// Calls `sample` every `interval` until `alive` (held by the subscriber)
+/// goes away.
+pub(crate) fn spawn(
+    alive: Weak<()>,
+    interval: Duration,
+    mut sample: Sample,
+    tx: Tx,
+    stats: Arc<Stats>,
+    start: Instant,
+) {
+    thread::spawn(move || {
+        while alive.upgrade().is_some() {
+            for event in sample(in_micros(start.elapsed())) {
+                if !stats.send(&tx, Message::Event(event, Route::Default)) {
+                    return;
+                }
+            }
+            thread::sleep(interval);
+        }
+    });
+}
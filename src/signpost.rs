@@ -0,0 +1,104 @@
+use std::ffi::CString;
+use std::os::raw::{c_char, c_void};
+
+const EVENT: u8 = 0;
+const INTERVAL_BEGIN: u8 = 1;
+const INTERVAL_END: u8 = 2;
+
+#[allow(non_camel_case_types)]
+type os_log_t = *mut c_void;
+
+extern "C" {
+    static __dso_handle: c_void;
+    fn os_log_create(subsystem: *const c_char, category: *const c_char) -> os_log_t;
+    fn _os_signpost_emit_with_name_impl(
+        dso: *const c_void,
+        log: os_log_t,
+        kind: u8,
+        id: u64,
+        name: *const c_char,
+        format: *const c_char,
+        buf: *const u8,
+        size: u32,
+    );
+}
+
+// Signpost names and formats are recorded by address, so like the C macros
+// these have to live in the image's `__oslogstring` section.
+#[link_section = "__TEXT,__oslogstring,cstring_literals"]
+static SPAN: [u8; 5] = *b"span\0";
+#[link_section = "__TEXT,__oslogstring,cstring_literals"]
+static EVENT_NAME: [u8; 6] = *b"event\0";
+#[link_section = "__TEXT,__oslogstring,cstring_literals"]
+static FORMAT: [u8; 11] = *b"%{public}s\0";
+
+/// An `os_log` handle in the Points of Interest category, which Instruments
+/// shows as its own track.
+pub(crate) struct Signposts {
+    log: os_log_t,
+}
+
+// os_log handles may be used from any thread.
+unsafe impl Send for Signposts {}
+unsafe impl Sync for Signposts {}
+
+impl Signposts {
+    pub fn new(subsystem: &str) -> Self {
+        let subsystem = c_string(subsystem);
+        let log = unsafe {
+            os_log_create(
+                subsystem.as_ptr(),
+                b"PointsOfInterest\0".as_ptr() as *const c_char,
+            )
+        };
+        Signposts { log }
+    }
+
+    /// Intervals are matched up by `id`, so span ids keep concurrent spans
+    /// apart. They're offset by one: span ids start at 0, which is
+    /// `OS_SIGNPOST_ID_NULL`.
+    pub fn begin(&self, id: u64, name: &str) {
+        self.emit(INTERVAL_BEGIN, id + 1, &SPAN, name);
+    }
+
+    pub fn end(&self, id: u64, name: &str) {
+        self.emit(INTERVAL_END, id + 1, &SPAN, name);
+    }
+
+    pub fn event(&self, message: &str) {
+        // OS_SIGNPOST_ID_EXCLUSIVE
+        self.emit(EVENT, 0xEEEE_B0B5_B2B6_EEEE, &EVENT_NAME, message);
+    }
+
+    fn emit(&self, kind: u8, id: u64, name: &[u8], message: &str) {
+        if self.log.is_null() {
+            return;
+        }
+        let message = c_string(message);
+        // An os_log argument buffer holding one public string argument:
+        // flags, argument count, then the argument's descriptor, size and
+        // pointer.
+        let mut buf = [0u8; 12];
+        buf[0] = 0x02;
+        buf[1] = 1;
+        buf[2] = 0x22;
+        buf[3] = 8;
+        buf[4..].copy_from_slice(&(message.as_ptr() as u64).to_ne_bytes());
+        unsafe {
+            _os_signpost_emit_with_name_impl(
+                &__dso_handle,
+                self.log,
+                kind,
+                id,
+                name.as_ptr() as *const c_char,
+                FORMAT.as_ptr() as *const c_char,
+                buf.as_ptr(),
+                buf.len() as u32,
+            );
+        }
+    }
+}
+
+fn c_string(s: &str) -> CString {
+    CString::new(s.replace('\0', "")).unwrap_or_default()
+}
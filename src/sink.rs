@@ -0,0 +1,390 @@
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::compact::Dictionary;
+use crate::index::Index;
+use crate::json;
+#[cfg(feature = "manifest")]
+use crate::manifest::Manifest;
+use crate::{ClosedSpan, TraceEvent};
+
+/// A destination for trace events. Sinks are driven from the writer thread,
+/// so every configured sink sees the same stream of events, and only
+/// whatever the sink itself does (serializing, I/O) happens there. Implement
+/// it to send events somewhere this crate doesn't support, and add it with
+/// `ChromeTraceBuilder::sink`.
+///
+/// Errors go to the internal logger (see
+/// `ChromeTraceSubscriber::set_internal_logger`) and are otherwise ignored;
+/// a sink that can't write should keep going as best it can. One whose
+/// destination turns out to be read-only or full is switched off.
+pub trait TraceSink: Send {
+    fn start(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_event(&mut self, event: &TraceEvent) -> io::Result<()>;
+
+    /// Captured ftrace/atrace text to embed alongside the events, for sinks
+    /// whose format has room for it.
+    fn system_trace(&mut self, _text: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether this sink wants `span_closed` calls. Field values are only
+    /// kept for spans if some sink does.
+    fn wants_spans(&self) -> bool {
+        false
+    }
+
+    fn span_closed(&mut self, _span: &ClosedSpan) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Push out anything buffered. Called whenever the writer thread has
+    /// caught up with the instrumented threads.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Leave the output in a loadable state without ending it; more events
+    /// may follow.
+    fn checkpoint(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// How `JsonSink` lays out the events.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JsonFormat {
+    /// A JSON array of events, `[{...},\n{...}\n]`.
+    #[default]
+    Array,
+    /// An array with a comma after every event, including the last. Only
+    /// chrome://tracing's forgiving parser reads it, but every event is
+    /// complete as soon as it's written.
+    LenientArray,
+    /// The JSON Object Format (`{"traceEvents": [...]}`), which can also
+    /// carry `systemTraceEvents`.
+    Object,
+}
+
+/// How `JsonSink` writes characters outside ASCII.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Escaping {
+    /// As UTF-8, the smallest output.
+    #[default]
+    Utf8,
+    /// As `\u` escapes, for consumers that mishandle raw UTF-8.
+    Ascii,
+}
+
+/// Writes events in a format chrome://tracing can load.
+pub struct JsonSink<W = File> {
+    writer: W,
+    /// Steps the writer back by the given number of bytes. Only seekable
+    /// writers support checkpoints.
+    rewind: Option<fn(&mut W, i64) -> io::Result<()>>,
+    /// Completes the writer's output once the trace is done, e.g. a
+    /// compressed stream's trailer.
+    end: Option<fn(&mut W) -> io::Result<()>>,
+    format: JsonFormat,
+    escaping: Escaping,
+    first: bool,
+    system_trace: String,
+    compact: Option<Dictionary>,
+    index: Option<Index>,
+    #[cfg(feature = "manifest")]
+    manifest: Option<Manifest>,
+}
+
+impl JsonSink<File> {
+    /// Writes a bare JSON array of events.
+    pub fn new(writer: File) -> Self {
+        JsonSink::seekable(writer)
+    }
+
+    /// Writes the JSON Object Format (`{"traceEvents": [...]}`), which can
+    /// also carry `systemTraceEvents`.
+    pub fn object(writer: File) -> Self {
+        JsonSink::new(writer).object_format()
+    }
+}
+
+/// A file that isn't created until something is written to it, see
+/// `ChromeTraceBuilder::lazy`.
+pub struct LazyFile {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl LazyFile {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        LazyFile {
+            path: path.as_ref().to_owned(),
+            file: None,
+        }
+    }
+
+    fn file(&mut self) -> io::Result<&mut File> {
+        if self.file.is_none() {
+            self.file = Some(File::create(&self.path)?);
+        }
+        Ok(self.file.as_mut().unwrap())
+    }
+}
+
+impl Write for LazyFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file {
+            Some(ref mut file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Seek for LazyFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file()?.seek(pos)
+    }
+}
+
+impl JsonSink<LazyFile> {
+    /// Writes to the file at `path`, creating (or truncating) it only once
+    /// the writer thread starts. Failing to create it is reported to the
+    /// internal logger then.
+    pub fn lazy_file<P: AsRef<Path>>(path: P) -> Self {
+        JsonSink::seekable(LazyFile::new(path))
+    }
+}
+
+impl<W: Write + Seek> JsonSink<W> {
+    /// Like `from_writer`, but also supports checkpoints.
+    pub fn seekable(writer: W) -> Self {
+        JsonSink {
+            rewind: Some(|writer, by| writer.seek(SeekFrom::Current(-by)).map(drop)),
+            ..JsonSink::from_writer(writer)
+        }
+    }
+}
+
+impl<W: Write> JsonSink<W> {
+    /// Has `finish` end with `end`, for writers that need finishing.
+    #[cfg(any(feature = "encryption", feature = "gzip"))]
+    pub(crate) fn ending_with(mut self, end: fn(&mut W) -> io::Result<()>) -> Self {
+        self.end = Some(end);
+        self
+    }
+
+    /// Writes to any writer. Checkpoints are skipped, since they need to
+    /// seek.
+    pub fn from_writer(writer: W) -> Self {
+        JsonSink {
+            writer,
+            rewind: None,
+            end: None,
+            format: JsonFormat::default(),
+            escaping: Escaping::default(),
+            first: true,
+            system_trace: String::new(),
+            compact: None,
+            index: None,
+            #[cfg(feature = "manifest")]
+            manifest: None,
+        }
+    }
+
+    pub fn format(mut self, format: JsonFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn escaping(mut self, escaping: Escaping) -> Self {
+        self.escaping = escaping;
+        self
+    }
+
+    pub fn object_format(self) -> Self {
+        self.format(JsonFormat::Object)
+    }
+
+    /// Shorten the keys of each event and replace names and categories with
+    /// indexes into a dictionary written inline as `chrome_trace_dictionary`
+    /// metadata records. Much smaller, but has to be run through
+    /// `decode_compact` (or the `chrome-trace-decode` tool) before a viewer
+    /// can load it.
+    pub fn compact(mut self) -> Self {
+        self.compact = Some(Dictionary::default());
+        self
+    }
+
+    /// When the trace is finished, write a JSON manifest to `path` with the
+    /// output's size, SHA-256, event count and time range. Both describe the
+    /// JSON as this sink wrote it, i.e. before any encryption or compression
+    /// done by the writer.
+    #[cfg(feature = "manifest")]
+    pub fn manifest<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.manifest = Some(Manifest::new(path.as_ref().to_owned()));
+        self
+    }
+
+    /// When the trace is finished, write an index to `path` for seeking
+    /// into a large trace without parsing all of it:
+    /// `{"bucket_us": ..., "size": ..., "buckets": [[start_us, offset], ...]}`
+    /// where `offset` is where the first event at or after `start_us` begins
+    /// in the output. Events are written as they're recorded, not sorted,
+    /// so a window should be read from its bucket's offset to a bucket or
+    /// two past its end.
+    pub fn index<P: AsRef<Path>>(mut self, path: P, bucket: Duration) -> Self {
+        self.index = Some(Index::new(path.as_ref().to_owned(), bucket));
+        self
+    }
+
+    pub(crate) fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes)?;
+        if let Some(ref mut index) = self.index {
+            index.update(bytes);
+        }
+        #[cfg(feature = "manifest")]
+        {
+            if let Some(ref mut manifest) = self.manifest {
+                manifest.update(bytes);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes one already serialized event.
+    pub(crate) fn write_json(&mut self, json: &[u8]) -> io::Result<()> {
+        if self.format == JsonFormat::LenientArray {
+            self.write_bytes(json)?;
+            return self.write_bytes(b",\n");
+        }
+        // Separate rather than terminate events, so parsers stricter than
+        // chrome://tracing's read the result.
+        if !self.first {
+            self.write_bytes(b",\n")?;
+        }
+        self.first = false;
+        self.write_bytes(json)
+    }
+
+    fn close(&self) -> io::Result<Vec<u8>> {
+        let mut close = if self.first || self.format == JsonFormat::LenientArray {
+            b"]".to_vec()
+        } else {
+            b"\n]".to_vec()
+        };
+        if self.format != JsonFormat::Object {
+            close.push(b'\n');
+            return Ok(close);
+        }
+        if !self.system_trace.is_empty() {
+            close.extend_from_slice(b",\"systemTraceEvents\":");
+            json::write_str(&mut close, &self.system_trace, self.escaping == Escaping::Ascii);
+        }
+        close.extend_from_slice(b"}\n");
+        Ok(close)
+    }
+}
+
+impl<W: Write + Send> TraceSink for JsonSink<W> {
+    fn start(&mut self) -> io::Result<()> {
+        if self.format == JsonFormat::Object {
+            self.write_bytes(b"{\"traceEvents\":[\n")
+        } else {
+            self.write_bytes(b"[\n")
+        }
+    }
+
+    fn write_event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        let ascii = self.escaping == Escaping::Ascii;
+        let json = match self.compact {
+            Some(ref mut dictionary) => {
+                let (record, encoded) = dictionary.encode(event);
+                if let Some(record) = record {
+                    let mut json = Vec::new();
+                    json::write_value(&mut json, &record, ascii);
+                    self.write_json(&json)?;
+                }
+                let mut json = Vec::new();
+                json::write_value(&mut json, &encoded, ascii);
+                json
+            }
+            None => {
+                let mut json = Vec::with_capacity(128 + 32 * event.args.len());
+                event.write_json(&mut json, ascii);
+                json
+            }
+        };
+        #[cfg(feature = "manifest")]
+        {
+            if let Some(ref mut manifest) = self.manifest {
+                manifest.event(event);
+            }
+        }
+        if let Some(ref mut index) = self.index {
+            // The `,\n` before the event, see `write_json`.
+            let separated = !self.first && self.format != JsonFormat::LenientArray;
+            index.event(event, if separated { 2 } else { 0 });
+        }
+        self.write_json(&json)
+    }
+
+    fn system_trace(&mut self, text: &str) -> io::Result<()> {
+        if !self.system_trace.is_empty() && !self.system_trace.ends_with('\n') {
+            self.system_trace.push('\n');
+        }
+        self.system_trace.push_str(text);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn checkpoint(&mut self) -> io::Result<()> {
+        let rewind = match self.rewind {
+            Some(rewind) => rewind,
+            None => return self.writer.flush(),
+        };
+        // Write the closing bracket, then step back over it so the next
+        // event overwrites it. This bypasses `write_bytes` since it doesn't
+        // stay in the output.
+        let close = self.close()?;
+        self.writer.write_all(&close)?;
+        self.writer.flush()?;
+        rewind(&mut self.writer, close.len() as i64)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let close = self.close()?;
+        self.write_bytes(&close)?;
+        self.writer.flush()?;
+        if let Some(end) = self.end.take() {
+            end(&mut self.writer)?;
+        }
+        if let Some(index) = self.index.take() {
+            index.write()?;
+        }
+        #[cfg(feature = "manifest")]
+        {
+            if let Some(manifest) = self.manifest.take() {
+                manifest.write()?;
+            }
+        }
+        Ok(())
+    }
+}
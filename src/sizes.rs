@@ -0,0 +1,57 @@
+use crate::json::{self, Map, Value};
+use crate::TraceEvent;
+
+/// Upper bounds of the size histogram buckets, in bytes. Anything larger
+/// goes in a final overflow bucket.
+const BUCKETS: &[usize] = &[128, 256, 512, 1024, 4096, 16384, 65536];
+
+/// Serialized sizes of the events written, measured on the writer thread.
+pub(crate) struct EventSizes {
+    threshold: usize,
+    counts: Vec<u64>,
+}
+
+impl EventSizes {
+    pub fn new(threshold: usize) -> Self {
+        EventSizes {
+            threshold,
+            counts: vec![0; BUCKETS.len() + 1],
+        }
+    }
+
+    /// Records `event`'s size, returning a warning to write alongside it if
+    /// it's over the threshold.
+    pub fn measure(&mut self, event: &TraceEvent) -> Option<TraceEvent> {
+        let size = json::to_vec(&event.to_json()).len();
+        let bucket = BUCKETS.iter().position(|&max| size <= max).unwrap_or(BUCKETS.len());
+        self.counts[bucket] += 1;
+        if size <= self.threshold {
+            return None;
+        }
+        let mut warning = TraceEvent::new("I", "oversized event".to_owned(), "chrome_trace".to_owned(), event.ts);
+        warning.pid = event.pid;
+        warning.tid = event.tid;
+        warning.scope = Some("t");
+        warning.cname = Some("bad".to_owned());
+        warning.args.insert("event".to_owned(), Value::from(&*event.name));
+        warning.args.insert("target".to_owned(), Value::from(event.target));
+        if let Some(level) = event.level {
+            warning.args.insert("level".to_owned(), Value::from(level));
+        }
+        warning.args.insert("size".to_owned(), Value::from(size));
+        Some(warning)
+    }
+
+    /// The histogram, as an arg for the summary event.
+    pub fn to_arg(&self) -> Value {
+        let mut histogram = Map::new();
+        for (i, &count) in self.counts.iter().enumerate() {
+            let label = match BUCKETS.get(i) {
+                Some(max) => format!("<={}", max),
+                None => format!(">{}", BUCKETS[BUCKETS.len() - 1]),
+            };
+            histogram.insert(label, Value::from(count));
+        }
+        Value::Object(histogram)
+    }
+}
@@ -0,0 +1,310 @@
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::clock::Instant;
+use crate::in_micros;
+use crate::json::{Map, Value};
+use crate::lanes::Lane;
+
+thread_local! {
+    // The spans this thread is currently inside and when they were entered,
+    // innermost last.
+    static STACK: RefCell<Vec<(u64, Instant)>> = const { RefCell::new(Vec::new()) };
+}
+
+pub(crate) fn push(id: u64) {
+    STACK.with(|stack| stack.borrow_mut().push((id, Instant::now())))
+}
+
+/// Leaves span `id`, returning how long it was entered for and the span that
+/// encloses it, if any.
+pub(crate) fn pop(id: u64) -> Option<(Duration, Option<u64>)> {
+    STACK.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        // Spans are usually exited in order, but don't rely on it.
+        let pos = stack.iter().rposition(|&(entered, _)| entered == id)?;
+        let (_, since) = stack.remove(pos);
+        let parent = if pos > 0 { Some(stack[pos - 1].0) } else { None };
+        Some((since.elapsed(), parent))
+    })
+}
+
+pub(crate) fn depth() -> usize {
+    STACK.with(|stack| stack.borrow().len())
+}
+
+pub(crate) fn current() -> Option<u64> {
+    STACK.with(|stack| stack.borrow().last().map(|&(id, _)| id))
+}
+
+pub(crate) struct SpanState {
+    refs: usize,
+    pub name: &'static str,
+    pub target: &'static str,
+    /// This span, or one of its ancestors, matched the `only_within` filter.
+    pub selected: bool,
+    /// The capture (see `ChromeTraceSubscriber::capture_span`) this span
+    /// belongs to, keyed by the id of the captured span.
+    pub capture: Option<u64>,
+    /// Index of the runtime preset this span is a task of.
+    pub runtime: Option<usize>,
+    pub created: Instant,
+    /// The span that was current when this one was created.
+    pub parent: Option<u64>,
+    /// The outermost span this one is nested in, or its own id.
+    pub root: u64,
+    /// Field values, only kept for exporters and span slices that need
+    /// them.
+    pub fields: Map<String, Value>,
+    /// Whether this span's slices carry its fields as args.
+    pub args: bool,
+    /// The level and source location slices carry too, see
+    /// `ChromeTraceBuilder::source_locations`.
+    pub location: Map<String, Value>,
+    /// The color name its slices are drawn in, see
+    /// `ChromeTraceBuilder::color_fn`.
+    pub cname: Option<String>,
+    /// See `ChromeTraceBuilder::task_lanes`.
+    pub lane: Option<Lane>,
+    /// Whether this span is also written as an object (see `OBJECT_FIELD`).
+    pub object: bool,
+    /// Whether this span declares `COLOR_FIELD`, so recording it can change
+    /// the color.
+    pub recolor: bool,
+    /// Flows from spans this one follows from or is linked from, to end
+    /// when it's next entered: the flow id, category and name.
+    pub flows: Vec<(u64, &'static str, String)>,
+    /// Total time spent entered.
+    pub busy: Duration,
+    /// Time spent entered in spans nested directly inside this one.
+    pub children: Duration,
+}
+
+impl SpanState {
+    pub fn new(name: &'static str, target: &'static str, id: u64) -> Self {
+        SpanState {
+            refs: 1,
+            name,
+            target,
+            selected: false,
+            capture: None,
+            runtime: None,
+            created: Instant::now(),
+            parent: None,
+            root: id,
+            fields: Map::new(),
+            args: false,
+            location: Map::new(),
+            object: false,
+            recolor: false,
+            cname: None,
+            lane: None,
+            flows: Vec::new(),
+            busy: Duration::from_secs(0),
+            children: Duration::from_secs(0),
+        }
+    }
+
+    pub fn self_time(&self) -> Duration {
+        if self.children < self.busy {
+            self.busy - self.children
+        } else {
+            Duration::from_secs(0)
+        }
+    }
+}
+
+/// A span that has closed, as handed to sinks that ask for spans (see
+/// `TraceSink::wants_spans`).
+#[derive(Clone, Debug)]
+pub struct ClosedSpan {
+    pub id: u64,
+    /// The span that was current when this one was created.
+    pub parent: Option<u64>,
+    /// The outermost span this one is nested in, or its own id.
+    pub root: u64,
+    pub name: &'static str,
+    pub target: &'static str,
+    /// When the span was created, in microseconds since the subscriber
+    /// started.
+    pub start: u64,
+    /// When the span was created, in microseconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Microseconds from creation to close.
+    pub duration: u64,
+    pub fields: Map<String, Value>,
+}
+
+#[derive(Default)]
+struct Spans {
+    states: HashMap<u64, SpanState>,
+    /// The open spans oldest first, for eviction to take from the front.
+    order: BTreeSet<(Instant, u64)>,
+}
+
+impl Spans {
+    fn remove(&mut self, id: u64) -> Option<SpanState> {
+        let span = self.states.remove(&id)?;
+        self.order.remove(&(span.created, id));
+        Some(span)
+    }
+
+    fn oldest(&self) -> Option<(Instant, u64)> {
+        self.order.iter().next().copied()
+    }
+}
+
+/// The registry is split by span id into `1 << SHARD_BITS` shards, each with
+/// its own lock, so threads in different spans rarely wait on each other.
+const SHARD_BITS: u32 = 4;
+
+#[derive(Default)]
+pub(crate) struct Registry {
+    shards: [Mutex<Spans>; 1 << SHARD_BITS],
+    len: AtomicUsize,
+}
+
+impl Registry {
+    fn shard(&self, id: u64) -> &Mutex<Spans> {
+        // Sequential ids share low bits, so mix them first.
+        let mixed = id.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        &self.shards[(mixed >> (64 - SHARD_BITS)) as usize]
+    }
+
+    pub fn insert(&self, id: u64, state: SpanState) {
+        let mut spans = self.shard(id).lock().unwrap();
+        if spans.remove(id).is_none() {
+            self.len.fetch_add(1, Ordering::Relaxed);
+        }
+        spans.order.insert((state.created, id));
+        spans.states.insert(id, state);
+    }
+
+    pub fn with<F, R>(&self, id: u64, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut SpanState) -> R,
+    {
+        self.shard(id).lock().unwrap().states.get_mut(&id).map(f)
+    }
+
+    /// Charges `elapsed` to span `id` and, as child time, to `parent`.
+    pub fn add_busy(&self, id: u64, parent: Option<u64>, elapsed: Duration) {
+        self.with(id, |span| span.busy += elapsed);
+        if let Some(parent) = parent {
+            self.with(parent, |parent| parent.children += elapsed);
+        }
+    }
+
+    /// Removes span `id` from the shard `spans`, its own.
+    fn remove(&self, spans: &mut Spans, id: u64) -> Option<SpanState> {
+        let span = spans.remove(id)?;
+        self.len.fetch_sub(1, Ordering::Relaxed);
+        Some(span)
+    }
+
+    /// Removes spans older than `ttl`, then the oldest spans past `max`,
+    /// returning them.
+    pub fn evict(&self, max: Option<usize>, ttl: Option<Duration>) -> Vec<(u64, SpanState)> {
+        let mut evicted = Vec::new();
+        if let Some(ttl) = ttl {
+            for shard in &self.shards {
+                let mut spans = shard.lock().unwrap();
+                while let Some((created, id)) = spans.oldest() {
+                    if created.elapsed() < ttl {
+                        break;
+                    }
+                    evicted.extend(self.remove(&mut spans, id).map(|span| (id, span)));
+                }
+            }
+        }
+        let max = match max {
+            Some(max) => max,
+            None => return evicted,
+        };
+        while self.len() > max {
+            // The oldest span overall is the oldest of one of the shards.
+            let oldest = self
+                .shards
+                .iter()
+                .filter_map(|shard| shard.lock().unwrap().oldest())
+                .min();
+            let id = match oldest {
+                Some((_, id)) => id,
+                None => break,
+            };
+            let mut spans = self.shard(id).lock().unwrap();
+            evicted.extend(self.remove(&mut spans, id).map(|span| (id, span)));
+        }
+        evicted
+    }
+
+    #[cfg(feature = "tracing")]
+    pub fn contains(&self, id: u64) -> bool {
+        self.shard(id).lock().unwrap().states.contains_key(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn clone_span(&self, id: u64) {
+        self.with(id, |span| span.refs += 1);
+    }
+
+    /// Drops one reference, returning the span's state once the last
+    /// reference is gone.
+    pub fn drop_span(&self, id: u64) -> Option<SpanState> {
+        let mut spans = self.shard(id).lock().unwrap();
+        let closed = match spans.states.get_mut(&id) {
+            Some(span) => {
+                span.refs -= 1;
+                span.refs == 0
+            }
+            None => false,
+        };
+        if closed {
+            self.remove(&mut spans, id)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Default)]
+struct Times {
+    count: u64,
+    total: Duration,
+    self_time: Duration,
+}
+
+/// Total and self time of closed spans, by span name.
+#[derive(Default)]
+pub(crate) struct TimeSummary {
+    names: Mutex<HashMap<&'static str, Times>>,
+}
+
+impl TimeSummary {
+    pub fn add(&self, span: &SpanState) {
+        let mut names = self.names.lock().unwrap();
+        let times = names.entry(span.name).or_default();
+        times.count += 1;
+        times.total += span.busy;
+        times.self_time += span.self_time();
+    }
+
+    pub fn to_args(&self) -> Map<String, Value> {
+        let names = self.names.lock().unwrap();
+        let mut args = Map::new();
+        for (name, times) in names.iter() {
+            let mut entry = Map::new();
+            entry.insert("count".to_owned(), Value::from(times.count));
+            entry.insert("total_us".to_owned(), Value::from(in_micros(times.total)));
+            entry.insert("self_us".to_owned(), Value::from(in_micros(times.self_time)));
+            args.insert((*name).to_owned(), Value::Object(entry));
+        }
+        args
+    }
+}
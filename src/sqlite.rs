@@ -0,0 +1,152 @@
+use rusqlite::{params, Connection};
+use std::io;
+use std::path::Path;
+
+use crate::json;
+use crate::{ClosedSpan, TraceEvent, TraceSink};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS events (
+    ts INTEGER NOT NULL,
+    dur INTEGER,
+    ph TEXT NOT NULL,
+    name TEXT NOT NULL,
+    cat TEXT NOT NULL,
+    target TEXT NOT NULL,
+    level TEXT,
+    pid INTEGER NOT NULL,
+    tid INTEGER NOT NULL,
+    span_id INTEGER,
+    args TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS events_ts ON events (ts);
+CREATE INDEX IF NOT EXISTS events_name ON events (name);
+CREATE INDEX IF NOT EXISTS events_target ON events (target);
+CREATE INDEX IF NOT EXISTS events_tid ON events (tid);
+CREATE INDEX IF NOT EXISTS events_span_id ON events (span_id);
+CREATE TABLE IF NOT EXISTS spans (
+    id INTEGER NOT NULL,
+    parent_id INTEGER,
+    root_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    target TEXT NOT NULL,
+    start INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    duration INTEGER NOT NULL,
+    fields TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS spans_id ON spans (id);
+CREATE INDEX IF NOT EXISTS spans_name ON spans (name);
+CREATE INDEX IF NOT EXISTS spans_start ON spans (start);
+";
+
+/// Rows are committed in batches of this many.
+const BATCH: usize = 10_000;
+
+/// Writes events, and spans once they close, to the `events` and `spans`
+/// tables of a SQLite database, for SQL over captures too large for a
+/// timeline viewer. Times are in microseconds since the subscriber started
+/// (`spans.timestamp` is since the Unix epoch); args and fields are JSON.
+pub struct SqliteSink {
+    conn: Connection,
+    pending: usize,
+}
+
+fn to_io(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+impl SqliteSink {
+    /// Opens or creates the database at `path`. Earlier runs' rows are
+    /// kept.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let conn = Connection::open(path).map_err(to_io)?;
+        conn.execute_batch(SCHEMA).map_err(to_io)?;
+        Ok(SqliteSink { conn, pending: 0 })
+    }
+
+    fn begin(&mut self) -> rusqlite::Result<()> {
+        if self.pending == 0 {
+            self.conn.execute_batch("BEGIN")?;
+        }
+        self.pending += 1;
+        if self.pending >= BATCH {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self) -> rusqlite::Result<()> {
+        if self.pending > 0 {
+            self.pending = 0;
+            self.conn.execute_batch("COMMIT")?;
+        }
+        Ok(())
+    }
+}
+
+impl TraceSink for SqliteSink {
+    fn write_event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        self.begin().map_err(to_io)?;
+        let args = json::map_to_string(&event.args);
+        self.conn
+            .prepare_cached(
+                "INSERT INTO events (ts, dur, ph, name, cat, target, level, pid, tid, span_id, args)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            )
+            .and_then(|mut insert| {
+                insert.execute(params![
+                    event.ts as i64,
+                    event.dur.map(|dur| dur as i64),
+                    event.ph,
+                    event.name,
+                    event.cat,
+                    event.target,
+                    event.level,
+                    i64::from(event.pid),
+                    event.tid as i64,
+                    event.span.map(|span| span as i64),
+                    args,
+                ])
+            })
+            .map(drop)
+            .map_err(to_io)
+    }
+
+    fn wants_spans(&self) -> bool {
+        true
+    }
+
+    fn span_closed(&mut self, span: &ClosedSpan) -> io::Result<()> {
+        self.begin().map_err(to_io)?;
+        let fields = json::map_to_string(&span.fields);
+        self.conn
+            .prepare_cached(
+                "INSERT INTO spans (id, parent_id, root_id, name, target, start, timestamp, duration, fields)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )
+            .and_then(|mut insert| {
+                insert.execute(params![
+                    span.id as i64,
+                    span.parent.map(|parent| parent as i64),
+                    span.root as i64,
+                    span.name,
+                    span.target,
+                    span.start as i64,
+                    span.timestamp as i64,
+                    span.duration as i64,
+                    fields,
+                ])
+            })
+            .map(drop)
+            .map_err(to_io)
+    }
+
+    fn checkpoint(&mut self) -> io::Result<()> {
+        self.commit().map_err(to_io)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.commit().map_err(to_io)
+    }
+}
@@ -0,0 +1,34 @@
+use backtrace::Backtrace;
+
+use crate::json::Value;
+
+const MAX_FRAMES: usize = 16;
+
+/// Captures the current call stack as `"symbol (file:line)"` strings,
+/// skipping frames from the tracing machinery itself.
+pub(crate) fn capture() -> Value {
+    let backtrace = Backtrace::new();
+    let frames = backtrace
+        .frames()
+        .iter()
+        .flat_map(|frame| frame.symbols())
+        .filter_map(|symbol| {
+            let name = symbol.name()?.to_string();
+            // Trait impls render as `<path::Type as Trait>::method`.
+            let path = name.trim_start_matches('<');
+            let internal = ["backtrace::", "tokio_trace"]
+                .iter()
+                .any(|prefix| path.starts_with(prefix));
+            if internal {
+                return None;
+            }
+            Some(match (symbol.filename(), symbol.lineno()) {
+                (Some(file), Some(line)) => format!("{} ({}:{})", name, file.display(), line),
+                _ => name,
+            })
+        })
+        .take(MAX_FRAMES)
+        .map(Value::from)
+        .collect();
+    Value::Array(frames)
+}
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::io;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tracing_core::callsite::Identifier;
+use tracing_core::Metadata;
+
+use crate::budget::{event_size, Shed};
+use crate::channel::{SendError, Tx};
+use crate::clock::Instant;
+use crate::json::{Map, Value};
+use crate::{in_micros, Message};
+
+pub(crate) type Logger = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Flushes taking longer than this are reported to the internal logger.
+const SLOW_FLUSH: Duration = Duration::from_millis(100);
+
+/// Why an enabled callsite's event was left out.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Suppressed {
+    Sampled,
+    Filtered,
+    Paused,
+}
+
+/// Events left out, by reason, for one callsite.
+#[derive(Default)]
+struct CallsiteDrops {
+    /// `target name (file:line)`, as it appears in the summary.
+    label: String,
+    sampled: usize,
+    filtered: usize,
+    paused: usize,
+}
+
+/// Counters shared between the instrumented threads and the writer thread,
+/// and the logger both report problems to.
+#[derive(Default)]
+pub(crate) struct Stats {
+    events: AtomicUsize,
+    dropped: AtomicUsize,
+    queued: AtomicUsize,
+    peak_queued: AtomicUsize,
+    /// Rough size of the queued messages, see `budget::event_size`.
+    queued_bytes: AtomicUsize,
+    /// Events dropped because the channel was full.
+    overflowed: AtomicUsize,
+    shed_events: AtomicUsize,
+    shed_args: AtomicUsize,
+    shed_spans: AtomicUsize,
+    /// Events left out by `ChromeTraceBuilder::adaptive_sampling`.
+    sampled_out: AtomicUsize,
+    /// See `ChromeTraceBuilder::callsite_drop_stats`.
+    by_callsite: Option<Mutex<HashMap<Identifier, CallsiteDrops>>>,
+    logger: RwLock<Option<Logger>>,
+}
+
+impl Stats {
+    /// Sends `msg` to the writer thread, keeping count. Returns whether the
+    /// writer thread is still there to receive it.
+    pub fn send(&self, tx: &Tx, msg: Message) -> bool {
+        let is_event = matches!(msg, Message::Event(..));
+        let size = message_size(&msg);
+        let queued = self.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak_queued.fetch_max(queued, Ordering::Relaxed);
+        self.queued_bytes.fetch_add(size, Ordering::Relaxed);
+        let result = tx.send(msg);
+        if result.is_ok() {
+            if is_event {
+                self.events.fetch_add(1, Ordering::Relaxed);
+            }
+            return true;
+        }
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        self.queued_bytes.fetch_sub(size, Ordering::Relaxed);
+        match result {
+            Err(SendError::Full) => {
+                if self.overflowed.fetch_add(1, Ordering::Relaxed) == 0 {
+                    self.log(|| "writer thread is falling behind; dropping events".to_owned());
+                }
+                true
+            }
+            _ => {
+                if self.dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+                    self.log(|| "writer thread has exited; dropping events".to_owned());
+                }
+                false
+            }
+        }
+    }
+
+    /// Called by the writer thread as it takes a message off the queue.
+    pub fn received(&self, msg: &Message) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        self.queued_bytes.fetch_sub(message_size(msg), Ordering::Relaxed);
+    }
+
+    pub fn overflowed(&self) -> usize {
+        self.overflowed.load(Ordering::Relaxed)
+    }
+
+    pub fn queued_bytes(&self) -> usize {
+        self.queued_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Counts something given up to stay under the memory limit.
+    pub fn shed(&self, what: Shed) {
+        let count = match what {
+            Shed::Nothing => return,
+            Shed::Args => &self.shed_args,
+            Shed::Event => &self.shed_events,
+        };
+        if count.fetch_add(1, Ordering::Relaxed) == 0 {
+            self.log(|| format!("memory limit reached; shedding {:?}", what));
+        }
+    }
+
+    pub fn shed_span(&self) {
+        if self.shed_spans.fetch_add(1, Ordering::Relaxed) == 0 {
+            self.log(|| "memory limit reached; not tracking new spans".to_owned());
+        }
+    }
+
+    pub fn sampled_out(&self) {
+        self.sampled_out.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn track_callsites(&mut self) {
+        self.by_callsite = Some(Mutex::new(HashMap::new()));
+    }
+
+    /// Counts an event from `meta` left out for `why`, if callsites are
+    /// being tracked.
+    pub fn suppressed(&self, meta: &Metadata, why: Suppressed) {
+        let by_callsite = match self.by_callsite {
+            Some(ref by_callsite) => by_callsite,
+            None => return,
+        };
+        let mut by_callsite = by_callsite.lock().unwrap();
+        let drops = by_callsite.entry(meta.callsite()).or_insert_with(|| CallsiteDrops {
+            label: format!(
+                "{} {} ({}:{})",
+                meta.target(),
+                meta.name(),
+                meta.file().unwrap_or("?"),
+                meta.line().unwrap_or(0)
+            ),
+            ..Default::default()
+        });
+        match why {
+            Suppressed::Sampled => drops.sampled += 1,
+            Suppressed::Filtered => drops.filtered += 1,
+            Suppressed::Paused => drops.paused += 1,
+        }
+    }
+
+    pub fn set_logger(&self, logger: Logger) {
+        *self.logger.write().unwrap() = Some(logger);
+    }
+
+    /// Reports a problem to the internal logger, if there is one. It's
+    /// called after the lock is released, so a slow logger, or one that
+    /// replaces itself, doesn't hold up other threads.
+    pub fn log<F: FnOnce() -> String>(&self, message: F) {
+        let logger = self.logger.read().unwrap().clone();
+        if let Some(logger) = logger {
+            logger(&message());
+        }
+    }
+
+    /// Reports a failed sink call.
+    pub fn check(&self, what: &str, result: io::Result<()>) {
+        if let Err(e) = result {
+            self.log(|| format!("sink {} failed: {}", what, e));
+        }
+    }
+
+    /// Like `check`, also reporting the call if it was slow.
+    pub fn timed<F: FnOnce() -> io::Result<()>>(&self, what: &str, f: F) {
+        let start = Instant::now();
+        let result = f();
+        let elapsed = start.elapsed();
+        if elapsed >= SLOW_FLUSH {
+            self.log(|| format!("sink {} took {:?}", what, elapsed));
+        }
+        self.check(what, result)
+    }
+
+    /// Messages sent but not yet handled by the writer thread.
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    pub fn summary(&self, run: Duration) -> Map<String, Value> {
+        let mut args = Map::new();
+        args.insert("duration_us".to_owned(), Value::from(in_micros(run)));
+        args.insert("events".to_owned(), Value::from(self.events.load(Ordering::Relaxed)));
+        args.insert("dropped".to_owned(), Value::from(self.dropped.load(Ordering::Relaxed)));
+        args.insert(
+            "peak_queue_depth".to_owned(),
+            Value::from(self.peak_queued.load(Ordering::Relaxed)),
+        );
+        let shed = [
+            ("overflowed", &self.overflowed),
+            ("shed_events", &self.shed_events),
+            ("shed_args", &self.shed_args),
+            ("shed_spans", &self.shed_spans),
+            ("sampled_out", &self.sampled_out),
+        ];
+        for &(name, count) in &shed {
+            let count = count.load(Ordering::Relaxed);
+            if count > 0 {
+                args.insert(name.to_owned(), Value::from(count));
+            }
+        }
+        if let Some(ref by_callsite) = self.by_callsite {
+            let mut table = Map::new();
+            for drops in by_callsite.lock().unwrap().values() {
+                let mut counts = Map::new();
+                let reasons = [
+                    ("sampled", drops.sampled),
+                    ("filtered", drops.filtered),
+                    ("paused", drops.paused),
+                ];
+                for &(reason, count) in &reasons {
+                    if count > 0 {
+                        counts.insert(reason.to_owned(), Value::from(count));
+                    }
+                }
+                table.insert(drops.label.clone(), Value::Object(counts));
+            }
+            args.insert("suppressed_by_callsite".to_owned(), Value::Object(table));
+        }
+        args
+    }
+}
+
+fn message_size(msg: &Message) -> usize {
+    match *msg {
+        Message::Event(ref event, _) | Message::Captured(ref event, _) => event_size(event),
+        _ => mem::size_of::<Message>(),
+    }
+}
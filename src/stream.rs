@@ -0,0 +1,190 @@
+use std::io::{self, Write};
+#[cfg(feature = "serde_json")]
+use std::io::Read;
+
+use crate::json::{self, Map, Value};
+#[cfg(feature = "serde_json")]
+use crate::JsonSink;
+use crate::{TraceEvent, TraceSink};
+
+/// Streams events as frames, for a live viewer or a receiver on the other
+/// end of a socket.
+///
+/// Each frame is a big-endian `u32` length followed by that many bytes of
+/// JSON: either one trace event, or `{"systemTraceEvents": "..."}` carrying
+/// captured ftrace text. A zero-length frame ends the trace.
+/// `reassemble_stream` turns the frames back into a trace file.
+pub struct StreamSink<W> {
+    writer: W,
+}
+
+impl<W: Write> StreamSink<W> {
+    pub fn new(writer: W) -> Self {
+        StreamSink { writer }
+    }
+
+    fn frame(&mut self, payload: &[u8]) -> io::Result<()> {
+        if payload.len() > u32::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "frame too large"));
+        }
+        self.writer.write_all(&(payload.len() as u32).to_be_bytes())?;
+        self.writer.write_all(payload)
+    }
+}
+
+impl<W: Write + Send> TraceSink for StreamSink<W> {
+    fn write_event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        let json = json::to_vec(&event.to_json());
+        self.frame(&json)
+    }
+
+    fn system_trace(&mut self, text: &str) -> io::Result<()> {
+        let mut frame = Map::new();
+        frame.insert("systemTraceEvents".to_owned(), Value::from(text));
+        let json = json::to_vec(&Value::Object(frame));
+        self.frame(&json)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.frame(&[])?;
+        self.writer.flush()
+    }
+}
+
+/// Reads frames written by a `StreamSink` from `input` and writes them to
+/// `output` as a JSON Object Format trace, returning the number of events.
+///
+/// If the stream stops early, e.g. because the traced process died, the
+/// events received so far are still written out as a complete trace.
+#[cfg(feature = "serde_json")]
+pub fn reassemble_stream<R: Read, W: Write + Send>(mut input: R, output: W) -> io::Result<u64> {
+    let mut sink = JsonSink::from_writer(output).object_format();
+    sink.start()?;
+    let mut events = 0;
+    let mut payload = Vec::new();
+    while read_frame(&mut input, &mut payload)? {
+        let frame: Value = serde_json::from_slice(&payload)?;
+        match frame.get("systemTraceEvents").and_then(Value::as_str) {
+            Some(text) => sink.system_trace(text)?,
+            None => {
+                sink.write_json(&payload)?;
+                events += 1;
+            }
+        }
+    }
+    sink.finish()?;
+    Ok(events)
+}
+
+/// Frames longer than this are taken for a corrupt or hostile stream rather
+/// than allocated for.
+#[cfg(feature = "serde_json")]
+const MAX_FRAME: usize = 64 << 20;
+
+/// Reads the next frame's payload into `payload`, or returns false once the
+/// trace has ended.
+#[cfg(feature = "serde_json")]
+pub(crate) fn read_frame<R: Read>(input: &mut R, payload: &mut Vec<u8>) -> io::Result<bool> {
+    let mut len = [0; 4];
+    if !read_frame_part(input, &mut len)? {
+        return Ok(false);
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len == 0 {
+        return Ok(false);
+    }
+    if len > MAX_FRAME {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes is over the {} byte limit", len, MAX_FRAME),
+        ));
+    }
+    payload.resize(len, 0);
+    read_frame_part(input, payload)
+}
+
+/// Fills `buf`, or returns false if the stream ended first.
+#[cfg(feature = "serde_json")]
+fn read_frame_part<R: Read>(input: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    match input.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(all(test, feature = "serde_json"))]
+mod tests {
+    use super::*;
+
+    fn events() -> Vec<TraceEvent> {
+        (0..3)
+            .map(|ts| {
+                let mut event = TraceEvent::new("X", "poll".to_owned(), "runtime".to_owned(), ts * 10);
+                event.dur = Some(5);
+                event.args.insert("message".to_owned(), Value::from("naïve \"quoted\"\n"));
+                event
+            })
+            .collect()
+    }
+
+    fn stream(finish: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut sink = StreamSink::new(&mut out);
+        for event in events() {
+            sink.write_event(&event).unwrap();
+        }
+        sink.system_trace("# tracer: nop").unwrap();
+        if finish {
+            sink.finish().unwrap();
+        }
+        out
+    }
+
+    fn plain() -> Value {
+        let mut out = Vec::new();
+        let mut sink = JsonSink::from_writer(&mut out).object_format();
+        sink.start().unwrap();
+        for event in events() {
+            sink.write_event(&event).unwrap();
+        }
+        sink.system_trace("# tracer: nop").unwrap();
+        sink.finish().unwrap();
+        drop(sink);
+        serde_json::from_slice(&out).unwrap()
+    }
+
+    #[test]
+    fn reassembles_the_plain_trace() {
+        // A stream cut off before its end frame is still a complete trace.
+        for &finish in &[true, false] {
+            let mut out = Vec::new();
+            assert_eq!(reassemble_stream(&stream(finish)[..], &mut out).unwrap(), 3);
+            assert_eq!(serde_json::from_slice::<Value>(&out).unwrap(), plain());
+        }
+    }
+
+    #[test]
+    fn stops_at_a_partial_frame() {
+        let mut stream = Vec::new();
+        StreamSink::new(&mut stream).frame(b"{}").unwrap();
+        stream.extend_from_slice(&100u32.to_be_bytes());
+        stream.extend_from_slice(b"{\"na");
+        let mut payload = Vec::new();
+        let mut input = &stream[..];
+        assert!(read_frame(&mut input, &mut payload).unwrap());
+        assert_eq!(payload, b"{}");
+        assert!(!read_frame(&mut input, &mut payload).unwrap());
+    }
+
+    #[test]
+    fn rejects_oversized_frames() {
+        let stream = (MAX_FRAME as u32 + 1).to_be_bytes();
+        let err = reassemble_stream(&stream[..], Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+use crate::json::{Map, Value};
+use crate::TraceEvent;
+
+#[derive(Default)]
+struct Seen {
+    first: u64,
+    last: u64,
+    named: bool,
+    sorted: bool,
+}
+
+/// Every thread written so far and what's known about it, see
+/// `ChromeTraceBuilder::backfill_thread_metadata`. Runs on the writer
+/// thread, on events as written.
+#[derive(Default)]
+pub(crate) struct Threads {
+    seen: HashMap<(u32, u64), Seen>,
+}
+
+impl Threads {
+    pub fn observe(&mut self, event: &TraceEvent) {
+        let seen = self.seen.entry((event.pid, event.tid)).or_insert_with(|| Seen {
+            first: u64::MAX,
+            ..Default::default()
+        });
+        match (event.ph, &*event.name) {
+            ("M", "thread_name") => seen.named = true,
+            ("M", "thread_sort_index") => seen.sorted = true,
+            ("M", _) => {}
+            _ => {
+                seen.first = seen.first.min(event.ts);
+                seen.last = seen.last.max(event.ts + event.dur.unwrap_or(0));
+            }
+        }
+    }
+
+    /// The metadata missing for threads that never got it, and when each
+    /// thread was first and last seen, as `thread_lifetime` records.
+    pub fn backfill(self) -> Vec<TraceEvent> {
+        // Threads only ever seen in metadata have nothing to label.
+        let mut threads: Vec<_> = self
+            .seen
+            .into_iter()
+            .filter(|(_, seen)| seen.first != u64::MAX)
+            .collect();
+        threads.sort_by_key(|&(key, ref seen)| (seen.first, key));
+        let mut records = Vec::new();
+        for ((pid, tid), seen) in threads {
+            let mut add = |name: &str, key: &str, value: Value| {
+                let mut args = Map::new();
+                args.insert(key.to_owned(), value);
+                let mut record = TraceEvent::metadata(name, args);
+                record.pid = pid;
+                record.tid = tid;
+                records.push(record);
+            };
+            if !seen.named {
+                add("thread_name", "name", Value::from(format!("thread {}", tid)));
+            }
+            if !seen.sorted {
+                add("thread_sort_index", "sort_index", Value::from(0));
+            }
+            let mut lifetime = Map::new();
+            lifetime.insert("first_us".to_owned(), Value::from(seen.first));
+            lifetime.insert("last_us".to_owned(), Value::from(seen.last));
+            let mut record = TraceEvent::metadata("thread_lifetime", lifetime);
+            record.pid = pid;
+            record.tid = tid;
+            records.push(record);
+        }
+        records
+    }
+}
@@ -0,0 +1,1040 @@
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, Write};
+use std::mem;
+use std::net::Shutdown;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::perfetto::{Encoder, Message};
+use crate::{TraceEvent, TraceSink};
+
+#[cfg(target_os = "android")]
+const PRODUCER_SOCKET: &str = "/dev/socket/traced_producer";
+/// Where traced listens for producers when `/run/perfetto` doesn't exist.
+#[cfg(target_os = "linux")]
+const PRODUCER_SOCKET: &str = "/tmp/perfetto-producer";
+#[cfg(target_os = "linux")]
+const RUN_PRODUCER_SOCKET: &str = "/run/perfetto/traced-producer.sock";
+
+/// The data source traced's track event sessions enable.
+const DATA_SOURCE: &str = "track_event";
+
+/// How long connecting waits for traced to answer.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long a write to traced may block the writer thread.
+const SEND_TIMEOUT: Duration = Duration::from_secs(1);
+/// traced's own limit on the size of an IPC frame.
+const MAX_FRAME: usize = 128 * 1024;
+
+// Field numbers from Perfetto's ipc/wire_protocol.proto.
+const FRAME_REQUEST_ID: u32 = 2;
+const FRAME_BIND_SERVICE: u32 = 3;
+const FRAME_BIND_SERVICE_REPLY: u32 = 4;
+const FRAME_INVOKE_METHOD: u32 = 5;
+const FRAME_INVOKE_METHOD_REPLY: u32 = 6;
+
+const BIND_SERVICE_NAME: u32 = 1;
+const BIND_REPLY_SUCCESS: u32 = 1;
+const BIND_REPLY_SERVICE_ID: u32 = 2;
+const BIND_REPLY_METHODS: u32 = 3;
+const METHOD_ID: u32 = 1;
+const METHOD_NAME: u32 = 2;
+
+const INVOKE_SERVICE_ID: u32 = 1;
+const INVOKE_METHOD_ID: u32 = 2;
+const INVOKE_ARGS: u32 = 3;
+const INVOKE_DROP_REPLY: u32 = 4;
+const REPLY_SUCCESS: u32 = 1;
+const REPLY_PROTO: u32 = 3;
+
+// And from producer_port.proto and the configs it carries.
+const INIT_PRODUCER_NAME: u32 = 3;
+const INIT_SDK_VERSION: u32 = 8;
+const INIT_USE_SHMEM_EMULATION: u32 = 3;
+
+const REGISTER_DESCRIPTOR: u32 = 1;
+const DESCRIPTOR_NAME: u32 = 1;
+const DESCRIPTOR_WILL_NOTIFY_ON_STOP: u32 = 2;
+const DESCRIPTOR_HANDLES_INCREMENTAL_STATE_CLEAR: u32 = 4;
+
+const COMMAND_START: u32 = 1;
+const COMMAND_STOP: u32 = 2;
+const COMMAND_SETUP_TRACING: u32 = 3;
+const COMMAND_FLUSH: u32 = 5;
+const COMMAND_CLEAR_INCREMENTAL_STATE: u32 = 7;
+
+const SETUP_PAGE_SIZE_KB: u32 = 1;
+const START_INSTANCE_ID: u32 = 1;
+const START_CONFIG: u32 = 2;
+const CONFIG_NAME: u32 = 1;
+const CONFIG_TARGET_BUFFER: u32 = 2;
+const STOP_INSTANCE_ID: u32 = 1;
+const FLUSH_INSTANCE_IDS: u32 = 1;
+const FLUSH_REQUEST_ID: u32 = 2;
+const CLEAR_INSTANCE_IDS: u32 = 1;
+
+const COMMIT_CHUNKS_TO_MOVE: u32 = 1;
+const COMMIT_FLUSH_REQUEST_ID: u32 = 3;
+const MOVE_PAGE: u32 = 1;
+const MOVE_CHUNK: u32 = 2;
+const MOVE_TARGET_BUFFER: u32 = 3;
+const WRITER_ID: u32 = 1;
+const WRITER_TARGET_BUFFER: u32 = 2;
+const NOTIFY_INSTANCE_ID: u32 = 1;
+
+const PACKET_PREVIOUS_PACKET_DROPPED: u32 = 42;
+/// `BUILTIN_CLOCK_REALTIME`, which traced's clock snapshots map onto the
+/// boot clock the rest of a system trace is on.
+const CLOCK_REALTIME: u64 = 1;
+
+// The shared memory ABI (shared_memory_abi.h). Every page is used as a
+// single chunk.
+const PAGE_HEADER: usize = 8;
+const CHUNK_HEADER: usize = 8;
+/// Each packet in a chunk is prefixed with its size as a 4 byte varint.
+const PACKET_HEADER: usize = 4;
+const LAYOUT_ONE_CHUNK: u32 = 1 << 28;
+const CHUNK_BEING_WRITTEN: u32 = 1;
+const CHUNK_COMPLETE: u32 = 3;
+const FIRST_PACKET_CONTINUES: u16 = 1 << 0;
+const LAST_PACKET_CONTINUES: u16 = 1 << 1;
+const MAX_PACKETS: u16 = (1 << 10) - 1;
+const MAX_WRITER_ID: u16 = (1 << 10) - 1;
+/// How many times to look for a free page before dropping a packet.
+const ACQUIRE_ATTEMPTS: u32 = 10;
+
+/// Sends events to the Perfetto tracing service, `traced`, as a
+/// `track_event` data source, so they land in system traces recorded with
+/// Perfetto alongside kernel scheduling and everything else. Linux and
+/// Android only.
+///
+/// Events are sent while a tracing session has `track_event` enabled and
+/// dropped otherwise; the session's category filters aren't applied.
+/// Timestamps go on the realtime clock, which Perfetto lines up with the
+/// rest of the trace, so build the subscriber with `TimeBase::UnixEpoch`.
+/// Thread ids are this crate's rather than the kernel's, so spans appear
+/// on threads of their own under the process.
+pub struct TracedSink {
+    producer: Arc<Mutex<Producer>>,
+    socket: UnixStream,
+    serving: Option<JoinHandle<()>>,
+}
+
+impl TracedSink {
+    /// Connects to traced's producer socket: the one named by
+    /// `PERFETTO_PRODUCER_SOCK_NAME`, or else the platform's default.
+    /// `producer` is the name traced knows this process by.
+    pub fn connect(producer: &str) -> io::Result<Self> {
+        match env::var_os("PERFETTO_PRODUCER_SOCK_NAME") {
+            Some(socket) => TracedSink::connect_to(socket, producer),
+            None => TracedSink::connect_to(default_socket(), producer),
+        }
+    }
+
+    /// Connects to the producer socket at `socket`.
+    pub fn connect_to<P: AsRef<Path>>(socket: P, producer: &str) -> io::Result<Self> {
+        let socket = UnixStream::connect(socket)?;
+        socket.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        socket.set_write_timeout(Some(SEND_TIMEOUT))?;
+        let mut frames = Frames::new(socket.try_clone()?);
+        let mut port = Port::bind(socket.try_clone()?, &mut frames)?;
+
+        let mut init = Message::default();
+        init.string(INIT_PRODUCER_NAME, producer);
+        init.string(INIT_SDK_VERSION, concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")));
+        let request = port.invoke("InitializeConnection", init, false)?;
+        let reply = frames.reply(request)?;
+        if uint(&reply, INIT_USE_SHMEM_EMULATION) == Some(1) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "traced can't share memory over this socket",
+            ));
+        }
+
+        let commands = port.invoke("GetAsyncCommand", Message::default(), false)?;
+        let mut descriptor = Message::default();
+        descriptor.string(DESCRIPTOR_NAME, DATA_SOURCE);
+        descriptor.uint(DESCRIPTOR_WILL_NOTIFY_ON_STOP, 1);
+        descriptor.uint(DESCRIPTOR_HANDLES_INCREMENTAL_STATE_CLEAR, 1);
+        let mut register = Message::default();
+        register.message(REGISTER_DESCRIPTOR, descriptor);
+        port.invoke("RegisterDataSource", register, true)?;
+
+        // From here on the socket is read by the thread, which waits for
+        // commands for as long as the sink lives.
+        socket.set_read_timeout(None)?;
+        let producer = Arc::new(Mutex::new(Producer {
+            port,
+            smb: None,
+            instances: Vec::new(),
+            last_writer: 0,
+            names: Vec::new(),
+            failed: None,
+        }));
+        let serving = {
+            let producer = producer.clone();
+            thread::spawn(move || serve(frames, commands, &producer))
+        };
+        Ok(TracedSink {
+            producer,
+            socket,
+            serving: Some(serving),
+        })
+    }
+}
+
+#[cfg(target_os = "android")]
+fn default_socket() -> &'static str {
+    PRODUCER_SOCKET
+}
+
+#[cfg(target_os = "linux")]
+fn default_socket() -> &'static str {
+    if Path::new("/run/perfetto/").is_dir() {
+        RUN_PRODUCER_SOCKET
+    } else {
+        PRODUCER_SOCKET
+    }
+}
+
+impl TraceSink for TracedSink {
+    fn write_event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        self.producer.lock().unwrap().write_event(event)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let committed = self.producer.lock().unwrap().commit_all();
+        // Ends the connection, and with it the thread.
+        drop(self.socket.shutdown(Shutdown::Both));
+        if let Some(serving) = self.serving.take() {
+            drop(serving.join());
+        }
+        committed
+    }
+}
+
+impl Drop for TracedSink {
+    fn drop(&mut self) {
+        drop(self.socket.shutdown(Shutdown::Both));
+    }
+}
+
+/// Handles traced's commands until the connection ends.
+fn serve(mut frames: Frames, commands: u64, producer: &Mutex<Producer>) {
+    let error = loop {
+        let frame = match frames.next() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break io::Error::new(io::ErrorKind::ConnectionAborted, "traced closed the connection"),
+            Err(e) => break e,
+        };
+        if uint(&frame, FRAME_REQUEST_ID) != Some(commands) {
+            continue;
+        }
+        let reply = match bytes(&frame, FRAME_INVOKE_METHOD_REPLY) {
+            Some(reply) => reply,
+            None => continue,
+        };
+        if uint(reply, REPLY_SUCCESS) != Some(1) {
+            break io::Error::new(io::ErrorKind::ConnectionAborted, "traced stopped sending commands");
+        }
+        let command = bytes(reply, REPLY_PROTO).unwrap_or(&[]);
+        let mut producer = producer.lock().unwrap();
+        if let Err(e) = producer.handle(command, &mut frames.fd) {
+            break e;
+        }
+    };
+    let mut producer = producer.lock().unwrap();
+    producer.instances.clear();
+    producer.failed = Some(error);
+}
+
+/// The connection's state, shared by the writer thread and the thread
+/// handling commands.
+struct Producer {
+    port: Port,
+    smb: Option<Smb>,
+    /// The sessions the data source is running in.
+    instances: Vec<Instance>,
+    last_writer: u16,
+    /// Process and thread names, replayed into sessions that start later.
+    names: Vec<TraceEvent>,
+    /// Why the connection ended, reported by the next write.
+    failed: Option<io::Error>,
+}
+
+impl Producer {
+    fn write_event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        if let Some(e) = self.failed.take() {
+            return Err(e);
+        }
+        if event.ph == "M" && (event.name == "process_name" || event.name == "thread_name") {
+            self.names.push(event.clone());
+        }
+        let smb = match self.smb.as_mut() {
+            Some(smb) => smb,
+            None => return Ok(()),
+        };
+        let mut full = Vec::new();
+        for instance in &mut self.instances {
+            instance.encoder.encode(event);
+            for packet in instance.encoder.packets() {
+                instance.write(smb, packet, &mut full);
+            }
+        }
+        self.port.commit(&full, None)
+    }
+
+    fn handle(&mut self, command: &[u8], fd: &mut Option<OwnedFd>) -> io::Result<()> {
+        for (field, value) in Fields(command) {
+            let body = match value {
+                Field::Bytes(body) => body,
+                Field::Varint(_) => continue,
+            };
+            match field {
+                COMMAND_SETUP_TRACING => {
+                    let page_size = uint(body, SETUP_PAGE_SIZE_KB).unwrap_or(4) as usize * 1024;
+                    if let Some(fd) = fd.take() {
+                        self.smb = Some(Smb::map(fd, page_size)?);
+                    }
+                }
+                COMMAND_START => {
+                    let id = uint(body, START_INSTANCE_ID).unwrap_or(0);
+                    self.start(id, bytes(body, START_CONFIG).unwrap_or(&[]))?;
+                }
+                COMMAND_STOP => self.stop(uint(body, STOP_INSTANCE_ID).unwrap_or(0))?,
+                COMMAND_FLUSH => {
+                    let ids = uints(body, FLUSH_INSTANCE_IDS);
+                    self.flush(&ids, uint(body, FLUSH_REQUEST_ID).unwrap_or(0))?;
+                }
+                COMMAND_CLEAR_INCREMENTAL_STATE => {
+                    let ids = uints(body, CLEAR_INSTANCE_IDS);
+                    for instance in self.instances.iter_mut().filter(|i| ids.contains(&i.id)) {
+                        instance.encoder.clear_incremental_state();
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn start(&mut self, id: u64, config: &[u8]) -> io::Result<()> {
+        let name = bytes(config, CONFIG_NAME).unwrap_or(&[]);
+        if name != DATA_SOURCE.as_bytes() || self.instances.iter().any(|i| i.id == id) {
+            return Ok(());
+        }
+        self.last_writer = self.last_writer % MAX_WRITER_ID + 1;
+        let mut instance = Instance {
+            id,
+            target_buffer: uint(config, CONFIG_TARGET_BUFFER).unwrap_or(0),
+            writer: self.last_writer,
+            // traced stamps each writer's packets with a sequence of its own.
+            encoder: Encoder::stamped(None, Some(CLOCK_REALTIME)),
+            chunk: None,
+            chunk_id: 0,
+            dropped: false,
+        };
+        for name in &self.names {
+            instance.encoder.encode(name);
+        }
+        let mut writer = Message::default();
+        writer.uint(WRITER_ID, u64::from(instance.writer));
+        writer.uint(WRITER_TARGET_BUFFER, instance.target_buffer);
+        self.instances.push(instance);
+        self.port.invoke("RegisterTraceWriter", writer, true).map(drop)
+    }
+
+    fn stop(&mut self, id: u64) -> io::Result<()> {
+        if let Some(i) = self.instances.iter().position(|i| i.id == id) {
+            let mut instance = self.instances.remove(i);
+            let mut full = Vec::new();
+            if let Some(smb) = self.smb.as_ref() {
+                instance.complete(smb, &mut full);
+            }
+            self.port.commit(&full, None)?;
+            let mut writer = Message::default();
+            writer.uint(WRITER_ID, u64::from(instance.writer));
+            self.port.invoke("UnregisterTraceWriter", writer, true)?;
+        }
+        let mut stopped = Message::default();
+        stopped.uint(NOTIFY_INSTANCE_ID, id);
+        self.port.invoke("NotifyDataSourceStopped", stopped, true).map(drop)
+    }
+
+    /// Commits what the sessions in `ids` have written so far, telling
+    /// traced that flush `request` is done.
+    fn flush(&mut self, ids: &[u64], request: u64) -> io::Result<()> {
+        let mut full = Vec::new();
+        if let Some(smb) = self.smb.as_ref() {
+            for instance in self.instances.iter_mut().filter(|i| ids.contains(&i.id)) {
+                instance.complete(smb, &mut full);
+            }
+        }
+        self.port.commit(&full, Some(request))
+    }
+
+    /// Commits what every session has written so far.
+    fn commit_all(&mut self) -> io::Result<()> {
+        let mut full = Vec::new();
+        if let Some(smb) = self.smb.as_ref() {
+            for instance in &mut self.instances {
+                instance.complete(smb, &mut full);
+            }
+        }
+        self.port.commit(&full, None)
+    }
+}
+
+/// The data source running in one session, writing its own sequence of
+/// packets into chunks for that session's buffer.
+struct Instance {
+    id: u64,
+    target_buffer: u64,
+    writer: u16,
+    encoder: Encoder,
+    chunk: Option<Chunk>,
+    /// The id the next chunk gets.
+    chunk_id: u32,
+    /// Whether a packet has been dropped since the last one written.
+    dropped: bool,
+}
+
+/// The chunk being written, which is a whole page.
+struct Chunk {
+    page: usize,
+    used: usize,
+    packets: u16,
+    flags: u16,
+}
+
+impl Instance {
+    /// Writes `packet`, splitting it across chunks if it doesn't fit in
+    /// what's left of this one, and adds the chunks it fills to `full`
+    /// along with their target buffer. The packet is dropped if the shared
+    /// memory stays full.
+    fn write(&mut self, smb: &mut Smb, mut packet: Message, full: &mut Vec<(usize, u64)>) {
+        if self.dropped {
+            packet.uint(PACKET_PREVIOUS_PACKET_DROPPED, 1);
+        }
+        let payload = smb.chunk_size() - CHUNK_HEADER;
+        let mut rest = &packet.0[..];
+        let mut continues = false;
+        while !rest.is_empty() {
+            let room = match self.chunk {
+                Some(ref chunk) if chunk.packets < MAX_PACKETS => payload - chunk.used,
+                _ => 0,
+            };
+            if room <= PACKET_HEADER {
+                self.complete(smb, full);
+                match smb.acquire(self.writer, self.chunk_id) {
+                    Some(page) => {
+                        self.chunk_id = self.chunk_id.wrapping_add(1);
+                        // A packet's later fragments each start a chunk.
+                        let flags = if continues { FIRST_PACKET_CONTINUES } else { 0 };
+                        self.chunk = Some(Chunk {
+                            page,
+                            used: 0,
+                            packets: 0,
+                            flags,
+                        });
+                        continue;
+                    }
+                    None => {
+                        self.dropped = true;
+                        return;
+                    }
+                }
+            }
+            let chunk = match self.chunk.as_mut() {
+                Some(chunk) => chunk,
+                None => return,
+            };
+            let fragment = rest.len().min(room - PACKET_HEADER);
+            if fragment < rest.len() {
+                chunk.flags |= LAST_PACKET_CONTINUES;
+            }
+            // The count goes up as a packet starts, as the ABI expects.
+            chunk.packets += 1;
+            smb.set_packets(chunk.page, chunk.packets, chunk.flags);
+            smb.write(chunk.page, chunk.used, &packet_size(fragment));
+            smb.write(chunk.page, chunk.used + PACKET_HEADER, &rest[..fragment]);
+            chunk.used += PACKET_HEADER + fragment;
+            rest = &rest[fragment..];
+            continues = true;
+        }
+        self.dropped = false;
+    }
+
+    /// Hands the chunk being written, if any, over to traced.
+    fn complete(&mut self, smb: &Smb, full: &mut Vec<(usize, u64)>) {
+        if let Some(chunk) = self.chunk.take() {
+            smb.complete(chunk.page);
+            full.push((chunk.page, self.target_buffer));
+        }
+    }
+}
+
+/// `size` as the redundant 4 byte varint packets are prefixed with.
+fn packet_size(size: usize) -> [u8; PACKET_HEADER] {
+    [
+        (size & 0x7f) as u8 | 0x80,
+        (size >> 7 & 0x7f) as u8 | 0x80,
+        (size >> 14 & 0x7f) as u8 | 0x80,
+        (size >> 21 & 0x7f) as u8,
+    ]
+}
+
+/// The shared memory buffer traced hands over when tracing is set up.
+struct Smb {
+    base: *mut u8,
+    size: usize,
+    page_size: usize,
+    /// Where to start looking for a free page.
+    next_page: usize,
+}
+
+// The mapping is only touched with the producer locked.
+unsafe impl Send for Smb {}
+
+impl Smb {
+    fn map(fd: OwnedFd, page_size: usize) -> io::Result<Smb> {
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+        if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let size = stat.st_size as usize;
+        let valid = (4096..=65536).contains(&page_size) && page_size.is_multiple_of(4096);
+        if !valid || size == 0 || !size.is_multiple_of(page_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "traced's shared memory has an unexpected layout",
+            ));
+        }
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Smb {
+            base: base.cast(),
+            size,
+            page_size,
+            next_page: 0,
+        })
+    }
+
+    fn chunk_size(&self) -> usize {
+        (self.page_size - PAGE_HEADER) & !3
+    }
+
+    fn page(&self, page: usize) -> *mut u8 {
+        debug_assert!(page < self.size / self.page_size);
+        unsafe { self.base.add(page * self.page_size) }
+    }
+
+    fn layout(&self, page: usize) -> &AtomicU32 {
+        unsafe { &*(self.page(page) as *const AtomicU32) }
+    }
+
+    fn chunk(&self, page: usize) -> *mut u8 {
+        unsafe { self.page(page).add(PAGE_HEADER) }
+    }
+
+    fn packets(&self, page: usize) -> &AtomicU16 {
+        unsafe { &*(self.chunk(page).add(6) as *const AtomicU16) }
+    }
+
+    /// Claims a free page as a single chunk for `writer` to write.
+    fn acquire(&mut self, writer: u16, chunk_id: u32) -> Option<usize> {
+        let pages = self.size / self.page_size;
+        for attempt in 0..ACQUIRE_ATTEMPTS {
+            for i in 0..pages {
+                let page = (self.next_page + i) % pages;
+                let layout = self.layout(page);
+                let claimed = layout.compare_exchange(
+                    0,
+                    LAYOUT_ONE_CHUNK | CHUNK_BEING_WRITTEN,
+                    Ordering::AcqRel,
+                    Ordering::Relaxed,
+                );
+                if claimed.is_ok() {
+                    let header = self.chunk(page);
+                    unsafe {
+                        (*(header as *const AtomicU32)).store(chunk_id, Ordering::Relaxed);
+                        (*(header.add(4) as *const AtomicU16)).store(writer, Ordering::Relaxed);
+                    }
+                    self.packets(page).store(0, Ordering::Release);
+                    self.next_page = (page + 1) % pages;
+                    return Some(page);
+                }
+            }
+            // traced frees pages as it copies out the ones committed.
+            if attempt + 1 < ACQUIRE_ATTEMPTS {
+                thread::sleep(Duration::from_micros(100));
+            }
+        }
+        None
+    }
+
+    fn set_packets(&self, page: usize, count: u16, flags: u16) {
+        self.packets(page).store(count | flags << 10, Ordering::Release);
+    }
+
+    fn write(&self, page: usize, offset: usize, bytes: &[u8]) {
+        assert!(CHUNK_HEADER + offset + bytes.len() <= self.chunk_size());
+        unsafe {
+            let at = self.chunk(page).add(CHUNK_HEADER + offset);
+            ptr::copy_nonoverlapping(bytes.as_ptr(), at, bytes.len());
+        }
+    }
+
+    fn complete(&self, page: usize) {
+        self.layout(page).store(LAYOUT_ONE_CHUNK | CHUNK_COMPLETE, Ordering::Release);
+    }
+}
+
+impl Drop for Smb {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.base.cast(), self.size);
+        }
+    }
+}
+
+/// traced's `ProducerPort` service, bound on the connection.
+struct Port {
+    socket: UnixStream,
+    service: u64,
+    methods: HashMap<String, u64>,
+    last_request: u64,
+}
+
+impl Port {
+    fn bind(socket: UnixStream, frames: &mut Frames) -> io::Result<Port> {
+        let mut port = Port {
+            socket,
+            service: 0,
+            methods: HashMap::new(),
+            last_request: 0,
+        };
+        let mut bind = Message::default();
+        bind.string(BIND_SERVICE_NAME, "ProducerPort");
+        let request = port.send(FRAME_BIND_SERVICE, bind)?;
+        let frame = frames.frame(request)?;
+        let reply = bytes(&frame, FRAME_BIND_SERVICE_REPLY).unwrap_or(&[]);
+        if uint(reply, BIND_REPLY_SUCCESS) != Some(1) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "traced has no ProducerPort"));
+        }
+        port.service = uint(reply, BIND_REPLY_SERVICE_ID).unwrap_or(0);
+        for (field, value) in Fields(reply) {
+            if let (BIND_REPLY_METHODS, Field::Bytes(method)) = (field, value) {
+                if let (Some(id), Some(name)) = (uint(method, METHOD_ID), bytes(method, METHOD_NAME)) {
+                    port.methods.insert(String::from_utf8_lossy(name).into_owned(), id);
+                }
+            }
+        }
+        Ok(port)
+    }
+
+    /// Sends a frame, returning its request id.
+    fn send(&mut self, field: u32, body: Message) -> io::Result<u64> {
+        self.last_request += 1;
+        let mut frame = Message::default();
+        frame.uint(FRAME_REQUEST_ID, self.last_request);
+        frame.message(field, body);
+        let mut bytes = (frame.0.len() as u32).to_le_bytes().to_vec();
+        bytes.extend_from_slice(&frame.0);
+        self.socket.write_all(&bytes)?;
+        Ok(self.last_request)
+    }
+
+    fn invoke(&mut self, method: &str, args: Message, drop_reply: bool) -> io::Result<u64> {
+        let id = match self.methods.get(method) {
+            Some(&id) => id,
+            None => {
+                let message = format!("traced's ProducerPort has no {}", method);
+                return Err(io::Error::new(io::ErrorKind::Unsupported, message));
+            }
+        };
+        let mut invoke = Message::default();
+        invoke.uint(INVOKE_SERVICE_ID, self.service);
+        invoke.uint(INVOKE_METHOD_ID, id);
+        invoke.bytes(INVOKE_ARGS, &args.0);
+        if drop_reply {
+            invoke.uint(INVOKE_DROP_REPLY, 1);
+        }
+        self.send(FRAME_INVOKE_METHOD, invoke)
+    }
+
+    /// Asks traced to move `chunks` (page and target buffer) out of shared
+    /// memory, and to mark flush `request` done once it has.
+    fn commit(&mut self, chunks: &[(usize, u64)], request: Option<u64>) -> io::Result<()> {
+        if chunks.is_empty() && request.is_none() {
+            return Ok(());
+        }
+        let mut commit = Message::default();
+        for &(page, target_buffer) in chunks {
+            let mut chunk = Message::default();
+            chunk.uint(MOVE_PAGE, page as u64);
+            chunk.uint(MOVE_CHUNK, 0);
+            chunk.uint(MOVE_TARGET_BUFFER, target_buffer);
+            commit.message(COMMIT_CHUNKS_TO_MOVE, chunk);
+        }
+        if let Some(request) = request {
+            commit.uint(COMMIT_FLUSH_REQUEST_ID, request);
+        }
+        self.invoke("CommitData", commit, true).map(drop)
+    }
+}
+
+/// Reads frames off the connection, keeping the file descriptor traced
+/// sends along with the shared memory.
+struct Frames {
+    socket: UnixStream,
+    buf: Vec<u8>,
+    read: Box<[u8]>,
+    fd: Option<OwnedFd>,
+}
+
+impl Frames {
+    fn new(socket: UnixStream) -> Self {
+        Frames {
+            socket,
+            buf: Vec::new(),
+            read: vec![0; 16 * 1024].into_boxed_slice(),
+            fd: None,
+        }
+    }
+
+    /// The next frame, or `None` once traced closes the connection.
+    fn next(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            if self.buf.len() >= 4 {
+                let len = u32::from_le_bytes([self.buf[0], self.buf[1], self.buf[2], self.buf[3]]) as usize;
+                if len > MAX_FRAME {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "oversized frame from traced"));
+                }
+                if self.buf.len() >= 4 + len {
+                    let frame = self.buf[4..4 + len].to_vec();
+                    self.buf.drain(..4 + len);
+                    return Ok(Some(frame));
+                }
+            }
+            match receive(&self.socket, &mut self.read, &mut self.fd)? {
+                0 => return Ok(None),
+                n => self.buf.extend_from_slice(&self.read[..n]),
+            }
+        }
+    }
+
+    /// The frame answering `request`, skipping any others.
+    fn frame(&mut self, request: u64) -> io::Result<Vec<u8>> {
+        loop {
+            match self.next()? {
+                Some(frame) if uint(&frame, FRAME_REQUEST_ID) == Some(request) => return Ok(frame),
+                Some(_) => continue,
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "traced closed the connection",
+                    ))
+                }
+            }
+        }
+    }
+
+    /// What method call `request` returned, if it succeeded.
+    fn reply(&mut self, request: u64) -> io::Result<Vec<u8>> {
+        let frame = self.frame(request)?;
+        match bytes(&frame, FRAME_INVOKE_METHOD_REPLY) {
+            Some(reply) if uint(reply, REPLY_SUCCESS) == Some(1) => Ok(bytes(reply, REPLY_PROTO).unwrap_or(&[]).to_vec()),
+            _ => Err(io::Error::new(io::ErrorKind::ConnectionRefused, "traced refused the producer")),
+        }
+    }
+}
+
+/// Reads into `buf`, keeping any file descriptor that came with the data
+/// in `fd`.
+fn receive(socket: &UnixStream, buf: &mut [u8], fd: &mut Option<OwnedFd>) -> io::Result<usize> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    // Room for a few descriptors, aligned for cmsghdr.
+    let mut control = [0u64; 8];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+    let n = loop {
+        let n = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+        if n >= 0 {
+            break n as usize;
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    };
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                let len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                for i in 0..len / mem::size_of::<RawFd>() {
+                    // Only the last one is kept; the others are closed.
+                    *fd = Some(OwnedFd::from_raw_fd(ptr::read_unaligned(data.add(i))));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    Ok(n)
+}
+
+/// A field read back from a protobuf message.
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+/// The fields of a protobuf message, up to the first malformed one.
+/// Fixed-width fields are skipped, as none of the ones read here are.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = (u32, Field<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let key = varint(&mut self.0)?;
+            let field = (key >> 3) as u32;
+            let skip = match key & 7 {
+                0 => match varint(&mut self.0) {
+                    Some(value) => return Some((field, Field::Varint(value))),
+                    None => return None,
+                },
+                1 => 8,
+                2 => {
+                    let len = varint(&mut self.0)? as usize;
+                    if len > self.0.len() {
+                        self.0 = &[];
+                        return None;
+                    }
+                    let (bytes, rest) = self.0.split_at(len);
+                    self.0 = rest;
+                    return Some((field, Field::Bytes(bytes)));
+                }
+                5 => 4,
+                _ => self.0.len() + 1,
+            };
+            if skip > self.0.len() {
+                self.0 = &[];
+                return None;
+            }
+            self.0 = &self.0[skip..];
+        }
+    }
+}
+
+fn varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for (i, &byte) in bytes.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *bytes = &bytes[i + 1..];
+            return Some(value);
+        }
+    }
+    *bytes = &[];
+    None
+}
+
+/// The last value of varint `field` in `message`.
+fn uint(message: &[u8], field: u32) -> Option<u64> {
+    Fields(message)
+        .filter_map(|(f, value)| match value {
+            Field::Varint(value) if f == field => Some(value),
+            _ => None,
+        })
+        .last()
+}
+
+fn bytes(message: &[u8], field: u32) -> Option<&[u8]> {
+    Fields(message)
+        .filter_map(|(f, value)| match value {
+            Field::Bytes(bytes) if f == field => Some(bytes),
+            _ => None,
+        })
+        .last()
+}
+
+/// Every value of repeated varint `field`, packed or not.
+fn uints(message: &[u8], field: u32) -> Vec<u64> {
+    let mut values = Vec::new();
+    for (_, value) in Fields(message).filter(|&(f, _)| f == field) {
+        match value {
+            Field::Varint(value) => values.push(value),
+            Field::Bytes(mut packed) => {
+                while let Some(value) = varint(&mut packed) {
+                    values.push(value);
+                }
+            }
+        }
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Shared memory of `pages` 4 KB pages, as traced would hand over.
+    fn smb(pages: usize) -> Smb {
+        let fd = unsafe { libc::memfd_create(b"smb\0".as_ptr().cast(), libc::MFD_CLOEXEC) };
+        assert!(fd >= 0);
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        assert_eq!(unsafe { libc::ftruncate(fd.as_raw_fd(), (pages * 4096) as libc::off_t) }, 0);
+        Smb::map(fd, 4096).unwrap()
+    }
+
+    fn instance() -> Instance {
+        Instance {
+            id: 1,
+            target_buffer: 3,
+            writer: 5,
+            encoder: Encoder::stamped(None, Some(CLOCK_REALTIME)),
+            chunk: None,
+            chunk_id: 0,
+            dropped: false,
+        }
+    }
+
+    fn packet(len: usize) -> Message {
+        Message((0..len).map(|i| i as u8).collect())
+    }
+
+    /// A chunk as traced reads it: its page's state, chunk id, writer,
+    /// packet count and flags, and the packets.
+    fn read(smb: &Smb, page: usize) -> (u32, u32, u16, u16, u16, Vec<Vec<u8>>) {
+        let chunk = unsafe { std::slice::from_raw_parts(smb.chunk(page), smb.chunk_size()) };
+        let layout = smb.layout(page).load(Ordering::Acquire);
+        let id = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        let writer = u16::from_le_bytes([chunk[4], chunk[5]]);
+        let packets = u16::from_le_bytes([chunk[6], chunk[7]]);
+        let mut rest = &chunk[CHUNK_HEADER..];
+        let mut fragments = Vec::new();
+        for _ in 0..packets & MAX_PACKETS {
+            let len = varint(&mut rest).unwrap() as usize;
+            fragments.push(rest[..len].to_vec());
+            rest = &rest[len..];
+        }
+        (layout, id, writer, packets & MAX_PACKETS, packets >> 10, fragments)
+    }
+
+    #[test]
+    fn packet_sizes() {
+        assert_eq!(packet_size(0), [0x80, 0x80, 0x80, 0x00]);
+        assert_eq!(packet_size(300), [0xac, 0x82, 0x80, 0x00]);
+        assert_eq!(varint(&mut &packet_size(70000)[..]), Some(70000));
+    }
+
+    #[test]
+    fn fields() {
+        let mut inner = Message::default();
+        inner.uint(1, 7);
+        let mut message = Message::default();
+        message.uint(1, 300);
+        message.string(2, "name");
+        message.message(3, inner);
+        message.uint(4, 1);
+        message.uint(4, 2);
+        // And a packed repeat of the same field.
+        message.bytes(4, &[3, 0x80, 0x01]);
+        message.uint(1, 301);
+
+        assert_eq!(uint(&message.0, 1), Some(301));
+        assert_eq!(bytes(&message.0, 2), Some(&b"name"[..]));
+        assert_eq!(uint(bytes(&message.0, 3).unwrap(), 1), Some(7));
+        assert_eq!(uints(&message.0, 4), [1, 2, 3, 128]);
+        assert_eq!(uint(&message.0, 5), None);
+        // Reading stops at a field running past the end.
+        assert_eq!(Fields(&message.0[..8]).count(), 1);
+    }
+
+    #[test]
+    fn packets_share_a_chunk() {
+        let mut smb = smb(2);
+        let mut instance = instance();
+        let mut full = Vec::new();
+        instance.write(&mut smb, packet(10), &mut full);
+        instance.write(&mut smb, packet(20), &mut full);
+        assert!(full.is_empty());
+        let (layout, id, writer, packets, flags, fragments) = read(&smb, 0);
+        assert_eq!(layout, LAYOUT_ONE_CHUNK | CHUNK_BEING_WRITTEN);
+        assert_eq!((id, writer, packets, flags), (0, 5, 2, 0));
+        assert_eq!(fragments, [packet(10).0, packet(20).0]);
+
+        instance.complete(&smb, &mut full);
+        assert_eq!(full, [(0, 3)]);
+        assert_eq!(read(&smb, 0).0, LAYOUT_ONE_CHUNK | CHUNK_COMPLETE);
+        assert_eq!(read(&smb, 1).0, 0);
+    }
+
+    #[test]
+    fn packets_continue_across_chunks() {
+        let mut smb = smb(3);
+        let mut instance = instance();
+        let mut full = Vec::new();
+        let payload = smb.chunk_size() - CHUNK_HEADER - PACKET_HEADER;
+        instance.write(&mut smb, packet(payload + 100), &mut full);
+        assert_eq!(full, [(0, 3)]);
+
+        let (_, id, _, packets, flags, fragments) = read(&smb, 0);
+        assert_eq!((id, packets, flags), (0, 1, LAST_PACKET_CONTINUES));
+        assert_eq!(fragments, [&packet(payload + 100).0[..payload]]);
+        let (layout, id, _, packets, flags, fragments) = read(&smb, 1);
+        assert_eq!(layout, LAYOUT_ONE_CHUNK | CHUNK_BEING_WRITTEN);
+        assert_eq!((id, packets, flags), (1, 1, FIRST_PACKET_CONTINUES));
+        assert_eq!(fragments, [&packet(payload + 100).0[payload..]]);
+    }
+
+    #[test]
+    fn packets_are_dropped_when_memory_is_full() {
+        let mut smb = smb(1);
+        let mut instance = instance();
+        let mut full = Vec::new();
+        let payload = smb.chunk_size() - CHUNK_HEADER - PACKET_HEADER;
+        instance.write(&mut smb, packet(payload), &mut full);
+        instance.write(&mut smb, packet(10), &mut full);
+        assert!(instance.dropped);
+        assert_eq!(full, [(0, 3)]);
+
+        // Once traced has read the page, the next packet says what was lost.
+        smb.layout(0).store(0, Ordering::Release);
+        let mut timestamped = Message::default();
+        timestamped.uint(8, 1);
+        instance.write(&mut smb, timestamped, &mut full);
+        assert!(!instance.dropped);
+        let (_, id, _, _, _, fragments) = read(&smb, 0);
+        assert_eq!(id, 1);
+        assert_eq!(uint(&fragments[0], PACKET_PREVIOUS_PACKET_DROPPED), Some(1));
+    }
+}
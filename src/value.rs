@@ -0,0 +1,470 @@
+//! The argument types used when the crate is built without serde_json.
+//!
+//! These mirror the parts of `serde_json::{Map, Number, Value}` the crate
+//! relies on, so the rest of it reads the same either way. Objects keep
+//! their keys sorted, as serde_json's do without `preserve_order`.
+
+use std::borrow::Cow;
+use std::collections::btree_map::{self, BTreeMap};
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::Index;
+
+/// Any JSON value.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Value {
+    #[default]
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(String),
+    Array(Vec<Value>),
+    Object(Map<String, Value>),
+}
+
+static NULL: Value = Value::Null;
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.as_object().and_then(|map| map.get(key))
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        self.as_object_mut().and_then(|map| map.get_mut(key))
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(*self, Value::Null)
+    }
+
+    pub fn is_number(&self) -> bool {
+        matches!(*self, Value::Number(_))
+    }
+
+    pub fn is_string(&self) -> bool {
+        matches!(*self, Value::String(_))
+    }
+
+    pub fn is_object(&self) -> bool {
+        matches!(*self, Value::Object(_))
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Value::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Value::Number(ref n) => n.as_u64(),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Value::Number(ref n) => n.as_i64(),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Number(ref n) => n.as_f64(),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match *self {
+            Value::Array(ref values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&Map<String, Value>> {
+        match *self {
+            Value::Object(ref map) => Some(map),
+            _ => None,
+        }
+    }
+
+    pub fn as_object_mut(&mut self) -> Option<&mut Map<String, Value>> {
+        match *self {
+            Value::Object(ref mut map) => Some(map),
+            _ => None,
+        }
+    }
+
+    pub fn take(&mut self) -> Value {
+        std::mem::take(self)
+    }
+}
+
+impl Index<&str> for Value {
+    type Output = Value;
+
+    fn index(&self, key: &str) -> &Value {
+        self.get(key).unwrap_or(&NULL)
+    }
+}
+
+/// Writes the value as compact JSON.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = Vec::new();
+        crate::json::write_value(&mut out, self, false);
+        f.write_str(&String::from_utf8_lossy(&out))
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_owned())
+    }
+}
+
+impl From<Cow<'_, str>> for Value {
+    fn from(s: Cow<str>) -> Self {
+        Value::String(s.into_owned())
+    }
+}
+
+impl From<Number> for Value {
+    fn from(n: Number) -> Self {
+        Value::Number(n)
+    }
+}
+
+/// Like serde_json, NaN and the infinities become `null`.
+impl From<f64> for Value {
+    fn from(f: f64) -> Self {
+        Number::from_f64(f).map_or(Value::Null, Value::Number)
+    }
+}
+
+impl From<f32> for Value {
+    fn from(f: f32) -> Self {
+        Value::from(f as f64)
+    }
+}
+
+impl From<Map<String, Value>> for Value {
+    fn from(map: Map<String, Value>) -> Self {
+        Value::Object(map)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(values: Vec<T>) -> Self {
+        Value::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+impl<T: Into<Value>> FromIterator<T> for Value {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Value::Array(iter.into_iter().map(Into::into).collect())
+    }
+}
+
+/// A JSON number: an integer that fits `u64` or `i64`, or a finite float.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Number(N);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum N {
+    PosInt(u64),
+    // Always negative.
+    NegInt(i64),
+    Float(f64),
+}
+
+impl Number {
+    pub fn from_f64(f: f64) -> Option<Number> {
+        if f.is_finite() {
+            Some(Number(N::Float(f)))
+        } else {
+            None
+        }
+    }
+
+    pub fn is_u64(&self) -> bool {
+        matches!(self.0, N::PosInt(_))
+    }
+
+    pub fn is_i64(&self) -> bool {
+        match self.0 {
+            N::PosInt(n) => n <= i64::MAX as u64,
+            N::NegInt(_) => true,
+            N::Float(_) => false,
+        }
+    }
+
+    pub fn is_f64(&self) -> bool {
+        matches!(self.0, N::Float(_))
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.0 {
+            N::PosInt(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self.0 {
+            N::PosInt(n) if n <= i64::MAX as u64 => Some(n as i64),
+            N::NegInt(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self.0 {
+            N::PosInt(n) => Some(n as f64),
+            N::NegInt(n) => Some(n as f64),
+            N::Float(f) => Some(f),
+        }
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            N::PosInt(n) => write!(f, "{}", n),
+            N::NegInt(n) => write!(f, "{}", n),
+            N::Float(n) => write_float(f, n),
+        }
+    }
+}
+
+/// Writes `n` the way serde_json does: the shortest digits that read back
+/// as `n`, in plain notation from 1e-5 up to 1e16 and with an exponent
+/// outside that.
+fn write_float(f: &mut fmt::Formatter, n: f64) -> fmt::Result {
+    let sci = format!("{:e}", n.abs());
+    let (mantissa, exp) = sci.split_once('e').unwrap();
+    let digits = mantissa.replace('.', "");
+    // Where the decimal point goes, counted from the left of `digits`.
+    let point = exp.parse::<i32>().unwrap() + 1;
+    if n.is_sign_negative() {
+        f.write_str("-")?;
+    }
+    let len = digits.len() as i32;
+    if len <= point && point <= 16 {
+        write!(f, "{}{}.0", digits, "0".repeat((point - len) as usize))
+    } else if 0 < point && point <= 16 {
+        let (int, frac) = digits.split_at(point as usize);
+        write!(f, "{}.{}", int, frac)
+    } else if -5 < point && point <= 0 {
+        write!(f, "0.{}{}", "0".repeat(-point as usize), digits)
+    } else {
+        let (first, rest) = digits.split_at(1);
+        let sign = if point > 0 { "+" } else { "" };
+        match rest {
+            "" => write!(f, "{}e{}{}", first, sign, point - 1),
+            _ => write!(f, "{}.{}e{}{}", first, rest, sign, point - 1),
+        }
+    }
+}
+
+macro_rules! from_unsigned {
+    ($($ty:ty),*) => {$(
+        impl From<$ty> for Number {
+            fn from(n: $ty) -> Self {
+                Number(N::PosInt(n as u64))
+            }
+        }
+
+        impl From<$ty> for Value {
+            fn from(n: $ty) -> Self {
+                Value::Number(Number::from(n))
+            }
+        }
+    )*};
+}
+
+macro_rules! from_signed {
+    ($($ty:ty),*) => {$(
+        impl From<$ty> for Number {
+            fn from(n: $ty) -> Self {
+                if n < 0 {
+                    Number(N::NegInt(n as i64))
+                } else {
+                    Number(N::PosInt(n as u64))
+                }
+            }
+        }
+
+        impl From<$ty> for Value {
+            fn from(n: $ty) -> Self {
+                Value::Number(Number::from(n))
+            }
+        }
+    )*};
+}
+
+from_unsigned!(u8, u16, u32, u64, usize);
+from_signed!(i8, i16, i32, i64, isize);
+
+/// A JSON object's members, sorted by key.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Map<K, V> {
+    map: BTreeMap<K, V>,
+}
+
+pub type Entry<'a> = btree_map::Entry<'a, String, Value>;
+
+impl Map<String, Value> {
+    pub fn new() -> Self {
+        Map { map: BTreeMap::new() }
+    }
+
+    pub fn with_capacity(_capacity: usize) -> Self {
+        Map::new()
+    }
+
+    pub fn insert(&mut self, key: String, value: Value) -> Option<Value> {
+        self.map.insert(key, value)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        self.map.remove(key)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.map.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        self.map.get_mut(key)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.map.contains_key(key)
+    }
+
+    pub fn entry<S: Into<String>>(&mut self, key: S) -> Entry<'_> {
+        self.map.entry(key.into())
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    pub fn append(&mut self, other: &mut Self) {
+        self.map.append(&mut other.map)
+    }
+
+    pub fn retain<F: FnMut(&String, &mut Value) -> bool>(&mut self, f: F) {
+        self.map.retain(f)
+    }
+
+    pub fn iter(&self) -> btree_map::Iter<'_, String, Value> {
+        self.map.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> btree_map::IterMut<'_, String, Value> {
+        self.map.iter_mut()
+    }
+
+    pub fn keys(&self) -> btree_map::Keys<'_, String, Value> {
+        self.map.keys()
+    }
+
+    pub fn values(&self) -> btree_map::Values<'_, String, Value> {
+        self.map.values()
+    }
+
+    pub fn values_mut(&mut self) -> btree_map::ValuesMut<'_, String, Value> {
+        self.map.values_mut()
+    }
+}
+
+impl Default for Map<String, Value> {
+    fn default() -> Self {
+        Map::new()
+    }
+}
+
+impl Index<&str> for Map<String, Value> {
+    type Output = Value;
+
+    fn index(&self, key: &str) -> &Value {
+        &self.map[key]
+    }
+}
+
+impl FromIterator<(String, Value)> for Map<String, Value> {
+    fn from_iter<I: IntoIterator<Item = (String, Value)>>(iter: I) -> Self {
+        Map { map: iter.into_iter().collect() }
+    }
+}
+
+impl Extend<(String, Value)> for Map<String, Value> {
+    fn extend<I: IntoIterator<Item = (String, Value)>>(&mut self, iter: I) {
+        self.map.extend(iter)
+    }
+}
+
+impl IntoIterator for Map<String, Value> {
+    type Item = (String, Value);
+    type IntoIter = btree_map::IntoIter<String, Value>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Map<String, Value> {
+    type Item = (&'a String, &'a Value);
+    type IntoIter = btree_map::Iter<'a, String, Value>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut Map<String, Value> {
+    type Item = (&'a String, &'a mut Value);
+    type IntoIter = btree_map::IterMut<'a, String, Value>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.iter_mut()
+    }
+}
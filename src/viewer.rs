@@ -0,0 +1,48 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Called with the links once the trace is finished.
+pub(crate) type Report = Box<dyn FnOnce(&ViewerLinks) + Send>;
+
+/// Where a finished trace was written and how to open it, see
+/// `ChromeTraceBuilder::viewer_links`.
+#[derive(Clone, Debug)]
+pub struct ViewerLinks {
+    /// The trace file, made absolute if it can be.
+    pub path: PathBuf,
+    /// Opens the file in the Perfetto UI, using Perfetto's
+    /// `open_trace_in_ui` script to serve it to ui.perfetto.dev.
+    pub perfetto: String,
+    /// The page to load the file into Chrome's trace viewer from. Browsers
+    /// won't open a local file from a link, so it has to be loaded by hand.
+    pub chrome: String,
+}
+
+impl ViewerLinks {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        ViewerLinks {
+            perfetto: format!("open_trace_in_ui -i {}", shell_quote(&path.to_string_lossy())),
+            chrome: "chrome://tracing".to_owned(),
+            path,
+        }
+    }
+}
+
+impl fmt::Display for ViewerLinks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "trace written to {}", self.path.display())?;
+        writeln!(f, "  perfetto: {}", self.perfetto)?;
+        write!(f, "  chrome:   open {} and load the file", self.chrome)
+    }
+}
+
+fn shell_quote(s: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "/._-+:,@".contains(c);
+    if !s.is_empty() && s.chars().all(plain) {
+        s.to_owned()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
@@ -0,0 +1,107 @@
+use js_sys::Array;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{Blob, BlobPropertyBag, HtmlAnchorElement, Url};
+
+use crate::{JsonSink, TraceEvent, TraceSink};
+
+/// The trace written by the `BrowserSink`, for the functions exported to
+/// JavaScript to hand out.
+static TRACE: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+static FINISHED: AtomicBool = AtomicBool::new(false);
+
+struct Shared;
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        TRACE.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Keeps the trace in the page's memory, for Rust running in a browser.
+/// From JavaScript, `chromeTraceDownload("trace.json")` saves it as a file
+/// for chrome://tracing, `chromeTraceLog()` prints it to the console and
+/// `chromeTraceJson()` returns it. A trace that hasn't finished yet is
+/// closed off in the copy handed out. There's one trace per page; a second
+/// `BrowserSink` starts it over.
+///
+/// There's no writer thread on wasm32: events reach the sink as they're
+/// recorded, so the copy is up to date whenever JavaScript asks for it.
+pub struct BrowserSink {
+    inner: JsonSink<Shared>,
+}
+
+impl BrowserSink {
+    pub fn new() -> Self {
+        TRACE.lock().unwrap().clear();
+        FINISHED.store(false, Ordering::Relaxed);
+        BrowserSink {
+            inner: JsonSink::from_writer(Shared),
+        }
+    }
+}
+
+impl Default for BrowserSink {
+    fn default() -> Self {
+        BrowserSink::new()
+    }
+}
+
+impl TraceSink for BrowserSink {
+    fn start(&mut self) -> io::Result<()> {
+        self.inner.start()
+    }
+
+    fn write_event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        self.inner.write_event(event)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.inner.finish()?;
+        FINISHED.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// The trace so far as a JSON array.
+#[wasm_bindgen(js_name = chromeTraceJson)]
+pub fn trace_json() -> String {
+    let mut json = String::from_utf8_lossy(&TRACE.lock().unwrap()).into_owned();
+    if !FINISHED.load(Ordering::Relaxed) {
+        json.push_str("\n]\n");
+    }
+    json
+}
+
+/// Prints the trace so far to the browser console.
+#[wasm_bindgen(js_name = chromeTraceLog)]
+pub fn log_trace() {
+    web_sys::console::log_1(&JsValue::from_str(&trace_json()));
+}
+
+/// Saves the trace so far as `filename`, through the browser's download
+/// prompt.
+#[wasm_bindgen(js_name = chromeTraceDownload)]
+pub fn download_trace(filename: &str) -> Result<(), JsValue> {
+    let parts = Array::of1(&JsValue::from_str(&trace_json()));
+    let options = BlobPropertyBag::new();
+    options.set_type("application/json");
+    let blob = Blob::new_with_str_sequence_and_options(&parts, &options)?;
+    let url = Url::create_object_url_with_blob(&blob)?;
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or_else(|| JsValue::from_str("no document to download from"))?;
+    let anchor: HtmlAnchorElement = document.create_element("a")?.dyn_into()?;
+    anchor.set_href(&url);
+    anchor.set_download(filename);
+    anchor.click();
+    Url::revoke_object_url(&url)
+}
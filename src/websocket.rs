@@ -0,0 +1,115 @@
+use std::io;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tungstenite::{Message, WebSocket};
+
+use crate::{TraceEvent, TraceSink};
+
+#[derive(Default)]
+struct Clients {
+    sockets: Vec<WebSocket<TcpStream>>,
+    /// Process and thread names seen so far, replayed to clients that connect
+    /// late so their view is labeled.
+    metadata: Vec<String>,
+}
+
+/// How long the accept thread waits between checks for new clients and
+/// for the sink finishing.
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
+
+/// Pushes each event, as a JSON text message, to every WebSocket client
+/// connected to it, so a browser can watch the trace live.
+///
+/// A client that can't keep up for more than a second, or takes longer
+/// than that to finish the handshake, is disconnected rather than stalling
+/// the writer thread. Finishing the sink releases the port, once any
+/// handshake under way is done or has timed out.
+pub struct WebSocketSink {
+    clients: Arc<Mutex<Clients>>,
+    finished: Arc<AtomicBool>,
+    accepting: Option<JoinHandle<()>>,
+}
+
+impl WebSocketSink {
+    /// Listens on `addr` (e.g. `"127.0.0.1:9001"`), accepting clients in the
+    /// background.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        // Polled, so the thread notices the sink finishing.
+        listener.set_nonblocking(true)?;
+        let clients = Arc::new(Mutex::new(Clients::default()));
+        let finished = Arc::new(AtomicBool::new(false));
+        let accepting = {
+            let (clients, finished) = (Arc::downgrade(&clients), finished.clone());
+            thread::spawn(move || loop {
+                if finished.load(Ordering::Relaxed) {
+                    return;
+                }
+                let clients = match clients.upgrade() {
+                    Some(clients) => clients,
+                    None => return,
+                };
+                let stream = match listener.accept() {
+                    Ok((stream, _)) => stream,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(ACCEPT_POLL);
+                        continue;
+                    }
+                    Err(_) => continue,
+                };
+                // Some platforms hand the listener's non-blocking mode on.
+                drop(stream.set_nonblocking(false));
+                drop(stream.set_read_timeout(Some(Duration::from_secs(1))));
+                drop(stream.set_write_timeout(Some(Duration::from_secs(1))));
+                let mut socket = match tungstenite::accept(stream) {
+                    Ok(socket) => socket,
+                    Err(_) => continue,
+                };
+                let mut clients = clients.lock().unwrap();
+                let replayed = clients
+                    .metadata
+                    .iter()
+                    .all(|json| socket.send(Message::Text(json.clone())).is_ok());
+                if replayed {
+                    clients.sockets.push(socket);
+                }
+            })
+        };
+        Ok(WebSocketSink {
+            clients,
+            finished,
+            accepting: Some(accepting),
+        })
+    }
+}
+
+impl TraceSink for WebSocketSink {
+    fn write_event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        let json = event.to_json().to_string();
+        let mut clients = self.clients.lock().unwrap();
+        // Drop clients that have gone away or fallen behind.
+        clients
+            .sockets
+            .retain_mut(|socket| socket.send(Message::Text(json.clone())).is_ok());
+        if event.ph == "M" {
+            clients.metadata.push(json);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.finished.store(true, Ordering::Relaxed);
+        if let Some(accepting) = self.accepting.take() {
+            drop(accepting.join());
+        }
+        let mut clients = self.clients.lock().unwrap();
+        for mut socket in clients.sockets.drain(..) {
+            drop(socket.close(None));
+            drop(socket.flush());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,619 @@
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+#[cfg(target_arch = "wasm32")]
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::anonymize::Anonymizer;
+use crate::args::{self, Shape};
+#[cfg(target_arch = "wasm32")]
+use crate::channel::SendError;
+use crate::clock::Instant;
+use crate::coalesce::Coalesce;
+use crate::dedup::Dedup;
+use crate::fallback::Fallback;
+use crate::gaps::GapElider;
+use crate::heatmap::Heatmap;
+use crate::json::Value;
+use crate::overhead::WriteTime;
+use crate::quota::Quotas;
+use crate::sizes::EventSizes;
+use crate::stats::Stats;
+use crate::threads::Threads;
+use crate::{in_micros, Message, TraceEvent, TraceSink, SUMMARY_EVENT};
+
+/// A user-supplied rewrite applied to every event before it reaches the
+/// sinks; returning `None` drops the event.
+pub(crate) type Transform = Box<dyn FnMut(TraceEvent) -> Option<TraceEvent> + Send>;
+
+/// A user-supplied rewrite of every timestamp, see
+/// `ChromeTraceBuilder::ts_map`.
+pub(crate) type TsMap = Box<dyn FnMut(u64) -> u64 + Send>;
+
+/// Which sinks an event is written to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Route {
+    /// The sinks added with `ChromeTraceBuilder::sink`.
+    Default,
+    /// Every sink, e.g. for process and thread names.
+    All,
+    /// The sink for the `n`th `ChromeTraceBuilder::route`.
+    Sink(usize),
+}
+
+pub(crate) struct Outputs {
+    sinks: Vec<Box<dyn TraceSink>>,
+    /// The first `defaults` sinks receive unrouted events; the rest are
+    /// route targets, in route order.
+    defaults: usize,
+    /// Extra sinks for captured span subtrees, by captured span id.
+    captures: HashMap<u64, Box<dyn TraceSink>>,
+    transforms: Vec<Transform>,
+    pub anonymizer: Option<Anonymizer>,
+    pub arg_shape: Option<Shape>,
+    pub sizes: Option<EventSizes>,
+    /// Set by `ChromeTraceBuilder::measure_overhead`.
+    pub write_time: Option<WriteTime>,
+    /// See `ChromeTraceBuilder::quota`.
+    pub quotas: Option<Quotas>,
+    /// See `ChromeTraceBuilder::elide_idle_gaps`.
+    pub gaps: Option<GapElider>,
+    /// See `ChromeTraceBuilder::summarize_short_spans`.
+    pub coalesce: Option<Coalesce>,
+    /// See `ChromeTraceBuilder::dedup_window`.
+    pub dedup: Option<Dedup>,
+    /// See `ChromeTraceBuilder::backfill_thread_metadata`.
+    pub threads: Option<Threads>,
+    /// See `ChromeTraceBuilder::activity_track`.
+    pub heatmap: Option<Heatmap>,
+    /// Added to every timestamp, see `ChromeTraceBuilder::time_base` and
+    /// `ChromeTraceBuilder::ts_offset`.
+    pub ts_offset: i64,
+    pub ts_map: Option<TsMap>,
+    /// When the subscriber started, for events the writer thread makes up.
+    pub start: Instant,
+    /// Written before anything from the channel, see
+    /// `ChromeTraceSubscriber::startup_records`.
+    pub startup: Vec<(TraceEvent, Route)>,
+    /// Metadata written so far, to start replacement outputs with.
+    metadata: Vec<TraceEvent>,
+    /// Events held while there's no default sink yet, see
+    /// `ChromeTraceBuilder::buffer_until_attached`.
+    pub backlog: Option<Backlog>,
+}
+
+pub(crate) struct Backlog {
+    events: VecDeque<TraceEvent>,
+    capacity: usize,
+    dropped: usize,
+}
+
+impl Backlog {
+    pub fn new(capacity: usize) -> Self {
+        Backlog {
+            events: VecDeque::new(),
+            capacity,
+            dropped: 0,
+        }
+    }
+
+    fn push(&mut self, event: TraceEvent, stats: &Stats) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+            if self.dropped == 0 {
+                stats.log(|| "no output attached yet; dropping the oldest buffered events".to_owned());
+            }
+            self.dropped += 1;
+        }
+        self.events.push_back(event);
+    }
+}
+
+impl Outputs {
+    pub fn new(
+        mut defaults: Vec<Box<dyn TraceSink>>,
+        routed: Vec<Box<dyn TraceSink>>,
+        transforms: Vec<Transform>,
+    ) -> Self {
+        let count = defaults.len();
+        defaults.extend(routed);
+        let sinks = defaults
+            .into_iter()
+            .map(|sink| Box::new(Fallback::new(sink)) as Box<dyn TraceSink>)
+            .collect();
+        Outputs {
+            sinks,
+            defaults: count,
+            captures: HashMap::new(),
+            transforms,
+            anonymizer: None,
+            arg_shape: None,
+            sizes: None,
+            write_time: None,
+            quotas: None,
+            gaps: None,
+            coalesce: None,
+            dedup: None,
+            threads: None,
+            heatmap: None,
+            ts_offset: 0,
+            ts_map: None,
+            start: Instant::now(),
+            startup: Vec::new(),
+            metadata: Vec::new(),
+            backlog: None,
+        }
+    }
+
+    /// Finishes the default sinks, after everything held back and `last`,
+    /// and puts `sink` in their place.
+    fn set_output(&mut self, sink: Box<dyn TraceSink>, last: Option<TraceEvent>, stats: &Stats) {
+        self.drain_held(stats);
+        // Written as is: `Coalesce` and `Dedup` could hold it past the switch.
+        if let Some(last) = last {
+            self.write_routed(last, Route::Default, stats);
+        }
+        let mut sink: Box<dyn TraceSink> = Box::new(Fallback::new(sink));
+        for mut old in self.sinks.drain(..self.defaults) {
+            stats.timed("finish", || old.finish());
+        }
+        stats.check("start", sink.start());
+        for event in &self.metadata {
+            stats.check("write event", sink.write_event(event));
+        }
+        if let Some(backlog) = self.backlog.take() {
+            for event in backlog.events {
+                stats.check("write event", sink.write_event(&event));
+            }
+        }
+        self.sinks.insert(0, sink);
+        self.defaults = 1;
+    }
+
+    fn transform(&mut self, mut event: TraceEvent) -> Option<TraceEvent> {
+        if let Some(ref gaps) = self.gaps {
+            gaps.apply(&mut event);
+        }
+        if event.ph != "M" {
+            event.ts = shift(event.ts, self.ts_offset);
+            if let Some(ref mut map) = self.ts_map {
+                let start = map(event.ts);
+                if let Some(dur) = event.dur {
+                    event.dur = Some(map(event.ts + dur).saturating_sub(start));
+                }
+                event.ts = start;
+            }
+        }
+        let event = self
+            .transforms
+            .iter_mut()
+            .try_fold(event, |event, transform| transform(event))?;
+        let mut event = match self.anonymizer {
+            Some(ref mut anonymizer) => anonymizer.event(event),
+            None => event,
+        };
+        // Metadata and object snapshots have args of a fixed shape.
+        if let Some(ref shape) = self.arg_shape {
+            if event.ph != "M" && event.ph != "O" {
+                let args = mem::take(&mut event.args);
+                event.args = shape.apply(args);
+                if shape.units && event.ph == "C" {
+                    args::label_counter(event.name.to_mut(), &event.args);
+                }
+            }
+        }
+        Some(event)
+    }
+
+    /// Writes `event`, made up by the writer thread, to every sink.
+    fn write_all(&mut self, event: TraceEvent, stats: &Stats) {
+        if let Some(event) = self.transform(event) {
+            for sink in self.route(Route::All) {
+                stats.check("write event", sink.write_event(&event));
+            }
+        }
+    }
+
+    /// Transforms `event` and writes it to the sinks for `route`, unless a
+    /// quota turns it away.
+    fn write_routed(&mut self, event: TraceEvent, route: Route, stats: &Stats) {
+        if let Some(marker) = self.gaps.as_mut().and_then(|gaps| gaps.observe(&event)) {
+            self.write_all(marker, stats);
+        }
+        let event = self.transform(event).filter(|event| match self.quotas {
+            Some(ref mut quotas) => event.name == SUMMARY_EVENT || quotas.admit(event),
+            None => true,
+        });
+        let mut event = match event {
+            Some(event) => event,
+            None => return,
+        };
+        let mut warning = None;
+        let summary = event.name == SUMMARY_EVENT && event.ph == "I";
+        if let Some(ref quotas) = self.quotas {
+            if summary {
+                event.args.insert("quota_dropped".to_owned(), quotas.to_arg());
+            }
+        }
+        if let Some(ref coalesce) = self.coalesce {
+            if summary {
+                event.args.insert("summarized_spans".to_owned(), Value::from(coalesce.folded()));
+            }
+        }
+        if let Some(ref dedup) = self.dedup {
+            if summary {
+                event.args.insert("deduplicated".to_owned(), Value::from(dedup.folded()));
+            }
+        }
+        if let Some(ref mut sizes) = self.sizes {
+            if summary {
+                event.args.insert("event_sizes".to_owned(), sizes.to_arg());
+            } else {
+                warning = sizes.measure(&event);
+            }
+        }
+        if let Some(ref write_time) = self.write_time {
+            if summary {
+                event.args.insert("write_ns".to_owned(), Value::from(write_time.per_event_ns()));
+            }
+        }
+        let started = Instant::now();
+        for sink in self.route(route) {
+            stats.check("write event", sink.write_event(&event));
+            if let Some(ref warning) = warning {
+                stats.check("write event", sink.write_event(warning));
+            }
+        }
+        if let Some(ref mut write_time) = self.write_time {
+            write_time.add(started.elapsed());
+        }
+        if let Some(ref mut threads) = self.threads {
+            threads.observe(&event);
+        }
+        let samples = match self.heatmap {
+            Some(ref mut heatmap) => heatmap.observe(&event),
+            None => Vec::new(),
+        };
+        for sample in samples {
+            for sink in self.route(Route::All) {
+                stats.check("write event", sink.write_event(&sample));
+            }
+        }
+        if event.ph == "M" {
+            self.metadata.push(event);
+        } else if let Route::Default = route {
+            if self.defaults == 0 {
+                if let Some(ref mut backlog) = self.backlog {
+                    backlog.push(event, stats);
+                }
+            }
+        }
+    }
+
+    /// Writes `event` by way of `Coalesce` and `Dedup`, when they're on.
+    fn write_event(&mut self, event: TraceEvent, route: Route, stats: &Stats) {
+        match self.coalesce {
+            Some(ref mut coalesce) => {
+                for (event, route) in coalesce.push(event, route) {
+                    self.write_deduped(event, route, stats);
+                }
+            }
+            None => self.write_deduped(event, route, stats),
+        }
+    }
+
+    fn write_deduped(&mut self, event: TraceEvent, route: Route, stats: &Stats) {
+        match self.dedup {
+            Some(ref mut dedup) => {
+                let ready = dedup.push(event, route);
+                self.write_held(ready, stats);
+            }
+            None => self.write_routed(event, route, stats),
+        }
+    }
+
+    /// Writes the events `Dedup` was holding on to.
+    fn write_held(&mut self, held: Vec<(TraceEvent, Route)>, stats: &Stats) {
+        for (event, route) in held {
+            self.write_routed(event, route, stats);
+        }
+    }
+
+    /// Writes everything `Coalesce` and `Dedup` are holding on to.
+    fn drain_held(&mut self, stats: &Stats) {
+        if let Some(runs) = self.coalesce.as_mut().map(Coalesce::drain) {
+            for (event, route) in runs {
+                self.write_deduped(event, route, stats);
+            }
+        }
+        if let Some(held) = self.dedup.as_mut().map(Dedup::drain) {
+            self.write_held(held, stats);
+        }
+    }
+
+    /// Writes what `Coalesce` and `Dedup` are done holding on to by `now`.
+    fn release_held(&mut self, now: u64, stats: &Stats) {
+        if let Some(runs) = self.coalesce.as_mut().map(|coalesce| coalesce.release(now)) {
+            for (event, route) in runs {
+                self.write_deduped(event, route, stats);
+            }
+        }
+        if let Some(held) = self.dedup.as_mut().map(|dedup| dedup.release(now)) {
+            self.write_held(held, stats);
+        }
+    }
+
+    pub fn wants_spans(&self) -> bool {
+        self.sinks.iter().any(|sink| sink.wants_spans())
+    }
+
+    fn route(&mut self, route: Route) -> &mut [Box<dyn TraceSink>] {
+        match route {
+            Route::Default => &mut self.sinks[..self.defaults],
+            Route::All => &mut self.sinks[..],
+            Route::Sink(n) => {
+                let index = self.defaults + n;
+                &mut self.sinks[index..index + 1]
+            }
+        }
+    }
+}
+
+/// `ts` moved by `offset`, clamped at zero.
+pub(crate) fn shift(ts: u64, offset: i64) -> u64 {
+    if offset < 0 {
+        ts.saturating_sub(offset.unsigned_abs())
+    } else {
+        ts.saturating_add(offset as u64)
+    }
+}
+
+/// What the writer keeps between messages.
+pub(crate) struct Writer {
+    outputs: Outputs,
+    checkpoint_every: Option<Duration>,
+    next_checkpoint: Option<Instant>,
+    /// Whether anything was written since the last checkpoint.
+    dirty: bool,
+    /// Events reported lost so far, for the next `events lost` record.
+    lost: usize,
+    stats: Arc<Stats>,
+}
+
+impl Writer {
+    pub fn new(outputs: Outputs, checkpoint_every: Option<Duration>, stats: Arc<Stats>) -> Self {
+        Writer {
+            outputs,
+            checkpoint_every,
+            next_checkpoint: None,
+            dirty: false,
+            lost: 0,
+            stats,
+        }
+    }
+
+    /// Starts the sinks and writes the startup records.
+    pub fn start(&mut self) {
+        let stats = &self.stats;
+        for sink in self.outputs.route(Route::All) {
+            stats.check("start", sink.start());
+        }
+        let startup = mem::take(&mut self.outputs.startup);
+        self.dirty = !startup.is_empty();
+        for (event, route) in startup {
+            self.outputs.write_event(event, route, stats);
+        }
+        self.next_checkpoint = self.checkpoint_every.map(|every| Instant::now() + every);
+    }
+
+    /// Checkpoints the sinks if one is due, and returns how long until the
+    /// next one.
+    pub fn checkpoint(&mut self) -> Option<Duration> {
+        let (at, every) = (self.next_checkpoint?, self.checkpoint_every?);
+        let now = Instant::now();
+        if now < at {
+            return Some(at - now);
+        }
+        if self.dirty {
+            for sink in self.outputs.route(Route::All) {
+                self.stats.timed("checkpoint", || sink.checkpoint());
+            }
+            self.dirty = false;
+        }
+        self.next_checkpoint = Some(now + every);
+        Some(every)
+    }
+
+    /// Writes out `msg`, returning false once the trace is done.
+    pub fn handle(&mut self, msg: Message) -> bool {
+        let (outputs, stats) = (&mut self.outputs, &*self.stats);
+        stats.received(&msg);
+        match msg {
+            Message::Done => {
+                outputs.drain_held(stats);
+                if let Some(sample) = outputs.heatmap.as_mut().and_then(Heatmap::finish) {
+                    for sink in outputs.route(Route::All) {
+                        stats.check("write event", sink.write_event(&sample));
+                    }
+                }
+                if let Some(threads) = outputs.threads.take() {
+                    for record in threads.backfill() {
+                        for sink in outputs.route(Route::All) {
+                            stats.check("write event", sink.write_event(&record));
+                        }
+                    }
+                }
+                for sink in outputs.route(Route::All) {
+                    stats.timed("finish", || sink.finish());
+                }
+                for (_, mut sink) in outputs.captures.drain() {
+                    stats.timed("finish", || sink.finish());
+                }
+                return false;
+            }
+            Message::Event(event, route) => {
+                outputs.write_event(event, route, stats);
+                self.dirty = true;
+            }
+            Message::StartCapture(id, mut sink) => {
+                stats.check("start", sink.start());
+                outputs.captures.insert(id, sink);
+            }
+            Message::Captured(event, id) => {
+                if let Some(event) = outputs.transform(event) {
+                    if let Some(sink) = outputs.captures.get_mut(&id) {
+                        stats.check("write event", sink.write_event(&event));
+                    }
+                }
+            }
+            Message::EndCapture(id) => {
+                if let Some(mut sink) = outputs.captures.remove(&id) {
+                    stats.timed("finish", || sink.finish());
+                }
+            }
+            Message::SpanClosed(span) => {
+                let span = match outputs.anonymizer {
+                    Some(ref anonymizer) => anonymizer.span(span),
+                    None => span,
+                };
+                for sink in outputs.route(Route::All) {
+                    stats.check("span closed", sink.span_closed(&span));
+                }
+            }
+            Message::Flush(ack) => {
+                outputs.drain_held(stats);
+                for sink in outputs.route(Route::All) {
+                    stats.timed("flush", || sink.flush());
+                }
+                for sink in outputs.captures.values_mut() {
+                    stats.timed("flush", || sink.flush());
+                }
+                let _ = ack.send(());
+                return true;
+            }
+            Message::SetOutput(sink, last) => {
+                outputs.set_output(sink, last, stats);
+                self.dirty = true;
+            }
+            Message::SystemTrace(text) => {
+                for sink in outputs.route(Route::All) {
+                    stats.check("system trace", sink.system_trace(&text));
+                }
+                self.dirty = true;
+            }
+        }
+        // Flush whenever the queue runs dry: busy periods are still written
+        // in batches, but nothing sits in a buffer once things go quiet.
+        if stats.queued() == 0 {
+            // Say so once things are quiet, when events were dropped for
+            // lack of room in the channel.
+            let overflowed = stats.overflowed();
+            if overflowed > self.lost {
+                let ts = in_micros(outputs.start.elapsed());
+                let mut event = TraceEvent::new("I", "events lost".to_owned(), "chrome_trace".to_owned(), ts);
+                event.scope = Some("g");
+                event.args.insert("count".to_owned(), Value::from(overflowed - self.lost));
+                event.args.insert("total".to_owned(), Value::from(overflowed));
+                self.lost = overflowed;
+                outputs.write_all(event, stats);
+            }
+            let now = in_micros(outputs.start.elapsed());
+            outputs.release_held(now, stats);
+            for sink in outputs.route(Route::All) {
+                stats.timed("flush", || sink.flush());
+            }
+            for sink in outputs.captures.values_mut() {
+                stats.timed("flush", || sink.flush());
+            }
+        }
+        true
+    }
+}
+
+/// The writer thread: handles messages from `rx` until the trace is done.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn run(rx: Receiver<Message>, mut writer: Writer) {
+    writer.start();
+    loop {
+        let msg = match writer.checkpoint() {
+            Some(wait) => match rx.recv_timeout(wait) {
+                Ok(msg) => msg,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match rx.recv() {
+                Ok(msg) => msg,
+                Err(_) => break,
+            },
+        };
+        if !writer.handle(msg) {
+            break;
+        }
+    }
+}
+
+/// Stands in for the writer thread where there are no threads: messages
+/// are written out on the thread sending them. Those sent before `start`,
+/// while a lazy subscriber waits, are kept until then.
+#[cfg(target_arch = "wasm32")]
+#[derive(Default)]
+pub(crate) struct Inline {
+    state: Mutex<InlineState>,
+}
+
+#[cfg(target_arch = "wasm32")]
+enum InlineState {
+    Waiting(Vec<Message>),
+    Running(Box<Writer>),
+    Done,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Default for InlineState {
+    fn default() -> Self {
+        InlineState::Waiting(Vec::new())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Inline {
+    pub fn start(&self, mut writer: Writer) {
+        let mut state = self.state.lock().unwrap();
+        writer.start();
+        let mut done = false;
+        if let InlineState::Waiting(ref mut waiting) = *state {
+            for msg in waiting.drain(..) {
+                done = done || !writer.handle(msg);
+            }
+        }
+        *state = if done { InlineState::Done } else { InlineState::Running(Box::new(writer)) };
+    }
+
+    pub fn send(&self, msg: Message) -> Result<(), SendError> {
+        // Busy if a sink records something while writing, which a thread
+        // would have queued; it's dropped and counted.
+        let mut state = self.state.try_lock().map_err(|_| SendError::Full)?;
+        let done = match *state {
+            InlineState::Waiting(ref mut waiting) => {
+                waiting.push(msg);
+                false
+            }
+            InlineState::Running(ref mut writer) => {
+                let done = !writer.handle(msg);
+                writer.checkpoint();
+                done
+            }
+            InlineState::Done => return Err(SendError::Closed),
+        };
+        if done {
+            *state = InlineState::Done;
+        }
+        Ok(())
+    }
+}
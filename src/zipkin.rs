@@ -0,0 +1,89 @@
+use std::io::{self, Write};
+
+use crate::json::{self, Map, Value};
+use crate::{ClosedSpan, TraceEvent, TraceSink};
+
+/// Writes closed spans as a Zipkin v2 JSON array, which can be posted as-is
+/// to a collector's `/api/v2/spans` (or Jaeger's Zipkin endpoint). Events
+/// are ignored.
+pub struct ZipkinSink<W> {
+    writer: W,
+    service: String,
+    first: bool,
+}
+
+impl<W: Write> ZipkinSink<W> {
+    /// `service` is the service name the spans are reported under.
+    pub fn new(writer: W, service: &str) -> Self {
+        ZipkinSink {
+            writer,
+            service: service.to_owned(),
+            first: true,
+        }
+    }
+}
+
+impl<W: Write + Send> TraceSink for ZipkinSink<W> {
+    fn start(&mut self) -> io::Result<()> {
+        self.writer.write_all(b"[\n")
+    }
+
+    fn write_event(&mut self, _event: &TraceEvent) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn wants_spans(&self) -> bool {
+        true
+    }
+
+    fn span_closed(&mut self, span: &ClosedSpan) -> io::Result<()> {
+        // The subscriber's start time keeps trace ids from different runs
+        // apart.
+        let run = span.timestamp - span.start;
+        let mut obj = Map::new();
+        obj.insert("traceId".to_owned(), Value::from(format!("{:016x}{}", run, span_id(span.root))));
+        obj.insert("id".to_owned(), Value::from(span_id(span.id)));
+        if let Some(parent) = span.parent {
+            obj.insert("parentId".to_owned(), Value::from(span_id(parent)));
+        }
+        obj.insert("name".to_owned(), Value::from(span.name));
+        obj.insert("timestamp".to_owned(), Value::from(span.timestamp));
+        // Zipkin treats a zero duration as unknown.
+        obj.insert("duration".to_owned(), Value::from(span.duration.max(1)));
+        let mut endpoint = Map::new();
+        endpoint.insert("serviceName".to_owned(), Value::from(self.service.as_str()));
+        obj.insert("localEndpoint".to_owned(), Value::Object(endpoint));
+        // Tags can only be strings.
+        let mut tags = Map::new();
+        tags.insert("target".to_owned(), Value::from(span.target));
+        for (key, value) in span.fields.iter() {
+            let value = match *value {
+                Value::String(ref s) => s.clone(),
+                ref other => other.to_string(),
+            };
+            tags.insert(key.clone(), Value::from(value));
+        }
+        obj.insert("tags".to_owned(), Value::Object(tags));
+        if !self.first {
+            self.writer.write_all(b",\n")?;
+        }
+        self.first = false;
+        self.writer.write_all(&json::to_vec(&Value::Object(obj)))?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.write_all(b"\n]\n")?;
+        self.writer.flush()
+    }
+}
+
+/// Zipkin ids are 16 hex digits and may not be all zeros, which the first
+/// span id is.
+fn span_id(id: u64) -> String {
+    format!("{:016x}", id + 1)
+}